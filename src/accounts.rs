@@ -0,0 +1,276 @@
+//! Stored OAuth identities, selected with `--account`. Their tokens are kept in the OS keyring
+//! where there is one, unless `--no-keyring` is given.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use imgurs::api::OAuthTokens;
+use imgurs::date::unix_now;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// Empty if the tokens are stored in the keyring and have not been read yet.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Whether the tokens are stored in the OS keyring instead of the accounts file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub in_keyring: bool,
+    /// Unix timestamp at which the access token expires, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+impl Account {
+    pub fn from_tokens(tokens: OAuthTokens) -> Account {
+        Account {
+            access_token: tokens.access_token,
+            refresh_token: Some(tokens.refresh_token),
+            expires_at: Some(unix_now() + tokens.expires_in),
+            username: tokens.account_username,
+            in_keyring: false,
+        }
+    }
+
+    /// Whether the access token has expired, or will within a minute.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at < unix_now() + 60)
+    }
+}
+
+/// Tokens of an account, as stored in the keyring.
+#[derive(Serialize, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+/// An account written by `account export`, with the client ID and secret which its tokens are
+/// refreshed with, for importing on another machine.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub name: String,
+    pub account: Account,
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// Environment variable which account bundles' passphrase is read from.
+const BUNDLE_PASSPHRASE_VAR: &str = "IMGURS_BUNDLE_PASSPHRASE";
+
+impl Bundle {
+    /// Writes the bundle to `path`, encrypted like the upload vault.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let passphrase = crate::vault::read_passphrase(BUNDLE_PASSPHRASE_VAR, "account bundle")?;
+        let contents = crate::vault::encrypt(
+            &passphrase,
+            &serde_json::to_vec(self)?,
+            crate::vault::KDF_ITERATIONS,
+        )?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(&contents))
+            .with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Bundle> {
+        let contents =
+            std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        let passphrase = crate::vault::read_passphrase(BUNDLE_PASSPHRASE_VAR, "account bundle")?;
+        let contents = crate::vault::decrypt(&passphrase, &contents)
+            .with_context(|| format!("Unable to decrypt {}", path.display()))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("Unable to parse account bundle {}", path.display()))
+    }
+}
+
+fn keyring_key(name: &str) -> String {
+    format!("account:{}", name)
+}
+
+/// Accounts keyed by name, stored as a JSON file readable only by the current user.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Accounts {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Whether new tokens are stored in the keyring.
+    #[serde(skip)]
+    keyring: bool,
+    /// Account used when none is selected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub accounts: BTreeMap<String, Account>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    crate::paths::data_dir().map(|dir| dir.join("accounts.json"))
+}
+
+impl Accounts {
+    /// Loads the accounts at `path`, or none if the file does not exist yet. With `keyring`, new
+    /// tokens are saved to the OS keyring.
+    pub fn load(path: &Path, keyring: bool) -> Result<Accounts> {
+        let mut accounts: Accounts = match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Unable to parse accounts file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Accounts::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read accounts file {}", path.display()))
+            }
+        };
+        accounts.path = path.to_owned();
+        accounts.keyring = keyring;
+        Ok(accounts)
+    }
+
+    /// Saves the accounts, moving the tokens of new accounts into the keyring. If that fails,
+    /// they are saved in the file instead.
+    pub fn save(&self) -> Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(anyhow!("Unable to determine accounts file location"));
+        }
+        let mut stored = self.clone();
+        if self.keyring {
+            for (name, account) in &mut stored.accounts {
+                if account.access_token.is_empty() {
+                    continue;
+                }
+                let tokens = serde_json::to_string(&Tokens {
+                    access_token: account.access_token.clone(),
+                    refresh_token: account.refresh_token.clone(),
+                })?;
+                match crate::keyring::set(&keyring_key(name), &tokens) {
+                    Ok(()) => {
+                        account.access_token.clear();
+                        account.refresh_token = None;
+                        account.in_keyring = true;
+                    }
+                    Err(e) => println!(
+                        "Unable to use the keyring, storing account {} in {}: {:#}",
+                        name,
+                        self.path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = self.path.with_extension("json.tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&temp_path)?
+            .write_all(&serde_json::to_vec_pretty(&stored)?)?;
+        std::fs::rename(&temp_path, &self.path).with_context(|| "Unable to save accounts file")?;
+        Ok(())
+    }
+
+    /// Returns the account called `name`, or the default account.
+    pub fn get(&self, name: Option<&str>) -> Result<Option<&Account>> {
+        match name.or(self.default.as_deref()) {
+            Some(name) => self
+                .accounts
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| anyhow!("Unknown account: {}", name)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the name of the account called `name`, or the default account, along with its
+    /// tokens, read from the keyring if they are stored there.
+    pub fn credentials(&self, name: Option<&str>) -> Result<Option<(String, Account)>> {
+        let Some(name) = name.or(self.default.as_deref()) else {
+            return Ok(None);
+        };
+        let Some(mut account) = self.get(Some(name))?.cloned() else {
+            return Ok(None);
+        };
+        if account.in_keyring && account.access_token.is_empty() {
+            if !self.keyring {
+                return Err(anyhow!(
+                    "Account {} is stored in the keyring, which --no-keyring disables",
+                    name
+                ));
+            }
+            let tokens = crate::keyring::get(&keyring_key(name))
+                .with_context(|| "Unable to read the keyring")?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The tokens of account {} are missing from the keyring",
+                        name
+                    )
+                })?;
+            let tokens: Tokens = serde_json::from_str(&tokens)
+                .with_context(|| format!("Unable to parse the tokens of account {}", name))?;
+            account.access_token = tokens.access_token;
+            account.refresh_token = tokens.refresh_token;
+        }
+        Ok(Some((name.to_owned(), account)))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let account = self
+            .accounts
+            .remove(name)
+            .ok_or_else(|| anyhow!("Unknown account: {}", name))?;
+        if account.in_keyring && self.keyring {
+            crate::keyring::delete(&keyring_key(name))?;
+        }
+        if self.default.as_deref() == Some(name) {
+            self.default = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_round_trip() {
+        std::env::set_var(BUNDLE_PASSPHRASE_VAR, "passphrase");
+        let path = std::env::temp_dir().join(format!("imgurs-bundle-{}", std::process::id()));
+        let bundle = Bundle {
+            name: "laptop".to_owned(),
+            account: Account {
+                access_token: "access".to_owned(),
+                refresh_token: Some("refresh".to_owned()),
+                in_keyring: false,
+                expires_at: None,
+                username: None,
+            },
+            client_id: "client".to_owned(),
+            client_secret: Some("secret".to_owned()),
+        };
+        bundle.write(&path).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        assert!(!contents.windows(6).any(|window| window == b"secret"));
+
+        let read = Bundle::read(&path).unwrap();
+        assert_eq!(read.name, "laptop");
+        assert_eq!(read.account.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(read.client_secret.as_deref(), Some("secret"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
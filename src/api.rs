@@ -0,0 +1,1459 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::future::BoxFuture;
+#[cfg(target_arch = "wasm32")]
+use futures_util::future::LocalBoxFuture as BoxFuture;
+use futures_util::future::{FutureExt, Shared};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::RateLimited;
+
+#[derive(Debug, Deserialize)]
+pub struct ImgurResponse<T> {
+    pub data: Option<T>,
+    pub status: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImgurAlbum {
+    pub id: String,
+    pub title: Option<String>,
+    /// The text of a post, separate from its images' descriptions.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Username of the uploader, if not anonymous.
+    #[serde(default)]
+    pub account_url: Option<String>,
+    /// Unix timestamp at which the album was created.
+    #[serde(default)]
+    pub datetime: i64,
+    /// Only included for gallery posts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<ImgurTag>,
+    /// Not included when listing albums.
+    #[serde(default)]
+    pub images: Vec<ImgurMedia>,
+}
+
+/// A tag of a gallery post.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImgurTag {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImgurMedia {
+    pub id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub link: String,
+    pub datetime: i64,
+    pub size: u64,
+    #[serde(rename = "type")]
+    pub content_type: String,
+    /// Dimensions in pixels, or 0 if unknown.
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    /// Whether the media is a GIF or video.
+    #[serde(default)]
+    pub animated: bool,
+}
+
+/// An entry of a gallery listing, such as search results or a tag gallery, which is a post of
+/// either an album or a single image.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImgurGalleryItem {
+    pub id: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub account_url: Option<String>,
+    #[serde(default)]
+    pub datetime: i64,
+    #[serde(default)]
+    pub is_album: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<ImgurTag>,
+    /// Only the album's cover, or a few of its images.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImgurMedia>,
+    /// Fields of single images, missing for albums.
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub link: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, rename = "type")]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    #[serde(default)]
+    pub animated: bool,
+    /// Whether the post is marked NSFW, which is not known for some posts.
+    #[serde(default)]
+    pub nsfw: Option<bool>,
+}
+
+/// The authenticated user's notifications, see <https://apidocs.imgur.com/#notification>.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImgurNotifications {
+    /// Replies to the user's comments and mentions of the user.
+    #[serde(default)]
+    pub replies: Vec<ImgurNotification>,
+    #[serde(default)]
+    pub messages: Vec<ImgurNotification>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImgurNotification {
+    pub id: u64,
+    #[serde(default)]
+    pub viewed: bool,
+    /// A comment for replies, or a conversation for messages.
+    pub content: ImgurNotificationContent,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImgurNotificationContent {
+    /// Author of a comment.
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Sender of a message.
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub last_message: Option<String>,
+    /// ID of the post commented on.
+    #[serde(default)]
+    pub image_id: Option<String>,
+    #[serde(default)]
+    pub datetime: i64,
+    /// Any other fields, kept so that archived notifications are complete.
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A comment on a gallery post.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImgurComment {
+    pub id: u64,
+    /// ID of the post commented on.
+    pub image_id: String,
+    pub comment: String,
+    pub author: String,
+    #[serde(default)]
+    pub datetime: i64,
+    #[serde(default)]
+    pub points: i64,
+    /// ID of the comment replied to, or 0.
+    #[serde(default)]
+    pub parent_id: u64,
+    /// Any other fields, kept so that archived comments are complete.
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Remaining API credits, see <https://apidocs.imgur.com/#credits>.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImgurCredits {
+    pub user_limit: u64,
+    pub user_remaining: u64,
+    /// Unix timestamp at which user credits are reset.
+    pub user_reset: i64,
+    pub client_limit: u64,
+    pub client_remaining: u64,
+}
+
+/// API rate limits, as reported by the headers of the latest response. See
+/// <https://apidocs.imgur.com/#rate-limits>.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub client_limit: Option<u64>,
+    pub client_remaining: Option<u64>,
+    pub user_limit: Option<u64>,
+    pub user_remaining: Option<u64>,
+    /// Unix timestamp at which user credits are reset.
+    pub user_reset: Option<i64>,
+    /// Remaining `POST` requests, such as uploads, for this IP address.
+    pub post_remaining: Option<u64>,
+    /// Seconds until `POST` requests are reset.
+    pub post_reset: Option<u64>,
+}
+
+impl RateLimits {
+    /// Updates the limits given in `headers`, keeping the others.
+    fn update(&mut self, headers: &reqwest::header::HeaderMap) {
+        fn header<T: std::str::FromStr>(
+            headers: &reqwest::header::HeaderMap,
+            name: &str,
+        ) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+        let fields = [
+            (&mut self.client_limit, "x-ratelimit-clientlimit"),
+            (&mut self.client_remaining, "x-ratelimit-clientremaining"),
+            (&mut self.user_limit, "x-ratelimit-userlimit"),
+            (&mut self.user_remaining, "x-ratelimit-userremaining"),
+            (&mut self.post_remaining, "x-post-rate-limit-remaining"),
+            (&mut self.post_reset, "x-post-rate-limit-reset"),
+        ];
+        for (field, name) in fields {
+            if let Some(value) = header(headers, name) {
+                *field = Some(value);
+            }
+        }
+        if let Some(reset) = header(headers, "x-ratelimit-userreset") {
+            self.user_reset = Some(reset);
+        }
+    }
+}
+
+/// Base URL of the Imgur API.
+pub const DEFAULT_BASE_URL: &str = "https://api.imgur.com";
+
+/// HTTP client for the Imgur API.
+///
+/// Identical `GET` requests made concurrently, e.g. for the same album, share a single request
+/// and its response. Clones share in-flight requests too.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    client: Client,
+    base_url: String,
+    in_flight: Arc<InFlight>,
+    rate_limits: Arc<Mutex<RateLimits>>,
+}
+
+type SharedBody = Result<Arc<Vec<u8>>, Arc<anyhow::Error>>;
+// Requests are not `Send` on wasm32, where `BoxFuture` is `LocalBoxFuture`.
+type SharedRequest = Shared<BoxFuture<'static, SharedBody>>;
+
+/// In-flight `GET` requests, keyed by authorization and URL.
+#[derive(Default)]
+struct InFlight(Mutex<HashMap<(String, String), SharedRequest>>);
+
+impl fmt::Debug for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InFlight").finish_non_exhaustive()
+    }
+}
+
+/// The error of a request shared by several callers.
+#[derive(Debug)]
+struct SharedError(Arc<anyhow::Error>);
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Configures an [`ApiClient`].
+#[derive(Debug, Default)]
+pub struct ApiClientBuilder {
+    client: Option<Client>,
+    base_url: Option<String>,
+}
+
+impl ApiClient {
+    pub fn builder() -> ApiClientBuilder {
+        ApiClientBuilder::default()
+    }
+
+    /// The underlying HTTP client, also used to download media.
+    pub fn http(&self) -> &Client {
+        &self.client
+    }
+
+    /// Rate limits reported by the API so far.
+    pub fn rate_limits(&self) -> RateLimits {
+        *self.rate_limits.lock().unwrap()
+    }
+
+    /// Sends a `GET` request to `path`, or waits for an identical request already in flight.
+    /// Fails with [`RateLimited`] if the API rate limits the request.
+    async fn get<T: DeserializeOwned>(&self, auth: &Auth, path: &str) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let key = (auth.header(), url.clone());
+        let request = {
+            let mut in_flight = self.in_flight.0.lock().unwrap();
+            in_flight
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    let request = self.client.get(url).header("Authorization", auth.header());
+                    let rate_limits = self.rate_limits.clone();
+                    let body = async move {
+                        let response = request.send().await?;
+                        rate_limits.lock().unwrap().update(response.headers());
+                        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                            return Err(RateLimited::from_headers(response.headers()).into());
+                        }
+                        let body = response.bytes().await?;
+                        Ok(Arc::new(body.to_vec()))
+                    };
+                    let body: BoxFuture<'static, SharedBody> =
+                        Box::pin(async move { body.await.map_err(Arc::new) });
+                    body.shared()
+                })
+                .clone()
+        };
+        let body = request.clone().await;
+
+        // Later requests should be sent again, rather than reusing this response. A newer
+        // request for the same URL is left in place, as it has not completed.
+        let mut in_flight = self.in_flight.0.lock().unwrap();
+        if in_flight
+            .get(&key)
+            .is_some_and(|request| request.peek().is_some())
+        {
+            in_flight.remove(&key);
+        }
+        drop(in_flight);
+
+        let body = body.map_err(|error| match error.downcast_ref::<RateLimited>() {
+            Some(rate_limited) => anyhow::Error::new(*rate_limited),
+            None => SharedError(error).into(),
+        })?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+impl ApiClientBuilder {
+    /// HTTP client to send requests with. Defaults to a new client.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Base URL of the API, for testing against a mock server or using a proxy. Defaults to
+    /// [`DEFAULT_BASE_URL`].
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    pub fn build(self) -> Result<ApiClient> {
+        let base_url = self
+            .base_url
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_owned())
+            .trim_end_matches('/')
+            .to_owned();
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(anyhow!("Invalid API base URL: {}", base_url));
+        }
+        let client = match self.client {
+            Some(client) => client,
+            None => Client::builder().build()?,
+        };
+        Ok(ApiClient {
+            client,
+            base_url,
+            in_flight: Arc::default(),
+            rate_limits: Arc::default(),
+        })
+    }
+}
+
+/// An uploaded image or created album.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImgurUpload {
+    pub id: String,
+    /// Deletes the upload, and adds images to anonymous albums.
+    pub deletehash: String,
+    /// Not included for albums.
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+/// An image to upload.
+#[derive(Debug, Default)]
+pub struct ImageUpload<'a> {
+    pub data: &'a [u8],
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    /// ID of the album to add the image to, or its deletehash for anonymous albums.
+    pub album: Option<&'a str>,
+}
+
+/// Credentials for accessing the Imgur API.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// Anonymous access with an application's client ID.
+    ClientId(String),
+    /// Access on behalf of a user with an OAuth access token.
+    AccessToken(String),
+}
+
+impl Auth {
+    fn header(&self) -> String {
+        match self {
+            Auth::ClientId(client_id) => format!("Client-ID {}", client_id),
+            Auth::AccessToken(token) => format!("Bearer {}", token),
+        }
+    }
+}
+
+const IMGUR_ALBUM_URL_PREFIX: &str = "https://imgur.com/a/";
+pub fn get_album_id(album_id: &str) -> &str {
+    album_id
+        .strip_prefix(IMGUR_ALBUM_URL_PREFIX)
+        .unwrap_or(album_id)
+}
+
+/// Returns the post ID of a gallery URL such as `https://imgur.com/gallery/<id>`, where the ID
+/// may be preceded by a slug of the post's title, e.g. `/gallery/my-cat-<id>`.
+pub fn get_gallery_id(input: &str) -> Option<&str> {
+    match parse_link(input)? {
+        ImgurLink::GalleryPost(id) => Some(id),
+        _ => None,
+    }
+}
+
+/// What an album ID or Imgur URL refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImgurLink<'a> {
+    Album(&'a str),
+    GalleryPost(&'a str),
+    Image(&'a str),
+    User(&'a str),
+    Tag(&'a str),
+    Subreddit(&'a str),
+}
+
+impl<'a> ImgurLink<'a> {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ImgurLink::Album(_) => "album",
+            ImgurLink::GalleryPost(_) => "gallery",
+            ImgurLink::Image(_) => "image",
+            ImgurLink::User(_) => "user",
+            ImgurLink::Tag(_) => "tag",
+            ImgurLink::Subreddit(_) => "subreddit",
+        }
+    }
+
+    /// The album ID, post ID, image ID, username, tag or subreddit.
+    pub fn id(&self) -> &'a str {
+        match self {
+            ImgurLink::Album(id)
+            | ImgurLink::GalleryPost(id)
+            | ImgurLink::Image(id)
+            | ImgurLink::User(id)
+            | ImgurLink::Tag(id)
+            | ImgurLink::Subreddit(id) => id,
+        }
+    }
+
+    /// The API endpoint describing what the link refers to. Gallery posts of a single image are
+    /// fetched from `/3/gallery/image/<id>` instead, and a user's albums which were not posted to
+    /// the gallery from `/3/account/<name>/albums` as well.
+    pub fn endpoint(&self) -> String {
+        match self {
+            ImgurLink::Album(id) => format!("/3/album/{}", id),
+            ImgurLink::GalleryPost(id) => format!("/3/gallery/album/{}", id),
+            ImgurLink::Image(id) => format!("/3/image/{}", id),
+            ImgurLink::User(name) => format!("/3/account/{}/submissions", name),
+            ImgurLink::Tag(tag) => format!("/3/gallery/t/{}", tag),
+            ImgurLink::Subreddit(name) => format!("/3/gallery/r/{}", name),
+        }
+    }
+}
+
+/// Parses an album ID or an Imgur URL, such as `https://imgur.com/a/<id>`,
+/// `https://imgur.com/gallery/<slug>-<id>`, `https://i.imgur.com/<id>.jpg`,
+/// `https://imgur.com/user/<name>`, `https://imgur.com/t/<tag>` or `https://imgur.com/r/<name>`.
+/// Returns `None` for other URLs.
+pub fn parse_link(input: &str) -> Option<ImgurLink<'_>> {
+    let input = input.trim();
+    let Some(rest) = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+    else {
+        let valid = !input.is_empty() && input.chars().all(|c| c.is_ascii_alphanumeric());
+        return valid.then_some(ImgurLink::Album(input));
+    };
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let first = segments.next()?;
+
+    if host == "i.imgur.com" {
+        let id = first.split('.').next()?;
+        return (!id.is_empty()).then_some(ImgurLink::Image(id));
+    }
+    if !matches!(host, "imgur.com" | "www.imgur.com" | "m.imgur.com") {
+        return None;
+    }
+    let link = match (first, segments.next()) {
+        ("a", Some(id)) => ImgurLink::Album(id),
+        ("gallery", Some(slug)) => ImgurLink::GalleryPost(slug.rsplit('-').next()?),
+        ("user", Some(name)) => ImgurLink::User(name),
+        ("t", Some(tag)) => ImgurLink::Tag(tag),
+        ("r", Some(name)) => ImgurLink::Subreddit(name),
+        (id, None) if !matches!(id, "a" | "gallery" | "user" | "t" | "r") => {
+            ImgurLink::Image(id.split('.').next()?)
+        }
+        _ => return None,
+    };
+    (!link.id().is_empty()).then_some(link)
+}
+
+/// Whether `host` is Imgur or one of its subdomains, such as `i.imgur.com`.
+pub fn is_imgur_host(host: &str) -> bool {
+    host == "imgur.com" || host.ends_with(".imgur.com")
+}
+
+/// Sizes of thumbnails Imgur generates for images, see <https://api.imgur.com/models/image>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    /// 90x90, cropped to a square.
+    SmallSquare,
+    /// 160x160, cropped to a square.
+    BigSquare,
+    /// Up to 160x160.
+    Small,
+    /// Up to 320x320.
+    Medium,
+    /// Up to 640x640.
+    Large,
+    /// Up to 1024x1024.
+    Huge,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 6] = [
+        ThumbnailSize::SmallSquare,
+        ThumbnailSize::BigSquare,
+        ThumbnailSize::Small,
+        ThumbnailSize::Medium,
+        ThumbnailSize::Large,
+        ThumbnailSize::Huge,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThumbnailSize::SmallSquare => "small-square",
+            ThumbnailSize::BigSquare => "big-square",
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
+            ThumbnailSize::Huge => "huge",
+        }
+    }
+
+    /// The longest side of thumbnails of this size, in pixels.
+    pub fn max_dimension(self) -> u32 {
+        match self {
+            ThumbnailSize::SmallSquare => 90,
+            ThumbnailSize::BigSquare | ThumbnailSize::Small => 160,
+            ThumbnailSize::Medium => 320,
+            ThumbnailSize::Large => 640,
+            ThumbnailSize::Huge => 1024,
+        }
+    }
+
+    fn suffix(self) -> char {
+        match self {
+            ThumbnailSize::SmallSquare => 's',
+            ThumbnailSize::BigSquare => 'b',
+            ThumbnailSize::Small => 't',
+            ThumbnailSize::Medium => 'm',
+            ThumbnailSize::Large => 'l',
+            ThumbnailSize::Huge => 'h',
+        }
+    }
+}
+
+impl std::str::FromStr for ThumbnailSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ThumbnailSize, String> {
+        ThumbnailSize::ALL
+            .into_iter()
+            .find(|size| size.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = ThumbnailSize::ALL.iter().map(|size| size.name()).collect();
+                format!("expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+/// Returns the URL of a thumbnail of the image with the given ID.
+pub fn thumbnail_link(image_id: &str, size: ThumbnailSize) -> String {
+    format!("https://i.imgur.com/{}{}.jpg", image_id, size.suffix())
+}
+
+/// File extensions for the MIME types Imgur serves. Animated PNGs keep the `.png` extension, as
+/// they are valid PNGs.
+const MEDIA_EXTENSIONS: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/pjpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/apng", "png"),
+    ("image/vnd.mozilla.apng", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/avif", "avif"),
+    ("image/heic", "heic"),
+    ("image/heif", "heif"),
+    ("image/tiff", "tiff"),
+    ("image/bmp", "bmp"),
+    ("image/svg+xml", "svg"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/quicktime", "mov"),
+    ("video/x-matroska", "mkv"),
+];
+
+/// Returns the file extension for media of `content_type`, falling back to the extension of
+/// `link` for types not in the table, and `unknown` if it has none.
+pub fn get_media_type(content_type: &str, link: &str) -> String {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if let Some((_, extension)) = MEDIA_EXTENSIONS
+        .iter()
+        .find(|(mime, _)| *mime == content_type)
+    {
+        return (*extension).to_owned();
+    }
+
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    let filename = path.rsplit('/').next().unwrap_or_default();
+    match filename.rsplit_once('.') {
+        Some((_, extension))
+            if !extension.is_empty()
+                && extension.len() <= 5
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            extension.to_ascii_lowercase()
+        }
+        _ => "unknown".to_owned(),
+    }
+}
+
+/// Fetches an album's details from the Imgur API.
+pub async fn fetch_album(
+    api: &ApiClient,
+    auth: &Auth,
+    album_id: &str,
+) -> Result<ImgurResponse<ImgurAlbum>> {
+    api.get(auth, &format!("/3/album/{}", album_id)).await
+}
+
+/// Fetches an image, wrapped in an album of its own.
+pub async fn fetch_image(
+    api: &ApiClient,
+    auth: &Auth,
+    image_id: &str,
+) -> Result<ImgurResponse<ImgurAlbum>> {
+    // Error responses have an object in place of the image.
+    let response: ImgurResponse<serde_json::Value> =
+        api.get(auth, &format!("/3/image/{}", image_id)).await?;
+    let data = match response.data {
+        Some(data) if response.status == 200 => {
+            let image: ImgurMedia = serde_json::from_value(data)?;
+            Some(ImgurAlbum {
+                id: image.id.clone(),
+                title: image.title.clone(),
+                description: None,
+                account_url: None,
+                tags: Vec::new(),
+                datetime: image.datetime,
+                images: vec![image],
+            })
+        }
+        _ => None,
+    };
+    Ok(ImgurResponse {
+        data,
+        status: response.status,
+    })
+}
+
+impl ImgurGalleryItem {
+    /// Wraps a single image in an album of its own, or returns `None` for albums.
+    fn image_album(&self) -> Option<ImgurAlbum> {
+        if self.is_album {
+            return None;
+        }
+        let media = ImgurMedia {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            link: self.link.clone()?,
+            datetime: self.datetime,
+            size: self.size,
+            content_type: self.content_type.clone()?,
+            width: self.width,
+            height: self.height,
+            animated: self.animated,
+        };
+        Some(ImgurAlbum {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            description: None,
+            account_url: self.account_url.clone(),
+            tags: self.tags.clone(),
+            datetime: self.datetime,
+            images: vec![media],
+        })
+    }
+}
+
+/// Expands a gallery listing entry into an album with all of its images. Albums are fetched
+/// through the album endpoint, since listings only include some of their images, while single
+/// images are wrapped in an album of their own.
+pub async fn expand_gallery_item(
+    api: &ApiClient,
+    auth: &Auth,
+    item: ImgurGalleryItem,
+) -> Result<ImgurAlbum> {
+    if let Some(album) = item.image_album() {
+        return Ok(album);
+    }
+    let response = fetch_album(api, auth, &item.id).await?;
+    let mut album = response.data.ok_or_else(|| {
+        anyhow!(
+            "Failed to get album {} with status code: {}",
+            item.id,
+            response.status
+        )
+    })?;
+    // The album endpoint does not include the tags of gallery posts.
+    if album.tags.is_empty() {
+        album.tags = item.tags;
+    }
+    Ok(album)
+}
+
+/// Fetches a gallery post, which is either an album or a single image. Single images are
+/// wrapped in an album of their own.
+pub async fn fetch_gallery_post(
+    api: &ApiClient,
+    auth: &Auth,
+    post_id: &str,
+) -> Result<ImgurResponse<ImgurAlbum>> {
+    // Error responses have an object in place of the album.
+    let response: ImgurResponse<serde_json::Value> = api
+        .get(auth, &format!("/3/gallery/album/{}", post_id))
+        .await?;
+    if response.status != 404 {
+        return Ok(ImgurResponse {
+            data: match response.data {
+                Some(data) if response.status == 200 => Some(serde_json::from_value(data)?),
+                _ => None,
+            },
+            status: response.status,
+        });
+    }
+
+    let response: ImgurResponse<serde_json::Value> = api
+        .get(auth, &format!("/3/gallery/image/{}", post_id))
+        .await?;
+    let data = match response.data {
+        Some(data) if response.status == 200 => {
+            let item: ImgurGalleryItem = serde_json::from_value(data)?;
+            let album = item
+                .image_album()
+                .ok_or_else(|| anyhow!("Gallery image {} has no link or type", item.id))?;
+            Some(album)
+        }
+        _ => None,
+    };
+    Ok(ImgurResponse {
+        data,
+        status: response.status,
+    })
+}
+
+/// Fetches the remaining API credits for a client ID or user.
+pub async fn fetch_credits(api: &ApiClient, auth: &Auth) -> Result<ImgurResponse<ImgurCredits>> {
+    api.get(auth, "/3/credits").await
+}
+
+/// A page of a paginated listing, such as the albums of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    /// Numbered from 0. Pages past the end of a listing are empty.
+    pub number: u32,
+    /// Number of entries per page, at most [`Page::MAX_PER_PAGE`].
+    pub per_page: u32,
+}
+
+impl Page {
+    /// The largest page size accepted by the API, which defaults to 50.
+    pub const MAX_PER_PAGE: u32 = 100;
+
+    /// The first page of a listing, with `per_page` entries.
+    pub fn first(per_page: u32) -> Page {
+        Page {
+            number: 0,
+            per_page: per_page.clamp(1, Page::MAX_PER_PAGE),
+        }
+    }
+
+    pub fn next(self) -> Page {
+        Page {
+            number: self.number + 1,
+            ..self
+        }
+    }
+
+    /// Whether a page which returned `len` entries is the last of its listing, so no further
+    /// request is needed.
+    pub fn is_last(&self, len: usize) -> bool {
+        len < self.per_page as usize
+    }
+
+    fn path(&self, listing: &str) -> String {
+        format!("{}/{}?perPage={}", listing, self.number, self.per_page)
+    }
+}
+
+impl Default for Page {
+    /// The first page, with as many entries as possible.
+    fn default() -> Page {
+        Page::first(Page::MAX_PER_PAGE)
+    }
+}
+
+/// Fetches every page of a listing of `what`, such as `"albums"`, with `fetch`.
+pub async fn all_pages<T, F, Fut>(what: &str, mut fetch: F) -> Result<Vec<T>>
+where
+    F: FnMut(Page) -> Fut,
+    Fut: Future<Output = Result<ImgurResponse<Vec<T>>>>,
+{
+    let mut all = vec![];
+    let mut page = Page::default();
+    loop {
+        let response = fetch(page).await?;
+        let entries = response.data.ok_or_else(|| {
+            anyhow!(
+                "Failed to list {} with status code: {}",
+                what,
+                response.status
+            )
+        })?;
+        let last = page.is_last(entries.len());
+        all.extend(entries);
+        if last {
+            return Ok(all);
+        }
+        page = page.next();
+    }
+}
+
+/// Fetches a page of the authenticated user's albums, without their images.
+pub async fn fetch_account_albums(
+    api: &ApiClient,
+    auth: &Auth,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurAlbum>>> {
+    api.get(auth, &page.path("/3/account/me/albums")).await
+}
+
+/// Fetches a page of the authenticated user's favorites, newest first. Favorites are gallery
+/// posts, of albums or single images.
+pub async fn fetch_account_favorites(
+    api: &ApiClient,
+    auth: &Auth,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    api.get(auth, &page.path("/3/account/me/favorites")).await
+}
+
+/// Fetches a page of a user's gallery posts, newest first.
+pub async fn fetch_account_submissions(
+    api: &ApiClient,
+    auth: &Auth,
+    username: &str,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    let listing = format!("/3/account/{}/submissions", username);
+    api.get(auth, &page.path(&listing)).await
+}
+
+/// Fetches a page of a user's public albums, without their images.
+pub async fn fetch_user_albums(
+    api: &ApiClient,
+    auth: &Auth,
+    username: &str,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurAlbum>>> {
+    let listing = format!("/3/account/{}/albums", username);
+    api.get(auth, &page.path(&listing)).await
+}
+
+/// How the posts of a subreddit gallery are sorted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SubredditSort {
+    /// Newest first.
+    #[default]
+    Time,
+    /// Highest scoring first, within the time window.
+    Top,
+}
+
+/// How the posts of the gallery or a tag are sorted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GallerySort {
+    /// Most popular first.
+    #[default]
+    Viral,
+    /// Highest scoring first, within the time window.
+    Top,
+    /// Newest first.
+    Time,
+    /// Gaining popularity fastest first, only for the user section.
+    Rising,
+}
+
+impl GallerySort {
+    fn as_str(self) -> &'static str {
+        match self {
+            GallerySort::Viral => "viral",
+            GallerySort::Top => "top",
+            GallerySort::Time => "time",
+            GallerySort::Rising => "rising",
+        }
+    }
+}
+
+/// A section of the gallery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GallerySection {
+    /// The front page, of the most viral posts.
+    #[default]
+    Hot,
+    /// The highest scoring posts.
+    Top,
+    /// User submitted posts which have not reached the front page, known as "Usersub".
+    User,
+}
+
+impl GallerySection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GallerySection::Hot => "hot",
+            GallerySection::Top => "top",
+            GallerySection::User => "user",
+        }
+    }
+}
+
+/// The time window of posts sorted by score.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TimeWindow {
+    Day,
+    #[default]
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl TimeWindow {
+    fn as_str(self) -> &'static str {
+        match self {
+            TimeWindow::Day => "day",
+            TimeWindow::Week => "week",
+            TimeWindow::Month => "month",
+            TimeWindow::Year => "year",
+            TimeWindow::All => "all",
+        }
+    }
+}
+
+/// Fetches a page of the gallery of images posted to a subreddit. Gallery pages may hold fewer
+/// posts than asked for before the end of the listing.
+pub async fn fetch_subreddit_gallery(
+    api: &ApiClient,
+    auth: &Auth,
+    subreddit: &str,
+    sort: SubredditSort,
+    window: TimeWindow,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    let sort = match sort {
+        SubredditSort::Time => "time",
+        SubredditSort::Top => "top",
+    };
+    let listing = format!("/3/gallery/r/{}/{}/{}", subreddit, sort, window.as_str());
+    api.get(auth, &page.path(&listing)).await
+}
+
+/// Fetches a page of a section of the gallery. Gallery pages may hold fewer posts than asked for
+/// before the end of the listing.
+pub async fn fetch_gallery(
+    api: &ApiClient,
+    auth: &Auth,
+    section: GallerySection,
+    sort: GallerySort,
+    window: TimeWindow,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    let listing = format!(
+        "/3/gallery/{}/{}/{}",
+        section.as_str(),
+        sort.as_str(),
+        window.as_str()
+    );
+    api.get(auth, &page.path(&listing)).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ImgurTagGallery {
+    items: Vec<ImgurGalleryItem>,
+}
+
+/// Fetches a page of the gallery of posts with a tag. Gallery pages may hold fewer posts than
+/// asked for before the end of the listing.
+pub async fn fetch_tag_gallery(
+    api: &ApiClient,
+    auth: &Auth,
+    tag: &str,
+    sort: GallerySort,
+    window: TimeWindow,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    let listing = format!("/3/gallery/t/{}/{}/{}", tag, sort.as_str(), window.as_str());
+    // Error responses have an object in place of the tag.
+    let response: ImgurResponse<serde_json::Value> = api.get(auth, &page.path(&listing)).await?;
+    let data = match response.data {
+        Some(data) if response.status == 200 => {
+            Some(serde_json::from_value::<ImgurTagGallery>(data)?.items)
+        }
+        _ => None,
+    };
+    Ok(ImgurResponse {
+        data,
+        status: response.status,
+    })
+}
+
+/// Percent-encodes `value` for a URL's query string.
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Fetches a page of the gallery posts matching a search `query`, which may use Imgur's search
+/// syntax such as `cats AND dogs` or `tag:cats`. Gallery pages may hold fewer posts than asked
+/// for before the end of the listing.
+pub async fn fetch_gallery_search(
+    api: &ApiClient,
+    auth: &Auth,
+    query: &str,
+    sort: GallerySort,
+    window: TimeWindow,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurGalleryItem>>> {
+    let listing = format!("/3/gallery/search/{}/{}", sort.as_str(), window.as_str());
+    let path = format!("{}&q={}", page.path(&listing), encode_query_value(query));
+    api.get(auth, &path).await
+}
+
+/// Fetches a page of a user's public comments, newest first.
+pub async fn fetch_account_comments(
+    api: &ApiClient,
+    auth: &Auth,
+    username: &str,
+    page: Page,
+) -> Result<ImgurResponse<Vec<ImgurComment>>> {
+    let listing = format!("/3/account/{}/comments/newest", username);
+    api.get(auth, &page.path(&listing)).await
+}
+
+/// Fetches the authenticated user's account settings, including their notification and
+/// messaging preferences, see <https://apidocs.imgur.com/#account-settings>.
+pub async fn fetch_account_settings(
+    api: &ApiClient,
+    auth: &Auth,
+) -> Result<ImgurResponse<serde_json::Value>> {
+    api.get(auth, "/3/account/me/settings").await
+}
+
+/// Fetches the users blocked by the authenticated user.
+pub async fn fetch_account_blocks(
+    api: &ApiClient,
+    auth: &Auth,
+) -> Result<ImgurResponse<serde_json::Value>> {
+    api.get(auth, "/3/account/me/block").await
+}
+
+/// Fetches all of the authenticated user's notifications, including those already viewed. The
+/// endpoint is not paginated.
+pub async fn fetch_notifications(
+    api: &ApiClient,
+    auth: &Auth,
+) -> Result<ImgurResponse<ImgurNotifications>> {
+    api.get(auth, "/3/notification?new=false").await
+}
+
+/// Deletes an anonymously uploaded image, given its deletehash.
+pub async fn delete_image(
+    api: &ApiClient,
+    auth: &Auth,
+    deletehash: &str,
+) -> Result<ImgurResponse<bool>> {
+    let response = api
+        .client
+        .delete(format!("{}/3/image/{}", api.base_url, deletehash))
+        .header("Authorization", auth.header())
+        .send()
+        .await?
+        .json::<ImgurResponse<bool>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// Deletes an anonymously created album, given its deletehash. Its images are not deleted.
+pub async fn delete_album(
+    api: &ApiClient,
+    auth: &Auth,
+    deletehash: &str,
+) -> Result<ImgurResponse<bool>> {
+    let response = api
+        .client
+        .delete(format!("{}/3/album/{}", api.base_url, deletehash))
+        .header("Authorization", auth.header())
+        .send()
+        .await?
+        .json::<ImgurResponse<bool>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// Uploads an image, anonymously unless authenticated with an access token.
+pub async fn upload_image(
+    api: &ApiClient,
+    auth: &Auth,
+    image: &ImageUpload<'_>,
+) -> Result<ImgurResponse<ImgurUpload>> {
+    let data = base64::encode(image.data);
+    let mut form = vec![("image", data.as_str()), ("type", "base64")];
+    form.extend(image.title.map(|title| ("title", title)));
+    form.extend(
+        image
+            .description
+            .map(|description| ("description", description)),
+    );
+    form.extend(image.album.map(|album| ("album", album)));
+    let response = api
+        .client
+        .post(format!("{}/3/image", api.base_url))
+        .header("Authorization", auth.header())
+        .form(&form)
+        .send()
+        .await?
+        .json::<ImgurResponse<ImgurUpload>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// Creates an empty album, anonymously unless authenticated with an access token.
+pub async fn create_album(
+    api: &ApiClient,
+    auth: &Auth,
+    title: Option<&str>,
+    description: Option<&str>,
+) -> Result<ImgurResponse<ImgurUpload>> {
+    let mut form = vec![];
+    form.extend(title.map(|title| ("title", title)));
+    form.extend(description.map(|description| ("description", description)));
+    let response = api
+        .client
+        .post(format!("{}/3/album", api.base_url))
+        .header("Authorization", auth.header())
+        .form(&form)
+        .send()
+        .await?
+        .json::<ImgurResponse<ImgurUpload>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// Sets an album's title, description and images, in order. Anonymous albums and their images
+/// are given by their deletehashes, and albums of the authenticated user by their IDs.
+pub async fn update_album(
+    api: &ApiClient,
+    auth: &Auth,
+    album: &str,
+    title: Option<&str>,
+    description: Option<&str>,
+    images: &[&str],
+) -> Result<ImgurResponse<bool>> {
+    let images_key = match auth {
+        Auth::ClientId(_) => "deletehashes[]",
+        Auth::AccessToken(_) => "ids[]",
+    };
+    let mut form = vec![];
+    form.extend(title.map(|title| ("title", title)));
+    form.extend(description.map(|description| ("description", description)));
+    form.extend(images.iter().map(|image| (images_key, *image)));
+    let response = api
+        .client
+        .put(format!("{}/3/album/{}", api.base_url, album))
+        .header("Authorization", auth.header())
+        .form(&form)
+        .send()
+        .await?
+        .json::<ImgurResponse<bool>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// Sets an image's title and description. Anonymous images are given by their deletehash.
+pub async fn update_image(
+    api: &ApiClient,
+    auth: &Auth,
+    image: &str,
+    title: Option<&str>,
+    description: Option<&str>,
+) -> Result<ImgurResponse<bool>> {
+    let mut form = vec![];
+    form.extend(title.map(|title| ("title", title)));
+    form.extend(description.map(|description| ("description", description)));
+    let response = api
+        .client
+        .post(format!("{}/3/image/{}", api.base_url, image))
+        .header("Authorization", auth.header())
+        .form(&form)
+        .send()
+        .await?
+        .json::<ImgurResponse<bool>>()
+        .await?;
+
+    Ok(response)
+}
+
+/// URL at which a user authorizes an application, see
+/// <https://apidocs.imgur.com/#authorization-and-oauth>. With `response_type` "pin" the user is
+/// shown a PIN to enter; with "code" they are redirected to the application's callback URL.
+pub fn oauth_authorize_url(
+    api: &ApiClient,
+    client_id: &str,
+    response_type: &str,
+    state: &str,
+) -> String {
+    format!(
+        "{}/oauth2/authorize?client_id={}&response_type={}&state={}",
+        api.base_url,
+        encode_query_value(client_id),
+        encode_query_value(response_type),
+        encode_query_value(state)
+    )
+}
+
+/// What to exchange for OAuth tokens.
+#[derive(Debug, Clone, Copy)]
+pub enum OAuthGrant<'a> {
+    /// A PIN shown to the user after authorizing the application.
+    Pin(&'a str),
+    /// A code passed to the application's callback URL.
+    AuthorizationCode(&'a str),
+    /// The refresh token of an expired access token.
+    RefreshToken(&'a str),
+}
+
+/// OAuth tokens for a user.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
+    #[serde(default)]
+    pub account_username: Option<String>,
+}
+
+/// Exchanges a PIN, code or refresh token for new OAuth tokens.
+pub async fn fetch_oauth_tokens(
+    api: &ApiClient,
+    client_id: &str,
+    client_secret: &str,
+    grant: OAuthGrant<'_>,
+) -> Result<OAuthTokens> {
+    let (grant_type, key, value) = match grant {
+        OAuthGrant::Pin(pin) => ("pin", "pin", pin),
+        OAuthGrant::AuthorizationCode(code) => ("authorization_code", "code", code),
+        OAuthGrant::RefreshToken(token) => ("refresh_token", "refresh_token", token),
+    };
+    let form = [
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("grant_type", grant_type),
+        (key, value),
+    ];
+    let response = api
+        .client
+        .post(format!("{}/oauth2/token", api.base_url))
+        .form(&form)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        let error = body["data"]["error"].as_str().unwrap_or("unknown error");
+        return Err(anyhow!(
+            "Unable to get OAuth tokens ({}): {}",
+            status,
+            error
+        ));
+    }
+    Ok(response.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gallery_items() {
+        let album: ImgurGalleryItem = serde_json::from_str(
+            r#"{"id": "abc", "title": "Album", "datetime": 1, "is_album": true, "cover": "x",
+                "images_count": 3, "images": []}"#,
+        )
+        .unwrap();
+        assert!(album.is_album);
+        assert!(album.link.is_none());
+
+        let image: ImgurGalleryItem = serde_json::from_str(
+            r#"{"id": "xyz", "title": "Image", "description": null, "datetime": 2,
+                "is_album": false, "link": "https://i.imgur.com/xyz.png", "size": 10,
+                "type": "image/png", "width": 1, "height": 1}"#,
+        )
+        .unwrap();
+        assert!(!image.is_album);
+        assert_eq!(image.link.as_deref(), Some("https://i.imgur.com/xyz.png"));
+        assert_eq!(image.content_type.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn oauth_authorize_urls() {
+        let api = ApiClient::builder()
+            .base_url("http://localhost:8080/")
+            .build()
+            .unwrap();
+        assert_eq!(
+            oauth_authorize_url(&api, "id&x=1", "pin", "a b"),
+            "http://localhost:8080/oauth2/authorize?client_id=id%26x%3D1&response_type=pin&state=a%20b"
+        );
+        let api = ApiClient::builder().build().unwrap();
+        assert!(oauth_authorize_url(&api, "id", "code", "state")
+            .starts_with("https://api.imgur.com/oauth2/authorize?"));
+    }
+
+    #[test]
+    fn gallery_ids() {
+        assert_eq!(get_gallery_id("https://imgur.com/gallery/abc"), Some("abc"));
+        assert_eq!(
+            get_gallery_id("https://m.imgur.com/gallery/my-cat-abc?utm=1"),
+            Some("abc")
+        );
+        assert_eq!(
+            get_gallery_id("http://www.imgur.com/gallery/abc/"),
+            Some("abc")
+        );
+        assert_eq!(get_gallery_id("https://imgur.com/gallery/"), None);
+        assert_eq!(get_gallery_id("https://imgur.com/a/abc"), None);
+        assert_eq!(get_gallery_id("abc"), None);
+    }
+
+    #[test]
+    fn media_types() {
+        let link = "https://i.imgur.com/abc.png";
+        assert_eq!(get_media_type("image/jpeg", link), "jpg");
+        assert_eq!(get_media_type("image/apng", link), "png");
+        assert_eq!(get_media_type("image/webp", link), "webp");
+        assert_eq!(get_media_type("image/avif", link), "avif");
+        assert_eq!(get_media_type("video/webm", link), "webm");
+        assert_eq!(get_media_type("Video/MP4; codecs=avc1", link), "mp4");
+        assert_eq!(
+            get_media_type("image/x-new", "https://i.imgur.com/abc.JXL?1"),
+            "jxl"
+        );
+        assert_eq!(get_media_type("", "https://i.imgur.com/abc"), "unknown");
+        assert_eq!(
+            get_media_type("", "https://i.imgur.com/abc.not/an-extension"),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn encodes_queries() {
+        assert_eq!(encode_query_value("cats AND dogs"), "cats%20AND%20dogs");
+        assert_eq!(encode_query_value("tag:été"), "tag%3A%C3%A9t%C3%A9");
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(parse_link("abc"), Some(ImgurLink::Album("abc")));
+        assert_eq!(
+            parse_link("https://imgur.com/a/abc?x=1"),
+            Some(ImgurLink::Album("abc"))
+        );
+        assert_eq!(
+            parse_link("https://i.imgur.com/xyz.jpg"),
+            Some(ImgurLink::Image("xyz"))
+        );
+        assert_eq!(
+            parse_link("https://imgur.com/xyz"),
+            Some(ImgurLink::Image("xyz"))
+        );
+        assert_eq!(
+            parse_link("https://imgur.com/user/someone/"),
+            Some(ImgurLink::User("someone"))
+        );
+        assert_eq!(
+            parse_link("https://imgur.com/t/cats"),
+            Some(ImgurLink::Tag("cats"))
+        );
+        assert_eq!(
+            parse_link("https://imgur.com/r/aww/top"),
+            Some(ImgurLink::Subreddit("aww"))
+        );
+        assert_eq!(parse_link("https://example.com/a/abc"), None);
+        assert_eq!(parse_link("https://imgur.com/a/"), None);
+        assert_eq!(parse_link("../abc"), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Uploads downloaded albums to the Internet Archive through its S3-like API.
+//!
+//! See <https://archive.org/developers/ias3.html>.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Client;
+use tokio_util::io::ReaderStream;
+
+use imgurs::download::album_files;
+
+const IA_S3_URL: &str = "https://s3.us.archive.org";
+
+/// Internet Archive S3 credentials, from <https://archive.org/account/s3.php>.
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl Credentials {
+    /// Reads credentials from `$IA_ACCESS_KEY` and `$IA_SECRET_KEY`, if both are set.
+    pub fn from_env() -> Option<Credentials> {
+        Some(Credentials {
+            access_key: std::env::var("IA_ACCESS_KEY").ok()?,
+            secret_key: std::env::var("IA_SECRET_KEY").ok()?,
+        })
+    }
+}
+
+/// Item-level metadata set when the item is created.
+pub struct ItemMetadata {
+    pub title: String,
+    pub mediatype: String,
+    pub collection: String,
+    /// Additional `(key, value)` metadata.
+    pub extra: Vec<(String, String)>,
+}
+
+/// Returns an item identifier derived from an album directory's name.
+pub fn default_identifier(dir: &Path) -> Option<String> {
+    let name = dir
+        .canonicalize()
+        .ok()?
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    let identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let identifier = identifier.trim_matches(|c| c == '_' || c == '.');
+    (!identifier.is_empty()).then(|| identifier.chars().take(100).collect())
+}
+
+/// Encodes a metadata value so that non-ASCII text survives being sent as a header.
+fn header_value(value: &str) -> String {
+    if value.bytes().all(|byte| (0x20..0x7f).contains(&byte)) {
+        return value.to_owned();
+    }
+    let encoded: String = value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect();
+    format!("uri({})", encoded)
+}
+
+/// Uploads every file in `dir` to the item `identifier`, creating it if needed.
+pub async fn upload(
+    client: &Client,
+    dir: &Path,
+    identifier: &str,
+    credentials: &Credentials,
+    metadata: &ItemMetadata,
+) -> Result<()> {
+    let files =
+        album_files(dir).with_context(|| format!("Unable to read directory {}", dir.display()))?;
+    if files.is_empty() {
+        return Err(anyhow!("Directory is empty"));
+    }
+
+    let sty = ProgressStyle::with_template(
+        "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {msg}",
+    )
+    .unwrap()
+    .progress_chars("#>-");
+
+    for (index, (name, size)) in files.iter().enumerate() {
+        let pb = ProgressBar::new(*size);
+        pb.set_style(sty.clone());
+        pb.set_message(name.clone());
+
+        let file = tokio::fs::File::open(dir.join(name))
+            .await
+            .with_context(|| format!("Unable to read {}", name))?;
+        let body = reqwest::Body::wrap_stream(ReaderStream::new(file).inspect({
+            let pb = pb.clone();
+            move |chunk| {
+                if let Ok(chunk) = chunk {
+                    pb.inc(chunk.len() as u64);
+                }
+            }
+        }));
+
+        let mut request = client
+            .put(format!(
+                "{}/{}/{}",
+                IA_S3_URL,
+                identifier,
+                url::form_urlencoded::byte_serialize(name.as_bytes())
+                    .collect::<String>()
+                    .replace('+', "%20")
+            ))
+            .header(
+                "Authorization",
+                format!("LOW {}:{}", credentials.access_key, credentials.secret_key),
+            )
+            .header("Content-Length", *size)
+            .body(body);
+
+        // Item metadata is set by the request which creates the item.
+        if index == 0 {
+            request = request
+                .header("x-amz-auto-make-bucket", "1")
+                .header("x-archive-meta-title", header_value(&metadata.title))
+                .header(
+                    "x-archive-meta-mediatype",
+                    header_value(&metadata.mediatype),
+                )
+                .header(
+                    "x-archive-meta-collection",
+                    header_value(&metadata.collection),
+                );
+            for (i, (key, value)) in metadata.extra.iter().enumerate() {
+                request = request.header(
+                    format!("x-archive-meta{:02}-{}", i, key),
+                    header_value(value),
+                );
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Unable to upload {}", name))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Unable to upload {}: {} {}",
+                name,
+                status,
+                body.trim()
+            ));
+        }
+        pb.finish_and_clear();
+        println!("Uploaded {}", name);
+    }
+
+    println!(
+        "Uploaded {} files to https://archive.org/details/{}",
+        files.len(),
+        identifier
+    );
+    Ok(())
+}
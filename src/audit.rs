@@ -0,0 +1,44 @@
+//! Append-only NDJSON log of everything a job does.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+use imgurs::date::DateTime;
+
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<std::fs::File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Unable to open audit log {}", path.display()))?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends a record of `action`, with the given fields and the current time.
+    pub fn record(&self, action: &str, fields: Value) {
+        let mut record = match fields {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+        record.insert("timestamp".to_owned(), DateTime::now().to_rfc3339().into());
+        record.insert("action".to_owned(), action.into());
+
+        let line = format!("{}\n", Value::Object(record));
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            eprintln!("Unable to write to audit log: {}", e);
+        }
+    }
+}
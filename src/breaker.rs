@@ -0,0 +1,181 @@
+//! Per-host circuit breaker, which holds back downloads from a host that keeps failing and
+//! retries it at increasing intervals, instead of failing every remaining file.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive network failures after which a host is considered unavailable.
+const THRESHOLD: u32 = 5;
+const INITIAL_DELAY: Duration = Duration::from_secs(10);
+const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+/// How long a host may be unavailable before its files are failed instead of retried.
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// A period during which a host was unavailable.
+#[derive(Debug, Clone)]
+pub struct Outage {
+    pub host: String,
+    pub duration: Duration,
+    /// Number of file downloads which were held back and retried.
+    pub retried_files: usize,
+}
+
+/// What to do after a network failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Fail the file normally.
+    Fail,
+    /// The host is unavailable; retry the file once it may be available again.
+    Retry,
+    /// Like `Retry`, but the host has just become unavailable, or is still unavailable after a
+    /// retry, and will be retried after this long.
+    Tripped(Duration),
+    /// The host has been unavailable for too long; fail the file and all further files.
+    GaveUp,
+}
+
+#[derive(Debug)]
+struct OpenState {
+    since: Instant,
+    retry_at: Instant,
+    delay: Duration,
+    retried_files: usize,
+}
+
+#[derive(Debug, Default)]
+struct HostState {
+    consecutive_failures: u32,
+    open: Option<OpenState>,
+    gave_up: bool,
+    /// Until when the host asked not to be sent requests, after rate limiting them.
+    held_until: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+impl CircuitBreaker {
+    /// Returns when downloads from `host` may next be attempted, if they are being held back.
+    pub fn retry_at(&self, host: &str) -> Option<Instant> {
+        let hosts = self.hosts.lock().unwrap();
+        let state = hosts.get(host)?;
+        let retry_at = state
+            .open
+            .as_ref()
+            .map(|open| open.retry_at)
+            .max(state.held_until)?;
+        (retry_at > Instant::now()).then_some(retry_at)
+    }
+
+    /// Whether downloads from `host` are being held back by [`CircuitBreaker::hold`], rather
+    /// than because of failures.
+    pub fn is_held(&self, host: &str) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .get(host)
+            .and_then(|state| state.held_until)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// Holds back downloads from `host` for `duration`, e.g. as asked by a rate limited response.
+    pub fn hold(&self, host: &str, duration: Duration) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_owned()).or_default();
+        let until = Instant::now() + duration;
+        state.held_until = state.held_until.max(Some(until));
+    }
+
+    /// Records a successful download, returning the outage which it ended, if any.
+    pub fn record_success(&self, host: &str) -> Option<Outage> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.get_mut(host)?;
+        state.consecutive_failures = 0;
+        state.gave_up = false;
+        let open = state.open.take()?;
+        Some(Outage {
+            host: host.to_owned(),
+            duration: open.since.elapsed(),
+            retried_files: open.retried_files,
+        })
+    }
+
+    /// Records a network failure of a download from `host`.
+    pub fn record_failure(&self, host: &str) -> Failure {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_owned()).or_default();
+        if state.gave_up {
+            return Failure::Fail;
+        }
+        state.consecutive_failures += 1;
+
+        let now = Instant::now();
+        match &mut state.open {
+            Some(open) if now.duration_since(open.since) > GIVE_UP_AFTER => {
+                state.open = None;
+                state.gave_up = true;
+                Failure::GaveUp
+            }
+            // Downloads attempted after the delay failed again, so wait longer.
+            Some(open) if now >= open.retry_at => {
+                open.delay = (open.delay * 2).min(MAX_DELAY);
+                open.retry_at = now + open.delay;
+                open.retried_files += 1;
+                Failure::Tripped(open.delay)
+            }
+            Some(open) => {
+                open.retried_files += 1;
+                Failure::Retry
+            }
+            None if state.consecutive_failures >= THRESHOLD => {
+                state.open = Some(OpenState {
+                    since: now,
+                    retry_at: now + INITIAL_DELAY,
+                    delay: INITIAL_DELAY,
+                    retried_files: 1,
+                });
+                Failure::Tripped(INITIAL_DELAY)
+            }
+            None => Failure::Fail,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trips_after_consecutive_failures_and_reports_outage() {
+        let breaker = CircuitBreaker::default();
+        for _ in 1..THRESHOLD {
+            assert_eq!(breaker.record_failure("i.imgur.com"), Failure::Fail);
+        }
+        assert!(breaker.retry_at("i.imgur.com").is_none());
+        assert_eq!(
+            breaker.record_failure("i.imgur.com"),
+            Failure::Tripped(INITIAL_DELAY)
+        );
+        assert_eq!(breaker.record_failure("i.imgur.com"), Failure::Retry);
+        assert!(breaker.retry_at("i.imgur.com").is_some());
+        assert!(breaker.retry_at("example.com").is_none());
+
+        let outage = breaker.record_success("i.imgur.com").unwrap();
+        assert_eq!(outage.retried_files, 2);
+        assert!(breaker.retry_at("i.imgur.com").is_none());
+        assert!(breaker.record_success("i.imgur.com").is_none());
+    }
+
+    #[test]
+    fn holds_rate_limited_hosts() {
+        let breaker = CircuitBreaker::default();
+        breaker.hold("i.imgur.com", Duration::from_secs(30));
+        let retry_at = breaker.retry_at("i.imgur.com").unwrap();
+        // A shorter hold does not shorten an earlier one.
+        breaker.hold("i.imgur.com", Duration::from_secs(1));
+        assert_eq!(breaker.retry_at("i.imgur.com"), Some(retry_at));
+        assert_eq!(breaker.record_failure("i.imgur.com"), Failure::Fail);
+    }
+}
@@ -0,0 +1,42 @@
+//! Album metadata cached from the API, used by `--offline`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use imgurs::api::ImgurAlbum;
+use imgurs::naming::safe_filename;
+
+pub fn default_dir() -> Option<PathBuf> {
+    crate::paths::data_dir().map(|dir| dir.join("albums"))
+}
+
+fn album_path(dir: &Path, album_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", safe_filename(album_id)))
+}
+
+pub fn store(dir: &Path, album: &ImgurAlbum) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = album_path(dir, &album.id);
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_vec(album)?)?;
+    std::fs::rename(&temp_path, &path)
+        .with_context(|| format!("Unable to cache album {}", album.id))?;
+    Ok(())
+}
+
+pub fn load(dir: &Path, album_id: &str) -> Result<ImgurAlbum> {
+    let path = album_path(dir, album_id);
+    let contents = match std::fs::read(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!(
+                "Album {} has not been cached, fetch it without --offline first",
+                album_id
+            ))
+        }
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+    };
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("Unable to parse cached album {}", path.display()))
+}
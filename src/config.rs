@@ -0,0 +1,169 @@
+//! The configuration file, `$XDG_CONFIG_HOME/imgurs/config.toml` (usually
+//! `~/.config/imgurs/config.toml`) by default.
+//!
+//! ```toml
+//! [defaults]
+//! output = "~/Pictures/imgur"
+//! parallelism = 4
+//! retries = 5
+//!
+//! [profiles.work]
+//! client_id = "0123456789abcde"
+//! account = "work"
+//! output = "~/work/imgur"
+//!
+//! [pipelines]
+//! wallpapers = [
+//!   { hook = { command = ["magick", "{file}", "{file}.png"], per_file = true } },
+//!   "xmp",
+//!   { hook = { command = ["notify-send", "Downloaded {dir}"] } },
+//! ]
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Defaults for options which are not given on the command line.
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Defaults selectable with `--profile`, which take precedence over `defaults`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Defaults>,
+    /// Post-processing pipelines selectable with `--pipeline`.
+    #[serde(default)]
+    pub pipelines: BTreeMap<String, Vec<Step>>,
+}
+
+/// Defaults for command line options, named like them. A leading `~/` in `output` is replaced
+/// with the home directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub output: Option<PathBuf>,
+    pub directory_template: Option<String>,
+    pub parallelism: Option<usize>,
+    /// Used if neither `--imgur-client-id` nor `$IMGUR_CLIENT_ID` is given.
+    pub client_id: Option<String>,
+    /// Stored account used if `--account` is not given, instead of the default account.
+    pub account: Option<String>,
+    pub filename_template: Option<String>,
+    pub retries: Option<usize>,
+    pub retries_on_429: Option<usize>,
+    /// Duration such as "30m".
+    pub max_rate_limit_wait: Option<String>,
+}
+
+/// A post-processing step run on a downloaded album.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Runs a command in the album directory. `{dir}` is replaced with the album directory, and
+    /// with `per_file`, the command is run for each file with `{file}` replaced by its path.
+    Hook {
+        command: Vec<String>,
+        #[serde(default)]
+        per_file: bool,
+    },
+    /// Writes an XMP sidecar with each file's title, description and source.
+    Xmp,
+    /// Writes a `manifest.toml` describing the album, which can be edited and passed to
+    /// `imgurs album apply` or `imgurs upload --manifest`.
+    Manifest,
+    /// Writes a SHA256SUMS file.
+    Checksums,
+    /// Writes SHA256SUMS and a torrent of the album directory.
+    Torrent {
+        #[serde(default)]
+        trackers: Vec<String>,
+    },
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    crate::paths::config_dir().map(|dir| dir.join("config.toml"))
+}
+
+impl Config {
+    /// Loads the configuration at `path`, or the default location. A missing default
+    /// configuration file is treated as empty.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("Unable to parse config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                Ok(Config::default())
+            }
+            Err(e) => {
+                Err(e).with_context(|| format!("Unable to read config file {}", path.display()))
+            }
+        }
+    }
+
+    /// Defaults of `profile`, falling back to the top-level defaults for those it does not
+    /// set, or only the latter without a profile.
+    pub fn profile_defaults(&self, profile: Option<&str>) -> Result<Defaults> {
+        let Some(name) = profile else {
+            return Ok(self.defaults.clone());
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown profile: {}", name))?
+            .clone();
+        let defaults = self.defaults.clone();
+        Ok(Defaults {
+            output: profile.output.or(defaults.output),
+            directory_template: profile.directory_template.or(defaults.directory_template),
+            parallelism: profile.parallelism.or(defaults.parallelism),
+            client_id: profile.client_id.or(defaults.client_id),
+            account: profile.account.or(defaults.account),
+            filename_template: profile.filename_template.or(defaults.filename_template),
+            retries: profile.retries.or(defaults.retries),
+            retries_on_429: profile.retries_on_429.or(defaults.retries_on_429),
+            max_rate_limit_wait: profile.max_rate_limit_wait.or(defaults.max_rate_limit_wait),
+        })
+    }
+
+    pub fn pipeline(&self, name: &str) -> Result<&[Step]> {
+        self.pipelines
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("Unknown pipeline: {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pipelines() {
+        let config: Config = toml::from_str(
+            r#"
+                [pipelines]
+                wallpapers = [
+                  { hook = { command = ["magick", "{file}", "{file}.png"], per_file = true } },
+                  "xmp",
+                  { torrent = { trackers = [] } },
+                ]
+            "#,
+        )
+        .unwrap();
+        let steps = config.pipeline("wallpapers").unwrap();
+        assert!(matches!(steps[0], Step::Hook { per_file: true, .. }));
+        assert!(matches!(steps[1], Step::Xmp));
+        assert!(matches!(&steps[2], Step::Torrent { trackers } if trackers.is_empty()));
+    }
+}
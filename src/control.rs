@@ -0,0 +1,163 @@
+//! JSON-RPC control interface over a Unix socket.
+//!
+//! Each line sent to the socket is a JSON-RPC 2.0 request, answered with a single line. Supported
+//! methods are `status`, `pause`, `resume`, `set-parallelism` (with `{"parallelism": ...}` or `[n]`
+//! params), `add-url` (with `{"url": ...}` or `[url]` params) and `cancel`.
+
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::job::{Destination, Job, QueuedAlbum};
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Control socket which is removed when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _success = std::fs::remove_file(&self.path).is_ok();
+    }
+}
+
+/// Listens on `path` and serves control requests for `job` in the background.
+pub async fn serve(path: &Path, job: Arc<Job>) -> Result<ControlSocket> {
+    // Replace stale sockets from previous runs, but never other files.
+    if let Ok(metadata) = tokio::fs::symlink_metadata(path).await {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow!("Control socket path exists and is not a socket"));
+        }
+        tokio::fs::remove_file(path)
+            .await
+            .with_context(|| "Unable to remove existing control socket")?;
+    }
+
+    let listener = UnixListener::bind(path).with_context(|| "Unable to bind control socket")?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_connection(stream, job.clone()));
+        }
+    });
+
+    Ok(ControlSocket {
+        path: path.to_owned(),
+    })
+}
+
+async fn handle_connection(stream: UnixStream, job: Arc<Job>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                match handle_request(request, &job) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                }
+            }
+            Err(_) => json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": "Parse error" },
+            }),
+        };
+        writer
+            .write_all(format!("{}\n", response).as_bytes())
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn handle_request(request: Request, job: &Job) -> Result<Value, (i64, String)> {
+    match request.method.as_str() {
+        "status" => {
+            let status = job.status();
+            Ok(json!({
+                "album": status.album,
+                "downloaded_bytes": status.downloaded_bytes,
+                "total_bytes": status.total_bytes,
+                "completed_files": status.completed_files,
+                "total_files": status.total_files,
+                "completed_albums": status.completed_albums,
+                "bytes_per_sec": status.bytes_per_sec,
+                "eta_secs": status.eta.map(|eta| eta.as_secs()),
+                "paused": job.pause.is_paused(),
+                "parallelism": job.parallelism.get(),
+                "cancelled": job.cancel.is_cancelled(),
+                "queued": job.queued(),
+            }))
+        }
+        "pause" => {
+            job.pause.pause();
+            Ok(json!(true))
+        }
+        "resume" => {
+            job.pause.resume();
+            Ok(json!(true))
+        }
+        "set-parallelism" => {
+            let parallelism = match &request.params {
+                Value::Object(params) => params.get("parallelism"),
+                Value::Array(params) => params.first(),
+                _ => None,
+            }
+            .and_then(Value::as_u64)
+            .filter(|&parallelism| parallelism > 0)
+            .ok_or((
+                -32602,
+                "Expected a \"parallelism\" parameter of at least 1".to_owned(),
+            ))?;
+            job.parallelism.set(parallelism as usize);
+            Ok(json!(true))
+        }
+        "add-url" => {
+            let url = match &request.params {
+                Value::Object(params) => params.get("url"),
+                Value::Array(params) => params.first(),
+                _ => None,
+            }
+            .and_then(Value::as_str)
+            .ok_or((-32602, "Expected a \"url\" parameter".to_owned()))?;
+            if job.cancel.is_cancelled() {
+                return Err((-32000, "Job has been cancelled".to_owned()));
+            }
+            job.enqueue(QueuedAlbum {
+                input: url.to_owned(),
+                destination: Destination::Named,
+            });
+            Ok(json!(true))
+        }
+        "cancel" => {
+            job.cancel.cancel();
+            job.pause.resume();
+            Ok(json!(true))
+        }
+        method => Err((-32601, format!("Method not found: {}", method))),
+    }
+}
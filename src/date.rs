@@ -0,0 +1,269 @@
+//! Minimal UTC calendar handling for Imgur's Unix timestamps.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A UTC date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/// Current time as seconds since the Unix epoch.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Formats a duration as e.g. "1h 2m 3s", omitting leading zero units.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+impl DateTime {
+    pub fn from_unix(timestamp: i64) -> DateTime {
+        let days = timestamp.div_euclid(86400);
+        let seconds = timestamp.rem_euclid(86400) as u32;
+
+        // Converts days since 1970-01-01 to a civil date, from
+        // https://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn to_unix(&self) -> i64 {
+        // Converts a civil date to days since 1970-01-01, from
+        // https://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = i64::from(self.month);
+        let doy =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    /// Parses an HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT`, as in `Date` headers.
+    pub fn parse_http_date(date: &str) -> Option<DateTime> {
+        let (_, date) = date.split_once(", ")?;
+        let mut parts = date.split(' ');
+        let day = parts.next()?.parse().ok()?;
+        let month = parts.next()?;
+        let month = MONTHS.iter().position(|name| name[..3] == *month)? as u32 + 1;
+        let year = parts.next()?.parse().ok()?;
+        let mut time = parts.next()?.split(':').map(|part| part.parse().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+        if parts.next()? != "GMT" || time.next().is_some() {
+            return None;
+        }
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    pub fn now() -> DateTime {
+        DateTime::from_unix(unix_now())
+    }
+
+    /// The current time in the local timezone.
+    pub fn now_local() -> DateTime {
+        let now = unix_now();
+        DateTime::from_unix(now + local_offset(now))
+    }
+
+    /// Formats with strftime-style specifiers: `%Y`, `%y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%b`,
+    /// `%B` and `%%`. Other specifiers are kept as-is.
+    pub fn format(&self, format: &str) -> String {
+        let month = MONTHS[(self.month - 1) as usize];
+
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => output.push_str(&format!("{:04}", self.year)),
+                Some('y') => output.push_str(&format!("{:02}", self.year.rem_euclid(100))),
+                Some('m') => output.push_str(&format!("{:02}", self.month)),
+                Some('d') => output.push_str(&format!("{:02}", self.day)),
+                Some('H') => output.push_str(&format!("{:02}", self.hour)),
+                Some('M') => output.push_str(&format!("{:02}", self.minute)),
+                Some('S') => output.push_str(&format!("{:02}", self.second)),
+                Some('b') => output.push_str(&month[..3]),
+                Some('B') => output.push_str(month),
+                Some('%') => output.push('%'),
+                Some(c) => {
+                    output.push('%');
+                    output.push(c);
+                }
+                None => output.push('%'),
+            }
+        }
+        output
+    }
+
+    /// Formats as e.g. `2023-05-01T12:34:56Z`.
+    pub fn to_rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Offset of the local timezone from UTC in seconds at `timestamp`. Always 0 where the local
+/// timezone cannot be determined, which is only known in advance with [`LOCAL_TIME_KNOWN`].
+#[cfg(unix)]
+pub fn local_offset(timestamp: i64) -> i64 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: `localtime_r` only writes to the given `tm`, which is valid to zero-initialize.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+/// Converts `timestamp` to local time with the rules of the current timezone for that year, as
+/// there is no Windows equivalent of `tm_gmtoff`.
+#[cfg(windows)]
+pub fn local_offset(timestamp: i64) -> i64 {
+    #[repr(C)]
+    #[derive(Default)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SystemTime {
+        year: u16,
+        month: u16,
+        day_of_week: u16,
+        day: u16,
+        hour: u16,
+        minute: u16,
+        second: u16,
+        milliseconds: u16,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FileTimeToSystemTime(file_time: *const FileTime, system_time: *mut SystemTime) -> i32;
+        fn SystemTimeToTzSpecificLocalTime(
+            time_zone: *const std::ffi::c_void,
+            universal_time: *const SystemTime,
+            local_time: *mut SystemTime,
+        ) -> i32;
+        fn SystemTimeToFileTime(system_time: *const SystemTime, file_time: *mut FileTime) -> i32;
+    }
+
+    // File times count 100ns intervals since 1601.
+    const TICKS_PER_SECOND: i64 = 10_000_000;
+    const UNIX_EPOCH: i64 = 11_644_473_600;
+    let Some(ticks) = timestamp
+        .checked_add(UNIX_EPOCH)
+        .and_then(|seconds| seconds.checked_mul(TICKS_PER_SECOND))
+        .filter(|ticks| *ticks >= 0)
+    else {
+        return 0;
+    };
+    let universal = FileTime {
+        low: ticks as u32,
+        high: (ticks >> 32) as u32,
+    };
+    let (mut universal_time, mut local_time) = (SystemTime::default(), SystemTime::default());
+    let mut local = FileTime::default();
+    // SAFETY: each call only reads and writes the given structs, and a null timezone selects the
+    // current one.
+    let converted = unsafe {
+        FileTimeToSystemTime(&universal, &mut universal_time) != 0
+            && SystemTimeToTzSpecificLocalTime(std::ptr::null(), &universal_time, &mut local_time)
+                != 0
+            && SystemTimeToFileTime(&local_time, &mut local) != 0
+    };
+    if !converted {
+        return 0;
+    }
+    let local_ticks = ((local.high as i64) << 32) | local.low as i64;
+    (local_ticks - ticks) / TICKS_PER_SECOND
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn local_offset(_timestamp: i64) -> i64 {
+    0
+}
+
+/// Whether [`local_offset`] can determine the local timezone on this platform.
+pub const LOCAL_TIME_KNOWN: bool = cfg!(any(unix, windows));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_dates() {
+        let date = DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(date.to_rfc3339(), "1994-11-06T08:49:37Z");
+        assert_eq!(date.to_unix(), 784111777);
+        assert_eq!(DateTime::from_unix(1_600_000_000).to_unix(), 1_600_000_000);
+        assert!(DateTime::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").is_none());
+    }
+}
@@ -0,0 +1,157 @@
+//! `imgurs doctor`: checks that imgurs can reach Imgur, authenticate and write downloads, as a
+//! first step in triaging problems.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::header::DATE;
+
+use imgurs::api::{fetch_credits, ApiClient, Auth};
+use imgurs::date::{format_duration, unix_now, DateTime};
+
+/// Where images are served from.
+const CDN_URL: &str = "https://i.imgur.com/";
+/// Largest difference from the servers' clocks which is not reported.
+const MAX_CLOCK_SKEW: i64 = 60;
+
+/// Describes `e` with only its root cause, as the errors of HTTP requests repeat their causes in
+/// their own messages.
+fn describe(e: &anyhow::Error) -> String {
+    if e.chain().count() > 1 {
+        format!("{}: {}", e, e.root_cause())
+    } else {
+        e.to_string()
+    }
+}
+
+fn report(name: &str, result: &Result<String>) {
+    match result {
+        Ok(detail) => println!("{}: OK, {}", name, detail),
+        Err(e) => println!("{}: FAILED, {}", name, describe(e)),
+    }
+}
+
+/// Requests `url`, returning its status and the time given in its `Date` header.
+async fn reach(api: &ApiClient, url: &str) -> Result<(reqwest::StatusCode, Option<i64>)> {
+    let response = api
+        .http()
+        .head(url)
+        .send()
+        .await
+        .with_context(|| format!("Unable to reach {}", url))?;
+    let date = response
+        .headers()
+        .get(DATE)
+        .and_then(|date| DateTime::parse_http_date(date.to_str().ok()?))
+        .map(|date| date.to_unix());
+    Ok((response.status(), date))
+}
+
+/// Creates and removes a file in `dir`, or its closest existing ancestor if it does not exist
+/// yet.
+fn check_writable(dir: &Path) -> Result<String> {
+    let existing = dir
+        .ancestors()
+        .find(|dir| dir.as_os_str().is_empty() || dir.is_dir())
+        .ok_or_else(|| anyhow!("{} has no existing parent directory", dir.display()))?;
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+    let probe = existing.join(format!(".imgurs-doctor-{}", std::process::id()));
+    std::fs::write(&probe, b"")
+        .with_context(|| format!("Unable to write to {}", existing.display()))?;
+    std::fs::remove_file(&probe)
+        .with_context(|| format!("Unable to remove {}", probe.display()))?;
+    Ok(format!("{} is writable", existing.display()))
+}
+
+/// Runs every check, printing each result, and fails if any of them did.
+pub async fn run(api: &ApiClient, auth: &Auth, base_url: &str, output: &Path) -> Result<()> {
+    println!(
+        "imgurs {} on {}/{}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let mut failed = 0;
+    let mut server_time = None;
+    let mut check = |name: &str, result: Result<String>| {
+        report(name, &result);
+        failed += usize::from(result.is_err());
+    };
+
+    for (name, url) in [("API", base_url), ("Images", CDN_URL)] {
+        let result = reach(api, url).await.map(|(status, date)| {
+            server_time = server_time.or(date);
+            format!("{} responded with {}", url, status)
+        });
+        check(name, result);
+    }
+
+    let credential = match auth {
+        Auth::ClientId(_) => "client ID",
+        Auth::AccessToken(_) => "access token",
+    };
+    let credits = fetch_credits(api, auth)
+        .await
+        .context("Unable to reach the API")
+        .and_then(|response| {
+            response.data.ok_or_else(|| {
+                anyhow!(
+                    "The API rejected the {} with status code {}",
+                    credential,
+                    response.status
+                )
+            })
+        });
+    let credentials = match (auth, &credits) {
+        (Auth::ClientId(id), _) if id.is_empty() => Err(anyhow!(
+            "No client ID, pass --imgur-client-id or set $IMGUR_CLIENT_ID"
+        )),
+        (_, Ok(_)) => Ok(format!("{} accepted", credential)),
+        (_, Err(e)) => Err(anyhow!(describe(e))),
+    };
+    check("Credentials", credentials);
+    check(
+        "Credits",
+        credits.map(|credits| {
+            format!(
+                "{}/{} for the client ID, {}/{} for the user, resetting in {}",
+                credits.client_remaining,
+                credits.client_limit,
+                credits.user_remaining,
+                credits.user_limit,
+                format_duration(std::time::Duration::from_secs(
+                    (credits.user_reset - unix_now()).max(0) as u64
+                ))
+            )
+        }),
+    );
+
+    check("Output directory", check_writable(output));
+
+    let clock = match server_time {
+        Some(server_time) => {
+            let skew = unix_now() - server_time;
+            let difference = format_duration(std::time::Duration::from_secs(skew.unsigned_abs()));
+            if skew.abs() > MAX_CLOCK_SKEW {
+                Err(anyhow!(
+                    "the local clock is {} {} the servers'",
+                    difference,
+                    if skew > 0 { "ahead of" } else { "behind" }
+                ))
+            } else {
+                Ok(format!("within {} of the servers'", difference))
+            }
+        }
+        None => Err(anyhow!("No server reported its time")),
+    };
+    check("Clock", clock);
+
+    if failed > 0 {
+        return Err(anyhow!("{} checks failed", failed));
+    }
+    Ok(())
+}
@@ -0,0 +1,440 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Cancelled, RateLimited, Removed};
+use crate::naming::safe_filename;
+use crate::plan::PlannedFile;
+use crate::throttle::Throttle;
+use crate::writer::{BufferLimit, FileWriter};
+
+/// What to do when a file already exists at the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OverwritePolicy {
+    /// Keep the existing file.
+    #[default]
+    Skip,
+    /// Download the file again, replacing the existing file.
+    Overwrite,
+    /// Download the file again next to the existing file, as "name (1).ext".
+    Rename,
+    /// Fail the download.
+    Error,
+}
+
+/// What to do with the temporary file of a download which failed or was interrupted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartialPolicy {
+    /// Keep it if the download may succeed when retried, such as after network errors or
+    /// cancellation, so that it can be resumed.
+    #[default]
+    Auto,
+    /// Always keep it, e.g. to resume once disk space has been freed.
+    Keep,
+    /// Remove it once the file has failed or been cancelled.
+    Clean,
+}
+
+/// How a file was successfully handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    Downloaded,
+    /// An existing file was kept.
+    Skipped,
+}
+
+/// Pauses and resumes downloads which share it. Paused downloads stop reading data until resumed.
+#[derive(Debug, Clone)]
+pub struct PauseToken {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for PauseToken {
+    fn default() -> PauseToken {
+        PauseToken {
+            paused: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl PauseToken {
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Completes once downloads are not paused.
+    pub async fn resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        while *paused.borrow_and_update() {
+            if paused.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Number of files downloaded in parallel by the plans which share it, which can be changed while
+/// they run. Raising it starts more files straight away, while lowering it lets in-flight files
+/// finish before further files are started.
+#[derive(Debug, Clone)]
+pub struct Parallelism {
+    limit: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Parallelism {
+    fn default() -> Parallelism {
+        Parallelism::new(8)
+    }
+}
+
+impl Parallelism {
+    pub fn new(parallelism: usize) -> Parallelism {
+        Parallelism {
+            limit: Arc::new(watch::channel(parallelism).0),
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        *self.limit.borrow()
+    }
+
+    pub fn set(&self, parallelism: usize) {
+        self.limit.send_replace(parallelism);
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<usize> {
+        self.limit.subscribe()
+    }
+}
+
+/// Returns the default directory name for an album with the given title.
+pub fn album_directory_name(title: &str) -> PathBuf {
+    PathBuf::from(safe_filename(
+        &title
+            .replace('\n', " ")
+            .replace(" : ", " - ")
+            .replace(": ", " - ")
+            .replace(':', "-"),
+    ))
+}
+
+/// Regular files in `dir` sorted by name, excluding temporary files from unfinished downloads.
+pub fn album_files(dir: &Path) -> Result<Vec<(String, u64)>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if metadata.is_file() && !name.starts_with("~!") {
+            files.push((name, metadata.len()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Extensions which [`sniff_extension`] can detect.
+pub const SNIFFED_EXTENSIONS: &[&str] =
+    &["jpg", "png", "gif", "webp", "avif", "mp4", "mov", "webm"];
+
+/// Detects a file's type from its first bytes, returning the extension it should have.
+pub fn sniff_extension(header: &[u8]) -> Option<&'static str> {
+    match header {
+        [0xff, 0xd8, 0xff, ..] => Some("jpg"),
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some("png"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => Some("webm"),
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand.get(..4)? {
+            b"avif" | b"avis" => Some("avif"),
+            b"qt  " => Some("mov"),
+            _ => Some("mp4"),
+        },
+        _ => None,
+    }
+}
+
+/// Reads the start of the file at `path` and detects its type with [`sniff_extension`].
+pub fn sniff_file(path: &Path) -> Result<Option<&'static str>> {
+    use std::io::Read;
+    let mut header = Vec::with_capacity(16);
+    std::fs::File::open(path)?
+        .take(16)
+        .read_to_end(&mut header)?;
+    Ok(sniff_extension(&header))
+}
+
+/// Returns `path` with the first of " (1)", " (2)", ... inserted before its extension which no
+/// file exists at.
+pub fn renamed_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+pub async fn prepare_directory(path: PathBuf) -> Result<()> {
+    let metadata = tokio::fs::metadata(path.clone()).await;
+    match metadata {
+        Err(e) => match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                tokio::fs::create_dir_all(path).await?;
+                Ok(())
+            }
+            std::io::ErrorKind::PermissionDenied => {
+                Err(e).with_context(|| "Permission denied when retrieving file metadata")
+            }
+            _ => Err(e).with_context(|| "Unable to retrieve file metadata"),
+        },
+        Ok(metadata) if metadata.is_file() => Err(anyhow!("Destination is a file")),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Whether Imgur no longer has the requested media. Removed images redirect to a placeholder.
+pub(crate) fn is_missing(response: &reqwest::Response) -> bool {
+    response.status() == reqwest::StatusCode::NOT_FOUND || response.url().path() == "/removed.png"
+}
+
+/// Requests `url` from byte `offset` onwards, trying each of `mirrors` in order if Imgur no
+/// longer has the media. Mirrors are always asked for the whole file.
+async fn get_with_fallback(
+    client: &Client,
+    url: reqwest::Url,
+    id: &str,
+    mirrors: &[String],
+    offset: u64,
+) -> Result<reqwest::Response> {
+    let mut response = if offset > 0 {
+        client
+            .get(url.clone())
+            .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+            .send()
+            .await?
+    } else {
+        client.get(url.clone()).send().await?
+    };
+    if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        response = client.get(url.clone()).send().await?;
+    }
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(RateLimited::from_headers(response.headers()).into());
+    }
+    if !is_missing(&response) {
+        return Ok(response.error_for_status()?);
+    }
+    if mirrors.is_empty() {
+        return Err(Removed.into());
+    }
+
+    for mirror in mirrors {
+        let mirror_url = mirror.replace("{url}", url.as_str()).replace("{id}", id);
+        match client.get(&mirror_url).send().await {
+            Ok(response) if response.status().is_success() && !is_missing(&response) => {
+                return Ok(response)
+            }
+            _ => continue,
+        }
+    }
+    Err(anyhow::Error::new(Removed).context("Media is missing from Imgur and all mirrors"))
+}
+
+/// The first byte of a partial response, from its `Content-Range: bytes <start>-<end>/<size>`.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.parse().ok()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn download_file(
+    client: &Client,
+    on_chunk: &(dyn Fn(u64) + Send + Sync),
+    on_resume: &(dyn Fn(u64) + Send + Sync),
+    file: &PlannedFile,
+    destination: &PathBuf,
+    temp_destination: &PathBuf,
+    overwrite: OverwritePolicy,
+    mirrors: &[String],
+    cancel: &CancellationToken,
+    pause: &PauseToken,
+    throttle: Option<&Throttle>,
+    buffer: Option<&BufferLimit>,
+) -> Result<FileOutcome> {
+    let download_url = reqwest::Url::parse(&file.url)
+        .with_context(|| format!("Failed to parse URL: {}", file.url))?;
+    let metadata = tokio::fs::metadata(destination.clone()).await;
+
+    // Exit early if destination already exists.
+    let error = match metadata {
+        Ok(metadata) if metadata.is_file() => match overwrite {
+            OverwritePolicy::Skip => return Ok(FileOutcome::Skipped),
+            OverwritePolicy::Overwrite => None,
+            OverwritePolicy::Rename | OverwritePolicy::Error => {
+                return Err(anyhow!("Found existing file"))
+            }
+        },
+        Ok(_) => return Err(anyhow!("Found existing directory")),
+        Err(error) => Some(error),
+    };
+
+    match error {
+        Some(error) if error.kind() == std::io::ErrorKind::PermissionDenied => {
+            return Err(error).with_context(|| "Permission denied when retrieving file metadata");
+        }
+        Some(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            return Err(error).with_context(|| "Unable to retrieve file metadata");
+        }
+        _ => {}
+    }
+
+    // Continue from the temporary file left by an interrupted attempt, if the server supports
+    // range requests.
+    let offset = match tokio::fs::metadata(temp_destination).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let mut res = tokio::select! {
+        res = get_with_fallback(client, download_url, &file.id, mirrors, offset) => res?,
+        _ = cancel.cancelled() => return Err(Cancelled.into()),
+    };
+    let resumed = offset > 0
+        && res.status() == reqwest::StatusCode::PARTIAL_CONTENT
+        && content_range_start(&res) == Some(offset);
+    let output = if resumed {
+        on_resume(offset);
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(temp_destination)
+            .await
+            .with_context(|| "Unable to open temporary file")?
+    } else {
+        tokio::fs::File::create(temp_destination)
+            .await
+            .with_context(|| "Unable to create temporary file")?
+    };
+    let writer = FileWriter::new(output.into_std().await, buffer.cloned());
+
+    // Download file, stopping between chunks if paused or cancelled.
+    let received = async {
+        loop {
+            tokio::select! {
+                _ = pause.resumed() => {},
+                _ = cancel.cancelled() => return Err(Cancelled.into()),
+            };
+            let chunk = tokio::select! {
+                chunk = res.chunk() => chunk?,
+                _ = cancel.cancelled() => return Err(Cancelled.into()),
+            };
+            let Some(chunk) = chunk else {
+                return Ok(());
+            };
+            let len = chunk.len() as u64;
+            on_chunk(len);
+            if !writer.write(chunk).await {
+                // The error is returned by `finish`.
+                return Ok(());
+            }
+            if let Some(throttle) = throttle {
+                tokio::select! {
+                    _ = throttle.consume(len) => {},
+                    _ = cancel.cancelled() => return Err(Cancelled.into()),
+                };
+            }
+        }
+    }
+    .await;
+    // Wait for what was received to be written even on failure, so that the temporary file is
+    // complete before it is resumed from or removed.
+    writer.finish().await.and(received)?;
+
+    // Rename file.
+    tokio::fs::rename(temp_destination, destination)
+        .await
+        .with_context(|| "Unable to move temporary file")?;
+
+    Ok(FileOutcome::Downloaded)
+}
+
+/// Sets a downloaded file's modified time to when it was uploaded.
+pub fn set_modified_time(path: &Path, datetime: i64) -> Result<()> {
+    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(datetime, 0))
+        .with_context(|| "Could not set file modified time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_media_types() {
+        assert_eq!(sniff_extension(&[0xff, 0xd8, 0xff, 0xe0]), Some("jpg"));
+        assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n\0\0"), Some("png"));
+        assert_eq!(sniff_extension(b"GIF89a"), Some("gif"));
+        assert_eq!(sniff_extension(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff_extension(b"\0\0\0\x1cftypavif"), Some("avif"));
+        assert_eq!(sniff_extension(b"\0\0\0\x18ftypmp42"), Some("mp4"));
+        assert_eq!(sniff_extension(b"not really a png"), None);
+        assert_eq!(sniff_extension(b""), None);
+    }
+
+    #[test]
+    fn renamed_paths_are_free() {
+        let dir = std::env::temp_dir().join(format!("imgurs-rename-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a (1).png"), "").unwrap();
+        assert_eq!(renamed_path(&dir.join("a.png")), dir.join("a (2).png"));
+        assert_eq!(renamed_path(&dir.join("b")), dir.join("b (1)"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn album_directory_name_stays_in_destination() {
+        assert_eq!(
+            album_directory_name("Cats: the album"),
+            PathBuf::from("Cats - the album")
+        );
+        for title in [
+            "..",
+            "../..",
+            "/",
+            "/etc",
+            "-",
+            "--output=x",
+            "a/../../b",
+            "",
+        ] {
+            let name = album_directory_name(title);
+            let components: Vec<_> = name.components().collect();
+            assert!(
+                matches!(components[..], [std::path::Component::Normal(_)]),
+                "{:?} -> {:?}",
+                title,
+                name
+            );
+            assert!(!name.to_string_lossy().starts_with('-'));
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::date::DateTime;
+
+/// Error returned when a download is cancelled.
+#[derive(Debug, Clone, Copy)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Download cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error returned when Imgur no longer has the requested media.
+#[derive(Debug, Clone, Copy)]
+pub struct Removed;
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Media has been removed")
+    }
+}
+
+impl std::error::Error for Removed {}
+
+/// Error returned when a server responds with "429 Too Many Requests".
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimited {
+    /// How long the server asked to wait before retrying.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimited {
+    /// Reads the wait from a `Retry-After` header given in seconds, or otherwise from when the
+    /// Imgur API says exhausted user or `POST` credits are reset. Reset times are taken relative
+    /// to the response's `Date` header, falling back to the system clock except on wasm32, where
+    /// it cannot be read.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> RateLimited {
+        fn header<T: std::str::FromStr>(
+            headers: &reqwest::header::HeaderMap,
+            name: &str,
+        ) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+        let exhausted = |name| header::<u64>(headers, name) == Some(0);
+        let retry_after = header(headers, reqwest::header::RETRY_AFTER.as_str())
+            .or_else(|| {
+                let reset: i64 = header(headers, "x-ratelimit-userreset")?;
+                let now = headers
+                    .get(reqwest::header::DATE)
+                    .and_then(|date| DateTime::parse_http_date(date.to_str().ok()?))
+                    .map(|date| date.to_unix())
+                    .or_else(system_time)?;
+                exhausted("x-ratelimit-userremaining").then(|| (reset - now).max(0) as u64)
+            })
+            .or_else(|| {
+                exhausted("x-post-rate-limit-remaining")
+                    .then(|| header(headers, "x-post-rate-limit-reset"))?
+            })
+            .map(Duration::from_secs);
+        RateLimited { retry_after }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn system_time() -> Option<i64> {
+    Some(crate::date::unix_now())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn system_time() -> Option<i64> {
+    None
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate limited by the server")?;
+        if let Some(retry_after) = self.retry_after {
+            write!(f, ", retry after {}s", retry_after.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// How long the server asked to wait, if `error` was caused by rate limiting.
+pub fn retry_after(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<RateLimited>())?
+        .retry_after
+}
+
+/// Broad cause of a failed download, to tell whether retrying is likely to help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorCategory {
+    Network,
+    RateLimited,
+    /// The media no longer exists on Imgur.
+    Removed,
+    Disk,
+    Permission,
+    Cancelled,
+    Other,
+}
+
+impl ErrorCategory {
+    /// Whether failures of this kind may succeed if retried later.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCategory::Network | ErrorCategory::RateLimited | ErrorCategory::Cancelled
+        )
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCategory::Network => "network",
+            ErrorCategory::RateLimited => "rate-limited",
+            ErrorCategory::Removed => "removed",
+            ErrorCategory::Disk => "disk",
+            ErrorCategory::Permission => "permission",
+            ErrorCategory::Cancelled => "cancelled",
+            ErrorCategory::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Classifies an error by the first recognised cause in its chain.
+pub fn categorize(error: &anyhow::Error) -> ErrorCategory {
+    for cause in error.chain() {
+        if cause.is::<Cancelled>() {
+            return ErrorCategory::Cancelled;
+        }
+        if cause.is::<RateLimited>() {
+            return ErrorCategory::RateLimited;
+        }
+        if cause.is::<Removed>() {
+            return ErrorCategory::Removed;
+        }
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return match error.status().map(|status| status.as_u16()) {
+                Some(429) => ErrorCategory::RateLimited,
+                Some(404 | 410) => ErrorCategory::Removed,
+                Some(status) if (400..500).contains(&status) => ErrorCategory::Other,
+                _ => ErrorCategory::Network,
+            };
+        }
+        if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+            return match error.kind() {
+                std::io::ErrorKind::PermissionDenied => ErrorCategory::Permission,
+                _ => ErrorCategory::Disk,
+            };
+        }
+    }
+    ErrorCategory::Other
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_credit_resets_relative_to_responses() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("date", "Sun, 13 Sep 2020 12:26:40 GMT".parse().unwrap());
+        headers.insert("x-ratelimit-userremaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-userreset", "1600000120".parse().unwrap());
+        assert_eq!(
+            RateLimited::from_headers(&headers).retry_after,
+            Some(Duration::from_secs(120))
+        );
+    }
+}
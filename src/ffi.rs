@@ -0,0 +1,185 @@
+//! Minimal C ABI for use from other languages.
+//!
+//! Build a shared library with `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//! Strings returned by this module must be freed with [`imgurs_string_free`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use reqwest::Client;
+use tokio_util::sync::CancellationToken;
+
+use crate::api::{fetch_album, get_album_id, ApiClient, Auth, ImgurAlbum};
+use crate::download::PauseToken;
+use crate::plan::{DownloadPlan, Event};
+
+/// Called with `(downloaded_bytes, total_bytes, completed_files, total_files, user_data)`.
+pub type ProgressCallback = extern "C" fn(u64, u64, usize, usize, *mut c_void);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: anyhow::Error) {
+    let message = CString::new(format!("{:?}", error).replace('\0', ""))
+        .expect("null bytes have been removed");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+unsafe fn to_str<'a>(ptr: *const c_char, name: &str) -> Result<&'a str> {
+    if ptr.is_null() {
+        return Err(anyhow!("{} must not be null", name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| anyhow!("{} must be valid UTF-8", name))
+}
+
+fn fetch(
+    runtime: &tokio::runtime::Runtime,
+    client_id: &str,
+    album_id: &str,
+) -> Result<(Client, ImgurAlbum)> {
+    let api = ApiClient::builder().build()?;
+    let auth = Auth::ClientId(client_id.to_owned());
+    let response = runtime.block_on(fetch_album(&api, &auth, get_album_id(album_id)))?;
+    let album = response.data.ok_or_else(|| {
+        anyhow!(
+            "Failed to get album details with status code: {}",
+            response.status
+        )
+    })?;
+    Ok((api.http().clone(), album))
+}
+
+/// Returns the message of the last error on this thread, or null if there was none.
+///
+/// The returned string is owned by the library and valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn imgurs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Fetches an album's details as a JSON string, or returns null on error.
+///
+/// # Safety
+///
+/// `client_id` and `album_id` must be valid null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn imgurs_fetch_album_json(
+    client_id: *const c_char,
+    album_id: *const c_char,
+) -> *mut c_char {
+    let result = (|| {
+        let client_id = to_str(client_id, "client_id")?;
+        let album_id = to_str(album_id, "album_id")?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (_, album) = fetch(&runtime, client_id, album_id)?;
+        let json = serde_json::to_string(&album)?;
+        Ok(CString::new(json)?)
+    })();
+
+    match result {
+        Ok(json) => json.into_raw(),
+        Err(error) => {
+            set_last_error(error);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Downloads an album into the `destination` directory.
+///
+/// Returns the number of files which failed to download, or -1 if the album could not be
+/// downloaded at all. `progress` may be null; `user_data` is passed through to it unchanged.
+///
+/// # Safety
+///
+/// `client_id`, `album_id` and `destination` must be valid null-terminated strings. `progress`
+/// may be called from multiple threads.
+#[no_mangle]
+pub unsafe extern "C" fn imgurs_download_album(
+    client_id: *const c_char,
+    album_id: *const c_char,
+    destination: *const c_char,
+    parallelism: usize,
+    progress: Option<ProgressCallback>,
+    user_data: *mut c_void,
+) -> i64 {
+    struct UserData(*mut c_void);
+    unsafe impl Send for UserData {}
+    unsafe impl Sync for UserData {}
+    let user_data = UserData(user_data);
+
+    let result = (|| {
+        let client_id = to_str(client_id, "client_id")?;
+        let album_id = to_str(album_id, "album_id")?;
+        let destination = PathBuf::from(to_str(destination, "destination")?);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (client, album) = fetch(&runtime, client_id, album_id)?;
+        if album.images.is_empty() {
+            return Ok(0);
+        }
+        let plan = DownloadPlan::builder(&album)
+            .destination(destination)
+            .parallelism(parallelism.max(1))
+            .build()?;
+
+        let m = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let cancel = CancellationToken::new();
+        let execution = runtime.block_on(plan.execute_with_events(
+            &client,
+            &m,
+            &cancel,
+            &CancellationToken::new(),
+            &PauseToken::default(),
+            |event| {
+                if let (Some(progress), Event::Progress(p)) = (progress, event) {
+                    let user_data = &user_data;
+                    progress(
+                        p.downloaded_bytes,
+                        p.total_bytes,
+                        p.completed_files,
+                        p.total_files,
+                        user_data.0,
+                    );
+                }
+            },
+        ))?;
+
+        Ok(execution.errors.len() as i64)
+    })();
+
+    match result {
+        Ok(failed) => failed,
+        Err(error) => {
+            set_last_error(error);
+            -1
+        }
+    }
+}
+
+/// Frees a string returned by this library.
+///
+/// # Safety
+///
+/// `ptr` must be null or a string returned by this library which has not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn imgurs_string_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        drop(CString::from_raw(ptr));
+    }
+}
@@ -0,0 +1,215 @@
+//! Downloads of gallery listings, such as the front page, the posts of a subreddit or tag, or
+//! search results.
+
+use std::collections::HashSet;
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+
+use imgurs::api::{
+    fetch_gallery, fetch_gallery_search, fetch_subreddit_gallery, fetch_tag_gallery, parse_link,
+    ApiClient, Auth, GallerySection, GallerySort, ImgurGalleryItem, ImgurLink, ImgurResponse, Page,
+    SubredditSort, TimeWindow,
+};
+
+/// Number of posts downloaded from a tag by default, about a page of the tag on Imgur.
+pub const DEFAULT_TAG_LIMIT: usize = 60;
+
+/// Returns the subreddit named by `input`, which is either its name, `r/<name>` or a URL such
+/// as `https://imgur.com/r/<name>` or `https://www.reddit.com/r/<name>`.
+pub fn subreddit_name(input: &str) -> Result<&str> {
+    let input = input.trim();
+    let name = match parse_link(input) {
+        Some(ImgurLink::Subreddit(name)) => name,
+        _ => {
+            let path = ["https://", "http://"]
+                .into_iter()
+                .find_map(|scheme| input.strip_prefix(scheme))
+                .and_then(|rest| {
+                    let (host, path) = rest.split_once('/')?;
+                    (host == "reddit.com" || host.ends_with(".reddit.com")).then_some(path)
+                })
+                .unwrap_or(input);
+            let path = path.trim_start_matches('/');
+            let path = path.strip_prefix("r/").unwrap_or(path);
+            path.split(['/', '?', '#']).next().unwrap_or_default()
+        }
+    };
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid
+        .then_some(name)
+        .ok_or_else(|| anyhow!("\"{}\" is not a subreddit name or URL", input))
+}
+
+/// Returns the tag named by `input`, which is either the tag or a URL such as
+/// `https://imgur.com/t/<tag>`.
+pub fn tag_name(input: &str) -> Result<&str> {
+    let input = input.trim();
+    let tag = match parse_link(input) {
+        Some(ImgurLink::Tag(tag)) => tag,
+        _ if !input.contains('/') => input,
+        _ => return Err(anyhow!("\"{}\" is not a tag or tag URL", input)),
+    };
+    (!tag.is_empty())
+        .then_some(tag)
+        .ok_or_else(|| anyhow!("The tag must not be empty"))
+}
+
+/// Which posts of a listing are downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    /// Number of pages fetched at most.
+    pub pages: u32,
+    /// Number of posts downloaded at most.
+    pub limit: usize,
+    /// Only posts marked NSFW if `true`, and only posts not marked NSFW if `false`.
+    pub nsfw: Option<bool>,
+}
+
+impl Selection {
+    fn includes(&self, item: &ImgurGalleryItem) -> bool {
+        self.nsfw
+            .is_none_or(|nsfw| item.nsfw.unwrap_or(false) == nsfw)
+    }
+}
+
+impl Default for Selection {
+    /// The first page of posts.
+    fn default() -> Selection {
+        Selection {
+            pages: 1,
+            limit: usize::MAX,
+            nsfw: None,
+        }
+    }
+}
+
+/// Fetches the posts of a gallery listing of `what` chosen by `selection` with `fetch`, stopping
+/// at the first empty page, as gallery pages may be short before the end. Posts which move
+/// between pages while fetching are only returned once.
+pub async fn posts<F, Fut>(
+    what: &str,
+    selection: Selection,
+    mut fetch: F,
+) -> Result<Vec<ImgurGalleryItem>>
+where
+    F: FnMut(Page) -> Fut,
+    Fut: Future<Output = Result<ImgurResponse<Vec<ImgurGalleryItem>>>>,
+{
+    let mut seen = HashSet::new();
+    let mut posts = vec![];
+    let mut page = Page::default();
+    for _ in 0..selection.pages {
+        if posts.len() >= selection.limit {
+            break;
+        }
+        let response = fetch(page).await?;
+        let items = response.data.ok_or_else(|| {
+            anyhow!(
+                "Failed to list {} with status code: {}",
+                what,
+                response.status
+            )
+        })?;
+        if items.is_empty() {
+            break;
+        }
+        posts.extend(
+            items
+                .into_iter()
+                .filter(|item| selection.includes(item) && seen.insert(item.id.clone())),
+        );
+        page = page.next();
+    }
+    posts.truncate(selection.limit);
+    Ok(posts)
+}
+
+/// Fetches the posts of a subreddit's gallery chosen by `selection`.
+pub async fn subreddit_posts(
+    api: &ApiClient,
+    auth: &Auth,
+    name: &str,
+    sort: SubredditSort,
+    window: TimeWindow,
+    selection: Selection,
+) -> Result<Vec<ImgurGalleryItem>> {
+    posts(&format!("posts of r/{}", name), selection, |page| {
+        fetch_subreddit_gallery(api, auth, name, sort, window, page)
+    })
+    .await
+}
+
+/// Fetches the posts with a tag chosen by `selection`.
+pub async fn tag_posts(
+    api: &ApiClient,
+    auth: &Auth,
+    tag: &str,
+    sort: GallerySort,
+    window: TimeWindow,
+    selection: Selection,
+) -> Result<Vec<ImgurGalleryItem>> {
+    posts(&format!("posts tagged {}", tag), selection, |page| {
+        fetch_tag_gallery(api, auth, tag, sort, window, page)
+    })
+    .await
+}
+
+/// Fetches the posts of a section of the gallery chosen by `selection`.
+pub async fn section_posts(
+    api: &ApiClient,
+    auth: &Auth,
+    section: GallerySection,
+    sort: GallerySort,
+    window: TimeWindow,
+    selection: Selection,
+) -> Result<Vec<ImgurGalleryItem>> {
+    let what = format!("posts of the {} section", section.as_str());
+    posts(&what, selection, |page| {
+        fetch_gallery(api, auth, section, sort, window, page)
+    })
+    .await
+}
+
+/// Fetches the posts matching a search `query` chosen by `selection`.
+pub async fn search(
+    api: &ApiClient,
+    auth: &Auth,
+    query: &str,
+    sort: GallerySort,
+    window: TimeWindow,
+    selection: Selection,
+) -> Result<Vec<ImgurGalleryItem>> {
+    posts(&format!("results for \"{}\"", query), selection, |page| {
+        fetch_gallery_search(api, auth, query, sort, window, page)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_subreddit_names() {
+        assert_eq!(subreddit_name("aww").unwrap(), "aww");
+        assert_eq!(subreddit_name("/r/aww").unwrap(), "aww");
+        assert_eq!(
+            subreddit_name("https://imgur.com/r/aww/top").unwrap(),
+            "aww"
+        );
+        assert_eq!(
+            subreddit_name("https://old.reddit.com/r/earth_porn/?sort=top").unwrap(),
+            "earth_porn"
+        );
+        assert!(subreddit_name("https://example.com/r/aww").is_err());
+        assert!(subreddit_name("").is_err());
+    }
+
+    #[test]
+    fn parses_tags() {
+        assert_eq!(tag_name("cats").unwrap(), "cats");
+        assert_eq!(tag_name("https://imgur.com/t/cats").unwrap(), "cats");
+        assert!(tag_name("https://imgur.com/a/cats").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Registers imgurs as the handler for `imgurs://` URLs, which are queued on a running
+//! `imgurs serve`.
+//!
+//! `imgurs://imgur.com/a/abc` and `imgurs:https://imgur.com/a/abc` both queue
+//! `https://imgur.com/a/abc`.
+
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+
+const SCHEME: &str = "imgurs";
+
+/// Converts an `imgurs://` URL to the album URL it refers to.
+pub fn album_url(url: &str) -> Result<String> {
+    let rest = url
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .ok_or_else(|| anyhow!("Not an {}:// URL: {}", SCHEME, url))?;
+    if rest.starts_with("http://") || rest.starts_with("https://") {
+        Ok(rest.to_owned())
+    } else {
+        Ok(format!("https://{}", rest.trim_start_matches('/')))
+    }
+}
+
+/// Queues the album referred to by an `imgurs://` URL on the server at `server`.
+pub async fn handle_url(client: &Client, server: &str, url: &str) -> Result<()> {
+    let album_url = album_url(url)?;
+    let response = client
+        .post(server)
+        .header("Content-Type", "text/plain")
+        .body(album_url)
+        .send()
+        .await
+        .with_context(|| format!("Unable to reach imgurs server at {}", server))?;
+    let status = response.status();
+    let message = response.text().await?;
+    if !status.is_success() {
+        return Err(anyhow!("Server rejected URL: {}", message.trim()));
+    }
+    print!("{}", message);
+    Ok(())
+}
+
+/// Registers the current executable as the handler for `imgurs://` URLs.
+pub fn register(server: &str) -> Result<()> {
+    let exe = std::env::current_exe().with_context(|| "Unable to locate imgurs executable")?;
+    let exe = exe
+        .to_str()
+        .ok_or_else(|| anyhow!("Executable path is not valid UTF-8"))?;
+
+    if cfg!(windows) {
+        register_windows(exe, server)
+    } else if cfg!(target_os = "macos") {
+        Err(anyhow!(
+            "Registering URL handlers is not supported on macOS, as it requires an app bundle"
+        ))
+    } else {
+        register_xdg(exe, server)
+    }
+}
+
+fn register_xdg(exe: &str, server: &str) -> Result<()> {
+    let applications = crate::paths::data_home()
+        .ok_or_else(|| anyhow!("Unable to determine data directory"))?
+        .join("applications");
+    std::fs::create_dir_all(&applications)?;
+
+    let desktop_file = format!("{}-handler.desktop", SCHEME);
+    let path = applications.join(&desktop_file);
+    std::fs::write(
+        &path,
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=imgurs\n\
+             Exec=\"{}\" handle-url --server \"{}\" %u\n\
+             NoDisplay=true\n\
+             MimeType=x-scheme-handler/{};\n",
+            exe.replace('\\', "\\\\").replace('"', "\\\""),
+            server,
+            SCHEME
+        ),
+    )
+    .with_context(|| format!("Unable to write {}", path.display()))?;
+
+    run(Command::new("xdg-mime").args([
+        "default",
+        &desktop_file,
+        &format!("x-scheme-handler/{}", SCHEME),
+    ]))?;
+    // Not all desktops need the database to be updated, so ignore failures.
+    let _success = run(Command::new("update-desktop-database").arg(&applications)).is_ok();
+
+    println!("Registered {}:// handler in {}", SCHEME, path.display());
+    Ok(())
+}
+
+fn register_windows(exe: &str, server: &str) -> Result<()> {
+    let key = format!("HKCU\\Software\\Classes\\{}", SCHEME);
+    let command = format!("\"{}\" handle-url --server \"{}\" \"%1\"", exe, server);
+    run(Command::new("reg").args([
+        "add",
+        &key,
+        "/ve",
+        "/d",
+        &format!("URL:{} Protocol", SCHEME),
+        "/f",
+    ]))?;
+    run(Command::new("reg").args(["add", &key, "/v", "URL Protocol", "/d", "", "/f"]))?;
+    run(Command::new("reg").args([
+        "add",
+        &format!("{}\\shell\\open\\command", key),
+        "/ve",
+        "/d",
+        &command,
+        "/f",
+    ]))?;
+
+    println!("Registered {}:// handler in the registry", SCHEME);
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .with_context(|| format!("Unable to run {}", program))?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
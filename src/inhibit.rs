@@ -0,0 +1,92 @@
+//! `--inhibit-sleep`: keeps the system awake while files are being downloaded.
+
+use anyhow::{Context, Result};
+
+/// Prevents the system from sleeping until dropped.
+pub struct SleepInhibitor {
+    #[cfg(unix)]
+    child: std::process::Child,
+    #[cfg(windows)]
+    release: std::sync::mpsc::Sender<()>,
+}
+
+impl SleepInhibitor {
+    /// Takes a logind inhibitor lock through `systemd-inhibit`, which is released once its
+    /// standard input is closed.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    pub fn acquire() -> Result<SleepInhibitor> {
+        let child = std::process::Command::new("systemd-inhibit")
+            .args([
+                "--what=sleep:idle",
+                "--who=imgurs",
+                "--why=Downloading albums",
+                "--mode=block",
+                "cat",
+            ])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .with_context(|| "Unable to run systemd-inhibit")?;
+        Ok(SleepInhibitor { child })
+    }
+
+    /// Runs `caffeinate` until this process exits or the inhibitor is dropped.
+    #[cfg(target_os = "macos")]
+    pub fn acquire() -> Result<SleepInhibitor> {
+        let child = std::process::Command::new("caffeinate")
+            .args(["-i", "-w", &std::process::id().to_string()])
+            .spawn()
+            .with_context(|| "Unable to run caffeinate")?;
+        Ok(SleepInhibitor { child })
+    }
+
+    /// Execution states are per-thread, so they are set from a dedicated thread which lives as
+    /// long as the inhibitor.
+    #[cfg(windows)]
+    pub fn acquire() -> Result<SleepInhibitor> {
+        const ES_CONTINUOUS: u32 = 0x80000000;
+        const ES_SYSTEM_REQUIRED: u32 = 0x00000001;
+
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn SetThreadExecutionState(flags: u32) -> u32;
+        }
+
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let (acquired, result) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name("inhibit-sleep".to_owned())
+            .spawn(move || {
+                // SAFETY: only changes the execution state of this thread.
+                let previous =
+                    unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                let _ = acquired.send(previous != 0);
+                let _ = released.recv();
+                // SAFETY: as above.
+                unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+            })
+            .with_context(|| "Unable to start thread")?;
+        if !result.recv().unwrap_or(false) {
+            return Err(anyhow::anyhow!("SetThreadExecutionState failed"));
+        }
+        Ok(SleepInhibitor { release })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn acquire() -> Result<SleepInhibitor> {
+        Err(anyhow::anyhow!("Not supported on this platform"))
+    }
+}
+
+impl Drop for SleepInhibitor {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            drop(self.child.stdin.take());
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+        #[cfg(windows)]
+        let _ = self.release.send(());
+    }
+}
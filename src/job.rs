@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use indicatif::MultiProgress;
+
+use imgurs::download::{Parallelism, PauseToken};
+use imgurs::plan::Progress;
+use imgurs::CancellationToken;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::audit::AuditLog;
+
+/// Where to download an album to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Destination {
+    /// A directory named after the album's title, in the current directory.
+    Named,
+    /// A directory named after the album's title, inside the given directory.
+    Under(PathBuf),
+    /// Exactly the given directory.
+    Exact(PathBuf),
+}
+
+/// An album waiting to be downloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedAlbum {
+    /// Album ID or URL.
+    pub input: String,
+    pub destination: Destination,
+}
+
+/// Parses a list of albums, one ID or URL per line. Blank lines and lines starting with `#` are
+/// ignored.
+pub fn parse_album_list(list: &str) -> Vec<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Reads a list of albums with [`parse_album_list`].
+pub fn read_album_list(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    Ok(parse_album_list(&contents))
+}
+
+/// Loads albums saved by [`save_albums`].
+pub fn load_albums(path: &Path) -> Result<Vec<QueuedAlbum>> {
+    let contents = std::fs::read(path)
+        .with_context(|| format!("Unable to read job file {}", path.display()))?;
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("Unable to parse job file {}", path.display()))
+}
+
+/// Saves albums which have not been downloaded yet, so that the job can be resumed.
+pub fn save_albums(path: &Path, albums: &[QueuedAlbum]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_vec_pretty(albums)?)?;
+    std::fs::rename(&temp_path, path).with_context(|| "Unable to save job file")?;
+    Ok(())
+}
+
+/// Progress of the album currently being downloaded.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub album: Option<String>,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    /// Bytes received from the network for the album, including retried attempts.
+    pub received_bytes: u64,
+    pub completed_files: usize,
+    pub total_files: usize,
+    pub completed_albums: usize,
+    /// Smoothed download speed in bytes per second.
+    pub bytes_per_sec: Option<f64>,
+    pub eta: Option<Duration>,
+}
+
+/// State of a running job, shared between the downloader and anything controlling it.
+#[derive(Debug, Default)]
+pub struct Job {
+    pub cancel: CancellationToken,
+    /// Stops the job once in-flight files are downloaded, leaving the rest queued.
+    pub stop: CancellationToken,
+    pub pause: PauseToken,
+    /// Number of files downloaded in parallel, shared by the plans of all albums.
+    pub parallelism: Parallelism,
+    pub audit_log: Option<AuditLog>,
+    /// Progress bars of the files being downloaded, which messages are printed above.
+    pub progress: MultiProgress,
+    queue: Mutex<VecDeque<QueuedAlbum>>,
+    status: Mutex<Status>,
+    /// Total bytes and files of the albums planned so far.
+    planned: Mutex<(u64, usize)>,
+    /// Bytes received for albums before the current one.
+    received_bytes: Mutex<u64>,
+}
+
+impl Job {
+    pub fn new(albums: impl IntoIterator<Item = QueuedAlbum>, audit_log: Option<AuditLog>) -> Job {
+        Job {
+            audit_log,
+            queue: Mutex::new(albums.into_iter().collect()),
+            ..Job::default()
+        }
+    }
+
+    /// Prints a message without tearing the progress bars.
+    pub fn log(&self, message: impl std::fmt::Display) {
+        self.progress.suspend(|| println!("{}", message));
+    }
+
+    /// Records an action in the audit log, if there is one.
+    pub fn audit(&self, action: &str, fields: Value) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(action, fields);
+        }
+    }
+
+    pub fn enqueue(&self, album: QueuedAlbum) {
+        self.queue.lock().unwrap().push_back(album);
+    }
+
+    /// Puts an album back at the front of the queue.
+    pub fn requeue(&self, album: QueuedAlbum) {
+        self.queue.lock().unwrap().push_front(album);
+    }
+
+    /// Returns the next album to download, unless the job has been cancelled or stopped.
+    pub fn next_album(&self) -> Option<QueuedAlbum> {
+        if self.cancel.is_cancelled() || self.stop.is_cancelled() {
+            return None;
+        }
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    /// Returns the album which [`Job::next_album`] would return next, leaving it queued.
+    pub fn next_queued(&self) -> Option<QueuedAlbum> {
+        self.queue.lock().unwrap().front().cloned()
+    }
+
+    pub fn queued(&self) -> Vec<String> {
+        let queue = self.queue.lock().unwrap();
+        queue.iter().map(|album| album.input.clone()).collect()
+    }
+
+    pub fn queued_albums(&self) -> Vec<QueuedAlbum> {
+        self.queue.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Returns the total bytes and files of the albums planned so far.
+    pub fn planned(&self) -> (u64, usize) {
+        *self.planned.lock().unwrap()
+    }
+
+    pub fn add_planned(&self, bytes: u64, files: usize) {
+        let mut planned = self.planned.lock().unwrap();
+        planned.0 += bytes;
+        planned.1 += files;
+    }
+
+    /// Returns the bytes received from the network so far, for bandwidth accounting.
+    pub fn received_bytes(&self) -> u64 {
+        let status = self.status.lock().unwrap();
+        *self.received_bytes.lock().unwrap() + status.received_bytes
+    }
+
+    pub fn start_album(&self, album: &str) {
+        let mut status = self.status.lock().unwrap();
+        // Keep what an album which failed part way through received.
+        *self.received_bytes.lock().unwrap() += status.received_bytes;
+        *status = Status {
+            album: Some(album.to_owned()),
+            completed_albums: status.completed_albums,
+            ..Status::default()
+        };
+    }
+
+    pub fn update_progress(&self, progress: Progress) {
+        let mut status = self.status.lock().unwrap();
+        status.downloaded_bytes = progress.downloaded_bytes;
+        status.total_bytes = progress.total_bytes;
+        status.received_bytes = progress.received_bytes;
+        status.completed_files = progress.completed_files;
+        status.total_files = progress.total_files;
+        status.bytes_per_sec = progress.bytes_per_sec;
+        status.eta = progress.eta;
+    }
+
+    pub fn finish_album(&self) {
+        let mut status = self.status.lock().unwrap();
+        *self.received_bytes.lock().unwrap() += std::mem::take(&mut status.received_bytes);
+        status.album = None;
+        status.completed_albums += 1;
+    }
+}
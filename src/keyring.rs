@@ -0,0 +1,44 @@
+//! Stores secrets in the OS keyring: the Secret Service on Linux and the BSDs, the login keychain
+//! on macOS, or the Credential Manager on Windows.
+
+use anyhow::{Context, Result};
+use keyring::{Entry, Error};
+
+/// Service name which secrets are stored under.
+const SERVICE: &str = "imgurs";
+
+/// Whether there is a keyring implementation for this platform. Without one, secrets are stored
+/// in plaintext files, as with `--no-keyring`.
+pub const SUPPORTED: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "macos",
+    windows
+));
+
+fn entry(key: &str) -> Result<Entry> {
+    Entry::new(SERVICE, key).context("Unable to open the keyring")
+}
+
+/// Returns the secret stored as `key`, if any.
+pub fn get(key: &str) -> Result<Option<String>> {
+    match entry(key)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Stores `secret` as `key`, replacing any secret already stored as it.
+pub fn set(key: &str, secret: &str) -> Result<()> {
+    Ok(entry(key)?.set_password(secret)?)
+}
+
+/// Removes the secret stored as `key`, if any.
+pub fn delete(key: &str) -> Result<()> {
+    match entry(key)?.delete_credential() {
+        Ok(()) | Err(Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
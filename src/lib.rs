@@ -0,0 +1,27 @@
+//! Fetches Imgur album details and downloads album media.
+
+pub mod api;
+#[cfg(feature = "download")]
+pub mod breaker;
+pub mod date;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod naming;
+#[cfg(feature = "download")]
+pub mod plan;
+#[cfg(feature = "download")]
+pub mod speed;
+#[cfg(feature = "download")]
+pub mod state;
+#[cfg(feature = "download")]
+pub mod throttle;
+#[cfg(feature = "download")]
+pub mod torrent;
+#[cfg(feature = "download")]
+pub mod writer;
+
+#[cfg(feature = "download")]
+pub use tokio_util::sync::CancellationToken;
@@ -0,0 +1,98 @@
+//! Upload links formatted for pasting into forums, GitHub or Reddit.
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LinkFormat {
+    Plain,
+    Markdown,
+    Bbcode,
+    Html,
+}
+
+/// Formats an embedded image. With a thumbnail, the thumbnail is embedded instead and links to
+/// the full image.
+pub fn image(format: LinkFormat, link: &str, alt: &str, thumbnail: Option<&str>) -> String {
+    match (format, thumbnail) {
+        (LinkFormat::Plain, _) => link.to_owned(),
+        (LinkFormat::Markdown, None) => format!("![{}]({})", markdown_text(alt), link),
+        (LinkFormat::Markdown, Some(thumbnail)) => {
+            format!("[![{}]({})]({})", markdown_text(alt), thumbnail, link)
+        }
+        (LinkFormat::Bbcode, None) => format!("[img]{}[/img]", link),
+        (LinkFormat::Bbcode, Some(thumbnail)) => {
+            format!("[url={}][img]{}[/img][/url]", link, thumbnail)
+        }
+        (LinkFormat::Html, None) => {
+            format!(
+                r#"<img src="{}" alt="{}">"#,
+                html_escape(link),
+                html_escape(alt)
+            )
+        }
+        (LinkFormat::Html, Some(thumbnail)) => format!(
+            r#"<a href="{}"><img src="{}" alt="{}"></a>"#,
+            html_escape(link),
+            html_escape(thumbnail),
+            html_escape(alt)
+        ),
+    }
+}
+
+/// Formats a text link, such as to an album.
+pub fn link(format: LinkFormat, link: &str, text: &str) -> String {
+    match format {
+        LinkFormat::Plain => link.to_owned(),
+        LinkFormat::Markdown => format!("[{}]({})", markdown_text(text), link),
+        LinkFormat::Bbcode => format!(
+            "[url={}]{}[/url]",
+            link,
+            text.replace('[', "(").replace(']', ")")
+        ),
+        LinkFormat::Html => format!(
+            r#"<a href="{}">{}</a>"#,
+            html_escape(link),
+            html_escape(text)
+        ),
+    }
+}
+
+fn markdown_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(if c == '\n' { ' ' } else { c });
+    }
+    escaped
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_are_escaped() {
+        assert_eq!(
+            image(
+                LinkFormat::Markdown,
+                "https://i.imgur.com/a.png",
+                "[x]",
+                Some("https://i.imgur.com/am.jpg")
+            ),
+            r"[![\[x\]](https://i.imgur.com/am.jpg)](https://i.imgur.com/a.png)"
+        );
+        assert_eq!(
+            link(LinkFormat::Html, "https://imgur.com/a/b", "<\"cats\">"),
+            r#"<a href="https://imgur.com/a/b">&lt;&quot;cats&quot;&gt;</a>"#
+        );
+    }
+}
@@ -1,280 +1,2732 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
-use clap::{ArgGroup, Parser};
-use futures_util::{stream, StreamExt};
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use humansize::{format_size, DECIMAL};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::Client;
-use serde::Deserialize;
-use tokio::io::AsyncWriteExt;
+use serde_json::json;
+
+use imgurs::api::{
+    fetch_album, fetch_credits, fetch_gallery_post, fetch_image, fetch_oauth_tokens, get_album_id,
+    get_media_type, is_imgur_host, parse_link, thumbnail_link, ApiClient, Auth, GallerySection,
+    GallerySort, ImgurAlbum, ImgurGalleryItem, ImgurLink, ImgurResponse, OAuthGrant, RateLimits,
+    SubredditSort, ThumbnailSize, TimeWindow, DEFAULT_BASE_URL,
+};
+use imgurs::date::{format_duration, local_offset, unix_now, DateTime};
+use imgurs::download::{album_directory_name, FileOutcome, OverwritePolicy, PartialPolicy};
+use imgurs::error::{categorize, retry_after, Cancelled};
+use imgurs::naming::{
+    glob_matches, render_directory, safe_filename, Template, BY_UPLOADER_DIRECTORY_TEMPLATE,
+    POST_DIRECTORY_TEMPLATE,
+};
+use imgurs::plan::{DownloadPlan, Event, Execution};
+use imgurs::state::{Bandwidth, DedupPolicy, RunRecord, StateDb};
+use imgurs::torrent;
+
+mod accounts;
+mod archive;
+mod audit;
+mod cache;
+mod config;
+#[cfg(unix)]
+mod control;
+mod doctor;
+mod gallery;
+mod handler;
+mod inhibit;
+mod job;
+mod keyring;
+mod links;
+mod my;
+mod oauth;
+mod paths;
+mod pipeline;
+mod priority;
+mod proxy;
+mod schedule;
+mod serve;
+mod unshorten;
+mod upload;
+mod user;
+mod vault;
+mod wayback;
+
+use accounts::{Account, Accounts};
+use audit::AuditLog;
+use config::{Config, Defaults};
+use inhibit::SleepInhibitor;
+use job::{Destination, Job, QueuedAlbum};
+use links::LinkFormat;
+use proxy::ProxyOption;
+use schedule::ScheduleWindow;
+use vault::{Upload, UploadKind, Vault};
 
 #[derive(Parser)]
 #[command(group(
             ArgGroup::new("op")
                 .args(["output", "details"]),
 ))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// ID of album to download.
-    album_id: String,
-    /// Output directory. Album will be downloaded to "$output/$album_name".
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// IDs or URLs of albums to download, or the URLs of gallery posts. "-" reads them from
+    /// standard input, like --stdin.
+    #[arg(required_unless_present_any = ["resume", "input_file", "stdin"])]
+    album_ids: Vec<String>,
+    /// Also downloads the albums listed in this file, one ID or URL per line, in order. Blank
+    /// lines and lines starting with "#" are ignored.
+    #[arg(long, conflicts_with = "resume")]
+    input_file: Option<PathBuf>,
+    /// Also downloads the albums listed on standard input, like --input-file.
+    #[arg(long, conflicts_with = "resume")]
+    stdin: bool,
+    /// Output directory. A single album is downloaded into it directly, several albums into
+    /// subdirectories named after them.
+    #[arg(short, long, global = true)]
     output: Option<PathBuf>,
+    /// Answers --details from cached album metadata and the state database, without network
+    /// access.
+    #[arg(long, requires = "details", conflicts_with_all = ["probe", "min_credits"])]
+    offline: bool,
+    /// Directory where fetched album metadata is cached for --offline. Default:
+    /// "$XDG_DATA_HOME/imgurs/albums"
+    #[arg(long, global = true)]
+    metadata_cache: Option<PathBuf>,
+    /// Submits each album's URL to the Wayback Machine's Save Page Now API while downloading,
+    /// authenticated with $IA_ACCESS_KEY and $IA_SECRET_KEY if set.
+    #[arg(long, global = true)]
+    save_to_wayback: bool,
+    /// With --save-to-wayback, also submits each image's URL.
+    #[arg(long, global = true, requires = "save_to_wayback")]
+    save_images_to_wayback: bool,
+    /// Mirror to download files from when Imgur no longer has them, as a URL in which {url} is
+    /// replaced with the Imgur URL and {id} with the media ID, e.g.
+    /// "https://web.archive.org/web/2id_/{url}". Can be given multiple times; tried in order.
+    #[arg(long, global = true)]
+    mirror: Vec<String>,
+    /// Checks that each file is available with a HEAD request before downloading, using the sizes
+    /// reported by the server. Unavailable files are skipped.
+    #[arg(long, global = true)]
+    probe: bool,
     /// Prints the album's details without downloading.
     #[arg(short, long)]
     details: bool,
+    /// With --details, prints the album's metadata, including each image, as JSON.
+    #[arg(long, requires = "details")]
+    json: bool,
+    /// With --details, also lists each image's index, ID, title, type, size, resolution and
+    /// upload date.
+    #[arg(long, requires = "details", conflicts_with = "json")]
+    list: bool,
+    /// Template for album directory names, in which "/" separates nested directories, e.g.
+    /// "{album_date:%Y/%m}/{title}". Placeholders: {id}, {title}, {uploader}, {tags} (of
+    /// gallery posts) and {album_date}, which takes a strftime format.
+    #[arg(long, global = true, value_parser = parse_directory_template)]
+    directory_template: Option<Template>,
+    /// Template for file names, e.g. "{date:%Y%m%d}_{index}.{ext}". Placeholders: {index},
+    /// {id}, {title}, {description}, {tags}, {album_id}, {ext} and {date}, the upload date, which
+    /// takes a strftime format. Text in [...] is dropped if a placeholder in it has no value.
+    #[arg(long, global = true, value_parser = parse_filename_template)]
+    filename_template: Option<String>,
+    /// Preset album directory layout. Ignored with --directory-template.
+    #[arg(long, global = true, value_enum, default_value_t = Layout::Flat)]
+    layout: Layout,
+    /// What to do with albums which have no images, which are often deleted.
+    #[arg(long, global = true, value_enum, default_value_t = EmptyAlbum::Warn)]
+    empty_album: EmptyAlbum,
     /// Number of files to download in parallel.
-    #[arg(short, long, default_value_t = 8)]
+    #[arg(short, long, default_value_t = 8, global = true)]
     parallelism: usize,
-    /// Imgur client ID for accessing the API. Default: $IMGUR_CLIENT_ID
-    #[arg(short, long)]
+    /// Base URL of the Imgur API, e.g. of a mock server or caching proxy.
+    #[arg(long, global = true, hide = true, default_value = DEFAULT_BASE_URL)]
+    api_base_url: String,
+    /// Sends requests through this proxy: a URL, "system" for the system's proxy settings or
+    /// "none". Default: the system settings on macOS and Windows, and $HTTPS_PROXY and the like
+    #[arg(long, global = true)]
+    proxy: Option<ProxyOption>,
+    /// Imgur client ID for accessing the API. Default: $IMGUR_CLIENT_ID, or the client ID stored
+    /// in the OS keyring by `account login`
+    #[arg(short, long, global = true)]
     imgur_client_id: Option<String>,
+    /// OAuth access token for accessing the API as a user. Default: $IMGUR_ACCESS_TOKEN, or the
+    /// token of the selected account
+    #[arg(long, global = true)]
+    access_token: Option<String>,
+    /// Stored account to access the API as. Default: the configured account, or the default
+    /// account, if any
+    #[arg(long, global = true)]
+    account: Option<String>,
+    /// File where accounts are stored. Default: "$XDG_DATA_HOME/imgurs/accounts.json"
+    #[arg(long, global = true)]
+    accounts_file: Option<PathBuf>,
+    /// Stores new account tokens in the accounts file rather than the OS keyring, e.g. on
+    /// headless machines, and does not read the client ID and secret from it.
+    #[arg(long, global = true)]
+    no_keyring: bool,
+    /// Encrypted file where anonymous uploads are recorded. Default:
+    /// "$XDG_DATA_HOME/imgurs/uploads.vault"
+    #[arg(long, global = true)]
+    vault_file: Option<PathBuf>,
+    /// Uses a single-threaded async runtime, for constrained environments.
+    #[arg(long, global = true)]
+    single_threaded: bool,
+    /// Prevents the system from sleeping while files are being downloaded.
+    #[arg(long, global = true)]
+    inhibit_sleep: bool,
+    /// Runs at a low CPU and I/O priority, so downloads don't slow down other programs.
+    #[arg(long, global = true)]
+    nice: bool,
+    /// Unix socket accepting JSON-RPC requests to control the running job: status, pause,
+    /// resume, set-parallelism, add-url and cancel. Added albums are downloaded to directories named after them.
+    #[arg(long)]
+    control_socket: Option<PathBuf>,
+    /// Once an album is fully downloaded, writes a SHA256SUMS file into its directory and a
+    /// torrent of the directory next to it.
+    #[arg(long, global = true)]
+    make_torrent: bool,
+    /// Tracker to announce the torrent to. Can be given multiple times; without one, the torrent
+    /// is trackerless.
+    #[arg(long, global = true, requires = "make_torrent")]
+    torrent_tracker: Vec<String>,
+    /// What to do with media already downloaded to another album, according to the state
+    /// database. By default, such media is downloaded again.
+    #[arg(long, global = true)]
+    dedup: Option<DedupPolicy>,
+    /// How many times to retry a file after network errors or server errors, waiting 1s, 2s,
+    /// 4s, ... in between.
+    #[arg(long, global = true, default_value_t = 3)]
+    retries: usize,
+    /// How many times to retry an album or file after being rate limited, waiting as long as
+    /// the server asks or until its credits reset. These do not count towards --retries.
+    #[arg(long = "retries-on-429", global = true, default_value_t = 5)]
+    retries_on_429: usize,
+    /// Longest rate limit to wait out before failing the album or file instead, e.g. "30m".
+    #[arg(long, global = true, value_parser = parse_duration, default_value = "10m")]
+    max_rate_limit_wait: Duration,
+    /// What to do when a file already exists at the destination: keep it, replace it, download
+    /// a "name (1).ext" copy next to it, or fail the file.
+    #[arg(long, global = true, value_enum, default_value_t = OverwritePolicy::Skip)]
+    on_conflict: OverwritePolicy,
+    /// Keeps the temporary files of all failed downloads to resume from later, rather than only
+    /// those cut off by network errors or cancellation.
+    #[arg(long, global = true)]
+    keep_partial: bool,
+    /// Removes the temporary files of failed and cancelled downloads instead of resuming them.
+    #[arg(long, global = true, conflicts_with = "keep_partial")]
+    clean_partial: bool,
+    /// Renames downloaded files whose contents do not match the type Imgur reports, e.g. a
+    /// ".png" which is really a JPEG.
+    #[arg(long, global = true)]
+    fix_extensions: bool,
+    /// State database recording downloaded media. Default: "$XDG_DATA_HOME/imgurs/state.json"
+    #[arg(long, global = true)]
+    state_db: Option<PathBuf>,
+    /// Refuses to start if fewer than this many API credits remain for the client ID or user,
+    /// and stops before fetching another album's metadata once they do, saving the remaining
+    /// albums to the job file.
+    #[arg(long, global = true)]
+    min_credits: Option<u64>,
+    /// With --min-credits, waits for credits to be reset instead of refusing to start.
+    #[arg(long, global = true, requires = "min_credits")]
+    wait_for_credits: bool,
+    /// Stops before downloading an album which would bring the job's total size above this, e.g.
+    /// "50GB" or "2GiB". Asks for confirmation instead when run interactively.
+    #[arg(long, global = true, value_parser = parse_size)]
+    max_total_size: Option<u64>,
+    /// Stops before downloading an album which would bring the job's total number of files above
+    /// this. Asks for confirmation instead when run interactively.
+    #[arg(long, global = true)]
+    max_files: Option<usize>,
+    /// Asks for confirmation before downloading an album with more files than this.
+    #[arg(long, default_value_t = 500, global = true)]
+    confirm_above: usize,
+    /// Downloads albums with more files than --confirm-above without asking.
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// Stops starting new files after this long, e.g. "2h" or "1h30m". In-flight files are
+    /// finished and the remaining albums are saved to the job file.
+    #[arg(long, value_parser = parse_duration)]
+    max_duration: Option<Duration>,
+    /// File where unfinished albums are saved. Default: "$XDG_DATA_HOME/imgurs/job.json"
+    #[arg(long)]
+    job_file: Option<PathBuf>,
+    /// Resumes the job saved in the job file.
+    #[arg(long, conflicts_with = "album_ids")]
+    resume: bool,
+    /// Downloads media hosted outside Imgur. By default, such media is skipped with a warning.
+    #[arg(long, global = true)]
+    allow_external_hosts: bool,
+    /// Downloads media hosted on this host or its subdomains, even if it is not Imgur. Can be
+    /// given multiple times.
+    #[arg(long, global = true)]
+    allow_host: Vec<String>,
+    /// Only downloads files whose names match this glob, e.g. "*sketch*". Can be given multiple
+    /// times.
+    #[arg(long, global = true)]
+    include: Vec<String>,
+    /// Skips files whose names match this glob, e.g. "*.mp4". Can be given multiple times.
+    #[arg(long, global = true)]
+    exclude: Vec<String>,
+    /// Skips images narrower than this many pixels.
+    #[arg(long, global = true)]
+    min_width: Option<u32>,
+    /// Skips images shorter than this many pixels.
+    #[arg(long, global = true)]
+    min_height: Option<u32>,
+    /// Skips images whose width or height is larger than this many pixels.
+    #[arg(long, global = true)]
+    max_dimension: Option<u32>,
+    /// Only downloads animated media, such as GIFs and videos.
+    #[arg(long, global = true, conflicts_with = "no_animated")]
+    only_animated: bool,
+    /// Skips animated media, such as GIFs and videos.
+    #[arg(long, global = true)]
+    no_animated: bool,
+    /// Skips videos, but not GIFs.
+    #[arg(long, global = true)]
+    no_video: bool,
+    /// Downloads Imgur's thumbnail of this size as a JPEG in place of still images which are
+    /// larger, e.g. "huge" for at most 1024x1024.
+    #[arg(long, global = true)]
+    downscale: Option<ThumbnailSize>,
+    /// Limits the combined download speed to this much per second on average, e.g. "500KB".
+    #[arg(long, global = true, value_parser = parse_size)]
+    limit_rate: Option<u64>,
+    /// Caps the data received but not yet written to disk across all downloads, e.g. "256MiB",
+    /// so that downloading quickly to a slow disk does not run out of memory.
+    #[arg(long, global = true, value_parser = parse_size)]
+    max_buffer: Option<u64>,
+    /// Preset for metered connections such as mobile hotspots: --limit-rate 1MB --downscale huge
+    /// --no-video --max-total-size 500MB. Options given explicitly take precedence.
+    #[arg(long, global = true)]
+    metered: bool,
+    /// Maximum number of redirects to follow when resolving short links to Imgur URLs.
+    #[arg(long, default_value_t = 5, global = true)]
+    max_redirects: usize,
+    /// Follows short links on this host or its subdomains, besides t.co, bit.ly, redd.it and
+    /// other well-known shorteners. Can be given multiple times.
+    #[arg(long, global = true)]
+    shortener_host: Vec<String>,
+    /// Configuration file. Default: "$XDG_CONFIG_HOME/imgurs/config.toml"
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Profile from the configuration file whose defaults to use, e.g. its client ID, output
+    /// directory and naming templates.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Post-processing pipeline from the configuration file to run on each downloaded album.
+    #[arg(long, global = true)]
+    pipeline: Option<String>,
+    /// Appends a JSON record of every metadata fetch, download, skip and failure to this file.
+    #[arg(long, global = true)]
+    audit_log: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum AccountCommand {
+    /// Authorizes imgurs to access the API as a user, and stores their tokens as an account.
+    /// Requires the client ID's secret.
+    Login {
+        /// Name to store the account as. Default: the username
+        name: Option<String>,
+        /// Default: $IMGUR_CLIENT_SECRET
+        #[arg(long)]
+        client_secret: Option<String>,
+        /// Receives the authorization on a local server on this port, instead of asking for a
+        /// PIN. The application's callback URL must be "http://localhost:<port>/".
+        #[arg(long)]
+        redirect_port: Option<u16>,
+        /// Also makes this the default account.
+        #[arg(long)]
+        default: bool,
+    },
+    /// Stores an account's OAuth tokens, replacing any account with the same name.
+    Add {
+        name: String,
+        #[arg(long)]
+        access_token: String,
+        #[arg(long)]
+        refresh_token: Option<String>,
+        /// Also makes this the default account.
+        #[arg(long)]
+        default: bool,
+    },
+    /// Writes the account selected with --account, along with the client ID and secret its
+    /// tokens are refreshed with, to an encrypted file, for `account import` on another machine.
+    /// The passphrase is read from $IMGURS_BUNDLE_PASSPHRASE, or asked for on the terminal.
+    Export { file: PathBuf },
+    /// Stores the account from a file written by `account export`, along with its client ID and
+    /// secret.
+    Import {
+        file: PathBuf,
+        /// Also makes this the default account.
+        #[arg(long)]
+        default: bool,
+    },
+    /// Lists stored accounts.
+    List,
+    /// Removes a stored account.
+    Remove { name: String },
+    /// Sets the account used when --account is not given.
+    Default { name: String },
+    /// Prints the authenticated user's settings, notification preferences and blocked users as
+    /// JSON.
+    ExportSettings,
+    /// Lists the authenticated user's albums.
+    Albums {
+        /// Downloads all of the albums instead, each into its own directory.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Downloads the authenticated user's favorites, each into its own directory.
+    Favorites,
+    /// Prints the authenticated user's notifications: replies, mentions and messages.
+    Notifications {
+        /// Archives the notifications as JSON and Markdown into this directory instead.
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AlbumCommand {
+    /// Sets an album's title and description, and its images' order, titles and descriptions
+    /// from a manifest, as written by the `manifest` pipeline step. Images not in the manifest
+    /// are removed from the album.
+    Apply { album: String, manifest: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum UploadsCommand {
+    /// Lists anonymous uploads recorded in the vault, with the files images were uploaded from.
+    List {
+        /// Lists only uploads whose title or file contains this text, ignoring case.
+        query: Option<String>,
+    },
+    /// Shows the links of an upload recorded in the vault.
+    Show {
+        /// ID or deletehash of the upload.
+        id: String,
+        /// Prints only the upload's links, ready to paste.
+        #[arg(long)]
+        format: Option<LinkFormat>,
+        /// With --format, embeds thumbnails of this size which link to the full images.
+        #[arg(long, requires = "format")]
+        thumbnail: Option<ThumbnailSize>,
+    },
+    /// Deletes an anonymous upload from Imgur and the vault.
+    Delete {
+        /// ID or deletehash of the upload.
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum MyCommand {
+    /// Downloads your album whose title contains the given text, ignoring case.
+    Find { title: String },
+}
+
+/// Preset album directory layouts.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Layout {
+    /// Directories named after each album.
+    Flat,
+    /// Album directories nested under their uploader's username.
+    ByUploader,
+}
+
+/// What to do with albums which have no images.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EmptyAlbum {
+    /// Fails the album, so that imgurs exits with an error.
+    Error,
+    /// Prints a warning.
+    Warn,
+    /// Skips the album silently.
+    Ok,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a local server which accepts album URLs over HTTP and downloads them in order. Albums
+    /// are downloaded to "$output/$album_name".
+    Serve {
+        /// Address to listen on.
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: SocketAddr,
+        /// File where the download queue is saved. Default: "$XDG_DATA_HOME/imgurs/queue.json"
+        #[arg(long)]
+        queue_file: Option<PathBuf>,
+        /// Only downloads between these local times, e.g. "01:00-07:00", pausing outside them.
+        #[arg(long)]
+        schedule_window: Option<ScheduleWindow>,
+    },
+    /// Registers imgurs as the handler for imgurs:// URLs, which queue albums on `imgurs serve`.
+    RegisterHandler {
+        /// URL of the server to queue albums on.
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+    /// Queues the album referred to by an imgurs:// URL.
+    #[command(hide = true)]
+    HandleUrl {
+        url: String,
+        /// URL of the server to queue albums on.
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+    /// Manages stored accounts.
+    #[command(alias = "auth")]
+    Account {
+        #[command(subcommand)]
+        command: AccountCommand,
+    },
+    /// Uploads images, anonymously unless an access token is given. Anonymous uploads are
+    /// recorded in the upload vault.
+    Upload {
+        /// Images to upload, in order.
+        #[arg(required_unless_present = "manifest", conflicts_with = "manifest")]
+        files: Vec<PathBuf>,
+        /// Title of each uploaded image.
+        #[arg(long, conflicts_with = "manifest")]
+        title: Option<String>,
+        /// Description of each uploaded image.
+        #[arg(long, conflicts_with = "manifest")]
+        description: Option<String>,
+        /// Creates an album with this title and uploads the images into it.
+        #[arg(long, conflicts_with = "manifest")]
+        album: Option<String>,
+        /// TOML file, such as "manifest.toml", listing images to upload, with their titles,
+        /// descriptions and albums.
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+        /// Prints uploaded links in this format, ready to paste.
+        #[arg(long)]
+        format: Option<LinkFormat>,
+        /// With --format, embeds thumbnails of this size which link to the full images.
+        #[arg(long, requires = "format")]
+        thumbnail: Option<ThumbnailSize>,
+    },
+    /// Edits uploaded albums.
+    Album {
+        #[command(subcommand)]
+        command: AlbumCommand,
+    },
+    /// Deletes an anonymous upload given its deletehash, such as one not recorded in the upload
+    /// vault.
+    Delete {
+        deletehash: String,
+        /// The deletehash is of an album rather than an image. The album's images are kept.
+        #[arg(long)]
+        album: bool,
+    },
+    /// Manages anonymous uploads, whose deletehashes are recorded in an encrypted vault.
+    Uploads {
+        #[command(subcommand)]
+        command: UploadsCommand,
+    },
+    /// Downloads what a user has posted publicly, their gallery posts and public albums, each
+    /// into its own directory of "$output", or a directory named after them. An index of the
+    /// posts is written to "index.md" and "index.json".
+    User {
+        username: String,
+        /// Archives the user's comments instead, with links to the posts commented on.
+        #[arg(long)]
+        comments: bool,
+    },
+    /// Downloads the posts of a subreddit's Imgur gallery, given its name or a "/r/<name>" URL,
+    /// each into its own directory of "$output", or a directory named after the subreddit.
+    Subreddit {
+        subreddit: String,
+        /// Number of pages of posts to download.
+        #[arg(long, default_value_t = 1)]
+        pages: u32,
+        #[arg(long, value_enum, default_value_t)]
+        sort: SubredditSort,
+        /// Time window of posts sorted by "top".
+        #[arg(long, value_enum, default_value_t)]
+        window: TimeWindow,
+    },
+    /// Downloads the posts of a section of the gallery, such as the front page, each into a
+    /// directory of "$output" named after the post's title and ID.
+    Gallery {
+        #[arg(long, value_enum, default_value_t)]
+        section: GallerySection,
+        #[arg(long, value_enum, default_value_t)]
+        sort: GallerySort,
+        /// Time window of posts sorted by "top".
+        #[arg(long, value_enum, default_value_t)]
+        window: TimeWindow,
+        /// Number of pages of posts to download.
+        #[arg(long, default_value_t = 1)]
+        pages: u32,
+    },
+    /// Downloads the posts with a tag, given the tag or a "/t/<tag>" URL, each into its own
+    /// directory of "$output", or a directory named after the tag.
+    Tag {
+        tag: String,
+        /// Number of posts to download at most.
+        #[arg(long, default_value_t = gallery::DEFAULT_TAG_LIMIT)]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t)]
+        sort: GallerySort,
+        /// Time window of posts sorted by "top".
+        #[arg(long, value_enum, default_value_t)]
+        window: TimeWindow,
+        /// Skips posts marked NSFW.
+        #[arg(long, conflicts_with = "only_nsfw")]
+        skip_nsfw: bool,
+        /// Only downloads posts marked NSFW.
+        #[arg(long)]
+        only_nsfw: bool,
+    },
+    /// Searches the gallery, printing the matching posts as JSON. Queries may use Imgur's search
+    /// syntax, such as "cats AND dogs" or "tag:cats".
+    Search {
+        query: String,
+        /// Number of posts to find at most.
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t = GallerySort::Time)]
+        sort: GallerySort,
+        /// Time window of posts sorted by "top".
+        #[arg(long, value_enum, default_value_t = TimeWindow::All)]
+        window: TimeWindow,
+        /// Downloads the posts instead, each into its own directory of "$output", or a
+        /// directory named after the query.
+        #[arg(long)]
+        download: bool,
+    },
+    /// Prints the number and size of the files recorded in the state database.
+    Stats {
+        /// Prints the bytes downloaded over all runs, in the last 30 days and by each recent
+        /// run instead.
+        #[arg(long)]
+        bandwidth: bool,
+    },
+    /// Prints the API requests which remain for the client ID and user, and when they reset.
+    Credits,
+    /// Checks connectivity to the API and image servers, the credentials, write access to the
+    /// output directory and the local clock.
+    Doctor,
+    /// Checks the files of a downloaded album directory against its SHA256SUMS file. Exits
+    /// with 3 if any files are missing, and 4 if any do not match.
+    Verify { dir: PathBuf },
+    /// Checks that the files listed in a manifest exist, and match the SHA256SUMS file next to
+    /// it if there is one. Exits like `verify`.
+    VerifyManifest { manifest: PathBuf },
+    /// Prints what an album ID or URL refers to, its normalized ID and the API endpoint used
+    /// for it. Short links are followed first.
+    Parse { input: String },
+    /// Acts on the account of the user whose access token is given.
+    My {
+        #[command(subcommand)]
+        command: MyCommand,
+    },
+    /// Uploads a downloaded album directory to an Internet Archive item.
+    IaUpload {
+        /// Album directory to upload.
+        dir: PathBuf,
+        /// Identifier of the item to create or add to. Default: derived from the directory name
+        #[arg(long)]
+        identifier: Option<String>,
+        /// Title of the item. Default: the directory name
+        #[arg(long)]
+        title: Option<String>,
+        /// Media type of the item.
+        #[arg(long, default_value = "image")]
+        mediatype: String,
+        /// Collection to add the item to.
+        #[arg(long, default_value = "opensource_image")]
+        collection: String,
+        /// Additional item metadata as "key=value". Can be given multiple times.
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: Vec<(String, String)>,
+        /// Internet Archive S3 access key. Default: $IA_ACCESS_KEY
+        #[arg(long)]
+        ia_access_key: Option<String>,
+        /// Internet Archive S3 secret key. Default: $IA_SECRET_KEY
+        #[arg(long)]
+        ia_secret_key: Option<String>,
+    },
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| "expected \"key=value\"".to_owned())
+}
+
+fn parse_directory_template(value: &str) -> Result<Template, String> {
+    Template::parse_directory(value).map_err(|e| e.to_string())
+}
+
+fn parse_filename_template(value: &str) -> Result<String, String> {
+    Template::parse(value).map_err(|e| e.to_string())?;
+    Ok(value.to_owned())
 }
 
-#[derive(Debug, Deserialize)]
-struct ImgurResponse<T> {
-    data: Option<T>,
-    status: u64,
+/// Fills in options not given on the command line from the configuration file.
+fn apply_defaults(args: &mut Cli, matches: &ArgMatches, defaults: &Defaults) -> Result<()> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if args.output.is_none() {
+        args.output = defaults.output.as_ref().map(|output| {
+            match (output.strip_prefix("~"), paths::home_dir()) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => output.clone(),
+            }
+        });
+    }
+    if args.imgur_client_id.is_none() && std::env::var_os("IMGUR_CLIENT_ID").is_none() {
+        args.imgur_client_id = defaults.client_id.clone();
+    }
+    if args.account.is_none() {
+        args.account = defaults.account.clone();
+    }
+    // A layout given on the command line takes precedence over a configured template.
+    if args.directory_template.is_none() && !given("layout") {
+        if let Some(template) = &defaults.directory_template {
+            args.directory_template = Some(
+                parse_directory_template(template)
+                    .map_err(|e| anyhow!("Invalid directory_template in config file: {}", e))?,
+            );
+        }
+    }
+    if args.filename_template.is_none() {
+        if let Some(template) = &defaults.filename_template {
+            args.filename_template = Some(
+                parse_filename_template(template)
+                    .map_err(|e| anyhow!("Invalid filename_template in config file: {}", e))?,
+            );
+        }
+    }
+    let numbers = [
+        (&mut args.parallelism, defaults.parallelism, "parallelism"),
+        (&mut args.retries, defaults.retries, "retries"),
+        (
+            &mut args.retries_on_429,
+            defaults.retries_on_429,
+            "retries_on_429",
+        ),
+    ];
+    for (value, default, id) in numbers {
+        if let (Some(default), false) = (default, given(id)) {
+            *value = default;
+        }
+    }
+    if let (Some(wait), false) = (&defaults.max_rate_limit_wait, given("max_rate_limit_wait")) {
+        args.max_rate_limit_wait = parse_duration(wait)
+            .map_err(|e| anyhow!("Invalid max_rate_limit_wait in config file: {}", e))?;
+    }
+    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-struct ImgurAlbum {
-    id: String,
-    title: Option<String>,
-    images: Vec<ImgurMedia>,
+/// Fills in the options of the --metered preset which were not given.
+fn apply_metered(args: &mut Cli) {
+    const RATE: u64 = 1000 * 1000;
+    const MAX_TOTAL_SIZE: u64 = 500 * 1000 * 1000;
+    args.limit_rate = args.limit_rate.or(Some(RATE));
+    args.downscale = args.downscale.or(Some(ThumbnailSize::Huge));
+    args.no_video = true;
+    args.max_total_size = args.max_total_size.or(Some(MAX_TOTAL_SIZE));
 }
 
-#[derive(Debug, Deserialize)]
-struct ImgurMedia {
-    id: String,
-    title: Option<String>,
-    description: Option<String>,
-    link: String,
-    datetime: i64,
-    size: u64,
-    #[serde(rename = "type")]
-    content_type: String,
+/// Parses a size such as "500MB", "1.5GiB" or "1024".
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size \"{}\"", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000_u64.pow(2),
+        "g" | "gb" => 1000_u64.pow(3),
+        "t" | "tb" => 1000_u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        unit => return Err(format!("unknown size unit \"{}\"", unit)),
+    };
+    Ok((number * multiplier as f64) as u64)
 }
 
-const IMGUR_ALBUM_URL_PREFIX: &str = "https://imgur.com/a/";
-fn get_album_id(album_id: &str) -> &str {
-    album_id
-        .strip_prefix(IMGUR_ALBUM_URL_PREFIX)
-        .unwrap_or(album_id)
+/// Parses a duration such as "2h", "1h30m", "45m" or "90s". Plain numbers are seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration \"{}\"", value);
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        seconds += number.parse::<u64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<u64>().map_err(|_| invalid())?;
+    }
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
 }
 
-fn get_media_type(content_type: &str) -> &str {
-    let (_, content_type) = content_type.split_once("/").unwrap_or(("", "unknown"));
-    if content_type == "jpeg" {
-        "jpg"
+const DEFAULT_SERVER: &str = "http://127.0.0.1:7878/";
+/// Keyring entries for the client ID and secret, stored by `account login`.
+const CLIENT_ID_KEY: &str = "client-id";
+const CLIENT_SECRET_KEY: &str = "client-secret";
+/// Exit codes of `verify` and `verify-manifest`, so that scheduled checks can tell problems
+/// apart. Mismatches take precedence over missing files.
+const EXIT_MISSING_FILES: i32 = 3;
+const EXIT_HASH_MISMATCH: i32 = 4;
+
+#[cfg(feature = "multi-threaded")]
+fn runtime_builder(single_threaded: bool) -> tokio::runtime::Builder {
+    if single_threaded {
+        tokio::runtime::Builder::new_current_thread()
     } else {
-        content_type
+        tokio::runtime::Builder::new_multi_thread()
+    }
+}
+
+#[cfg(not(feature = "multi-threaded"))]
+fn runtime_builder(_single_threaded: bool) -> tokio::runtime::Builder {
+    tokio::runtime::Builder::new_current_thread()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config = Config::load(args.config.as_deref())?;
+    let defaults = config.profile_defaults(args.profile.as_deref())?;
+    apply_defaults(&mut args, &matches, &defaults)?;
+    if args.metered {
+        apply_metered(&mut args);
+    }
+    // Posts of gallery sections often share titles, such as "Meme" or untitled ones.
+    let layout_given = matches.value_source("layout") == Some(ValueSource::CommandLine);
+    if matches!(args.command, Some(Command::Gallery { .. }))
+        && args.directory_template.is_none()
+        && !layout_given
+    {
+        args.directory_template = Some(Template::parse_directory(POST_DIRECTORY_TEMPLATE)?);
+    }
+
+    if args.nice {
+        priority::lower_priority().map_err(|e| format!("Unable to lower priority: {}", e))?;
+    }
+
+    runtime_builder(args.single_threaded)
+        .enable_all()
+        .build()?
+        .block_on(run(args, config))
+}
+
+/// Whether the command makes requests through the API client, as opposed to only reading and
+/// writing local files. Short links are followed with a client of their own.
+fn sends_requests(args: &Cli) -> bool {
+    match &args.command {
+        None => !args.offline,
+        Some(
+            Command::RegisterHandler { .. }
+            | Command::Verify { .. }
+            | Command::VerifyManifest { .. }
+            | Command::Stats { .. }
+            | Command::Parse { .. },
+        ) => false,
+        Some(Command::Uploads { command }) => !matches!(
+            command,
+            UploadsCommand::List { .. } | UploadsCommand::Show { .. }
+        ),
+        Some(Command::Account { command }) => !matches!(
+            command,
+            AccountCommand::Add { .. }
+                | AccountCommand::Export { .. }
+                | AccountCommand::Import { .. }
+                | AccountCommand::List
+                | AccountCommand::Remove { .. }
+                | AccountCommand::Default { .. }
+        ),
+        Some(_) => true,
     }
 }
 
-async fn prepare_directory(path: PathBuf) -> Result<()> {
-    let metadata = tokio::fs::metadata(path.clone()).await;
-    if let Err(e) = metadata {
-        match e.kind() {
-            std::io::ErrorKind::NotFound => {
-                tokio::fs::create_dir_all(path).await?;
-                Ok(())
+async fn run(args: Cli, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let use_keyring = keyring::SUPPORTED && !args.no_keyring;
+    // Only commands which use the client ID or secret look them up, keeping others from waiting
+    // on, or prompting to unlock, the keyring.
+    let needs_credentials = sends_requests(&args)
+        || matches!(
+            &args.command,
+            Some(Command::Account {
+                command: AccountCommand::Export { .. }
+            })
+        );
+    let keyring_secret = |key: &str| {
+        if !use_keyring || !needs_credentials {
+            return None;
+        }
+        keyring::get(key).unwrap_or_else(|e| {
+            println!("Unable to read the {} from the keyring: {:#}", key, e);
+            None
+        })
+    };
+    let client_id = args
+        .imgur_client_id
+        .clone()
+        .or_else(|| std::env::var("IMGUR_CLIENT_ID").ok())
+        .or_else(|| keyring_secret(CLIENT_ID_KEY))
+        .unwrap_or_default();
+    let mut accounts = match args.accounts_file.clone().or_else(accounts::default_path) {
+        Some(path) => Accounts::load(&path, use_keyring)?,
+        None => Accounts::default(),
+    };
+    let sends_requests = sends_requests(&args);
+    let client = if sends_requests {
+        proxy::configure(Client::builder(), args.proxy.as_ref())?.build()?
+    } else {
+        Client::new()
+    };
+    let api = ApiClient::builder()
+        .client(client.clone())
+        .base_url(&args.api_base_url)
+        .build()?;
+    let access_token = match args
+        .access_token
+        .clone()
+        .or_else(|| std::env::var("IMGUR_ACCESS_TOKEN").ok())
+    {
+        Some(token) => Some(token),
+        // Refreshing the account's tokens would make a request.
+        None if !sends_requests => None,
+        None => {
+            let client_secret = || {
+                std::env::var("IMGUR_CLIENT_SECRET")
+                    .ok()
+                    .or_else(|| keyring_secret(CLIENT_SECRET_KEY))
+            };
+            account_access_token(
+                &api,
+                &client_id,
+                client_secret,
+                &mut accounts,
+                args.account.as_deref(),
+            )
+            .await?
+        }
+    };
+    let auth = match access_token {
+        Some(token) => Auth::AccessToken(token),
+        None => Auth::ClientId(client_id.clone()),
+    };
+
+    let job_file = || {
+        args.job_file
+            .clone()
+            .or_else(|| paths::data_dir().map(|dir| dir.join("job.json")))
+            .ok_or_else(|| anyhow!("Unable to determine job file location"))
+    };
+    let vault_file = || {
+        args.vault_file
+            .clone()
+            .or_else(vault::default_path)
+            .ok_or_else(|| anyhow!("Unable to determine vault location"))
+    };
+    let albums = if args.resume {
+        job::load_albums(&job_file()?)?
+    } else {
+        let read_stdin = args.stdin || args.album_ids.iter().any(|input| input == "-");
+        let mut inputs: Vec<String> = args
+            .album_ids
+            .iter()
+            .filter(|input| *input != "-")
+            .cloned()
+            .collect();
+        if let Some(input_file) = &args.input_file {
+            inputs.extend(job::read_album_list(input_file)?);
+        }
+        if read_stdin {
+            let list = std::io::read_to_string(std::io::stdin())
+                .map_err(|e| format!("Unable to read standard input: {}", e))?;
+            inputs.extend(job::parse_album_list(&list));
+        }
+        let several = inputs.len() > 1;
+        inputs
+            .into_iter()
+            .map(|input| QueuedAlbum {
+                input,
+                destination: match &args.output {
+                    Some(output) if several => Destination::Under(output.clone()),
+                    Some(output) => Destination::Exact(output.clone()),
+                    None => Destination::Named,
+                },
+            })
+            .collect()
+    };
+    // Fail early on invalid configuration, rather than after downloading an album.
+    if let Some(name) = &args.pipeline {
+        config.pipeline(name)?;
+    }
+
+    let audit_log = args.audit_log.as_deref().map(AuditLog::open).transpose()?;
+    let job = Arc::new(Job::new(albums, audit_log));
+    job.parallelism.set(args.parallelism);
+
+    // Stop downloading cleanly on Ctrl-C.
+    tokio::spawn({
+        let job = job.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                job.cancel.cancel();
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    let _control_socket = match &args.control_socket {
+        Some(path) => Some(control::serve(path, job.clone()).await?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if args.control_socket.is_some() {
+        return Err("Control sockets are only supported on Unix".into());
+    }
+
+    if let Some(min_credits) = args.min_credits {
+        if !matches!(
+            &args.command,
+            None | Some(Command::Serve { .. })
+                | Some(Command::My { .. })
+                | Some(Command::User {
+                    comments: false,
+                    ..
+                })
+                | Some(Command::Subreddit { .. })
+                | Some(Command::Tag { .. })
+                | Some(Command::Gallery { .. })
+                | Some(Command::Search { download: true, .. })
+                | Some(Command::Account {
+                    command: AccountCommand::Albums { all: true } | AccountCommand::Favorites
+                })
+        ) {
+            return Err("--min-credits can only be used when downloading".into());
+        }
+        ensure_credits(&api, &auth, min_credits, args.wait_for_credits, &job).await?;
+    }
+
+    match &args.command {
+        Some(Command::Serve {
+            listen,
+            queue_file,
+            schedule_window,
+        }) => {
+            let queue_file = queue_file
+                .clone()
+                .or_else(serve::default_queue_file)
+                .ok_or_else(|| anyhow!("Unable to determine queue file location"))?;
+            if let Some(window) = *schedule_window {
+                tokio::spawn(schedule::enforce(window, job.clone()));
+            }
+            serve::serve(
+                &api,
+                &auth,
+                &args,
+                &config,
+                job.clone(),
+                *listen,
+                &queue_file,
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Command::RegisterHandler { server }) => {
+            handler::register(server)?;
+            return Ok(());
+        }
+        Some(Command::HandleUrl { url, server }) => {
+            handler::handle_url(&client, server, url).await?;
+            return Ok(());
+        }
+        Some(Command::IaUpload {
+            dir,
+            identifier,
+            title,
+            mediatype,
+            collection,
+            metadata,
+            ia_access_key,
+            ia_secret_key,
+        }) => {
+            let credentials = archive::Credentials {
+                access_key: ia_access_key
+                    .clone()
+                    .or_else(|| std::env::var("IA_ACCESS_KEY").ok())
+                    .ok_or_else(|| anyhow!("Missing Internet Archive access key"))?,
+                secret_key: ia_secret_key
+                    .clone()
+                    .or_else(|| std::env::var("IA_SECRET_KEY").ok())
+                    .ok_or_else(|| anyhow!("Missing Internet Archive secret key"))?,
+            };
+            let identifier = identifier
+                .clone()
+                .or_else(|| archive::default_identifier(dir))
+                .ok_or_else(|| anyhow!("Unable to derive an identifier, use --identifier"))?;
+            let metadata = archive::ItemMetadata {
+                title: title.clone().unwrap_or_else(|| {
+                    dir.canonicalize()
+                        .ok()
+                        .and_then(|dir| Some(dir.file_name()?.to_string_lossy().into_owned()))
+                        .unwrap_or_else(|| identifier.clone())
+                }),
+                mediatype: mediatype.clone(),
+                collection: collection.clone(),
+                extra: metadata.clone(),
+            };
+            archive::upload(&client, dir, &identifier, &credentials, &metadata).await?;
+            return Ok(());
+        }
+        Some(Command::Account {
+            command: AccountCommand::Albums { all: true },
+        }) => {
+            let albums = my::albums(&api, &auth).await?;
+            println!("Downloading {} albums", albums.len());
+            for album in albums {
+                job.enqueue(QueuedAlbum {
+                    input: album.id,
+                    destination: match &args.output {
+                        Some(output) => Destination::Under(output.clone()),
+                        None => Destination::Named,
+                    },
+                });
+            }
+        }
+        Some(Command::Account {
+            command: AccountCommand::Favorites,
+        }) => {
+            let favorites = my::favorites(&api, &auth).await?;
+            println!("Downloading {} favorites", favorites.len());
+            // Favorites are gallery posts, which are downloaded as albums even if they are of a
+            // single image.
+            for favorite in favorites {
+                job.enqueue(QueuedAlbum {
+                    input: format!("https://imgur.com/gallery/{}", favorite.id),
+                    destination: match &args.output {
+                        Some(output) => Destination::Under(output.clone()),
+                        None => Destination::Named,
+                    },
+                });
+            }
+        }
+        Some(Command::Account { command }) => {
+            match command {
+                AccountCommand::Add {
+                    name,
+                    access_token,
+                    refresh_token,
+                    default,
+                } => {
+                    let account = Account {
+                        access_token: access_token.clone(),
+                        refresh_token: refresh_token.clone(),
+                        expires_at: None,
+                        username: None,
+                        in_keyring: false,
+                    };
+                    accounts.accounts.insert(name.clone(), account);
+                    if *default || accounts.default.is_none() {
+                        accounts.default = Some(name.clone());
+                    }
+                }
+                AccountCommand::Login {
+                    name,
+                    client_secret,
+                    redirect_port,
+                    default,
+                } => {
+                    let client_secret = client_secret
+                        .clone()
+                        .or_else(|| std::env::var("IMGUR_CLIENT_SECRET").ok())
+                        .or_else(|| keyring_secret(CLIENT_SECRET_KEY))
+                        .ok_or_else(|| anyhow!("Missing Imgur client secret"))?;
+                    let tokens =
+                        oauth::login(&api, &client_id, &client_secret, *redirect_port).await?;
+                    // Keep the client ID and secret for refreshing the tokens later.
+                    if use_keyring {
+                        store_client(&client_id, &client_secret);
+                    }
+                    let account = Account::from_tokens(tokens);
+                    let name = name
+                        .clone()
+                        .or_else(|| account.username.clone())
+                        .ok_or_else(|| {
+                            anyhow!("Imgur did not give a username, pass a name for the account")
+                        })?;
+                    println!(
+                        "Logged in as {}, stored as account {}",
+                        account.username.as_deref().unwrap_or("?"),
+                        name
+                    );
+                    accounts.accounts.insert(name.clone(), account);
+                    if *default || accounts.default.is_none() {
+                        accounts.default = Some(name);
+                    }
+                }
+                AccountCommand::Export { file } => {
+                    let (name, mut account) = accounts
+                        .credentials(args.account.as_deref())?
+                        .ok_or_else(|| anyhow!("No account to export, pass --account"))?;
+                    account.in_keyring = false;
+                    let client_secret = std::env::var("IMGUR_CLIENT_SECRET")
+                        .ok()
+                        .or_else(|| keyring_secret(CLIENT_SECRET_KEY));
+                    if client_secret.is_none() {
+                        println!(
+                            "No client secret, so the tokens cannot be refreshed once they expire"
+                        );
+                    }
+                    let bundle = accounts::Bundle {
+                        name,
+                        account,
+                        client_id: client_id.clone(),
+                        client_secret,
+                    };
+                    bundle.write(file)?;
+                    println!("Exported account {} to {}", bundle.name, file.display());
+                    return Ok(());
+                }
+                AccountCommand::Import { file, default } => {
+                    let bundle = accounts::Bundle::read(file)?;
+                    match &bundle.client_secret {
+                        Some(client_secret) if use_keyring => {
+                            store_client(&bundle.client_id, client_secret)
+                        }
+                        Some(_) => println!(
+                            "Set $IMGUR_CLIENT_ID to {} and $IMGUR_CLIENT_SECRET to refresh the \
+                             account's tokens",
+                            bundle.client_id
+                        ),
+                        None => println!(
+                            "The bundle has no client secret, so the account's tokens cannot be \
+                             refreshed once they expire"
+                        ),
+                    }
+                    println!("Imported account {}", bundle.name);
+                    accounts
+                        .accounts
+                        .insert(bundle.name.clone(), bundle.account);
+                    if *default || accounts.default.is_none() {
+                        accounts.default = Some(bundle.name);
+                    }
+                }
+                AccountCommand::List => {
+                    for name in accounts.accounts.keys() {
+                        let default = accounts.default.as_ref() == Some(name);
+                        println!("{}{}", name, if default { " (default)" } else { "" });
+                    }
+                    return Ok(());
+                }
+                AccountCommand::Remove { name } => accounts.remove(name)?,
+                AccountCommand::Default { name } => {
+                    accounts.get(Some(name))?;
+                    accounts.default = Some(name.clone());
+                }
+                AccountCommand::ExportSettings => {
+                    let export = my::export_settings(&api, &auth).await?;
+                    println!("{}", serde_json::to_string_pretty(&export)?);
+                    return Ok(());
+                }
+                AccountCommand::Albums { all: true } | AccountCommand::Favorites => {
+                    unreachable!("downloads are queued above")
+                }
+                AccountCommand::Albums { all: false } => {
+                    for album in my::albums(&api, &auth).await? {
+                        println!("{}: {}", album.id, album.title.as_deref().unwrap_or(""));
+                    }
+                    return Ok(());
+                }
+                AccountCommand::Notifications { export } => {
+                    let notifications = my::notifications(&api, &auth).await?;
+                    match export {
+                        Some(dir) => {
+                            my::export_notifications(dir, &notifications)?;
+                            println!(
+                                "Archived {} notifications to {}",
+                                notifications.replies.len() + notifications.messages.len(),
+                                dir.display()
+                            );
+                        }
+                        None => my::print_notifications(&notifications),
+                    }
+                    return Ok(());
+                }
+            }
+            accounts.save()?;
+            return Ok(());
+        }
+        Some(Command::Upload {
+            files,
+            title,
+            description,
+            album,
+            manifest,
+            format,
+            thumbnail,
+        }) => {
+            let mut manifest = match manifest {
+                Some(manifest) => upload::Manifest::load(manifest)?,
+                None => {
+                    let images =
+                        upload::Manifest::images(files, title.as_deref(), description.as_deref());
+                    match album {
+                        Some(album) => images.into_album(album),
+                        None => images,
+                    }
+                }
+            };
+            manifest.check_files()?;
+            let mut vault = match auth {
+                Auth::ClientId(_) => Some(Vault::open(&vault_file()?)?),
+                Auth::AccessToken(_) => None,
+            };
+            upload::upload_manifest(&api, &auth, &manifest, vault.as_mut(), &mut |upload| {
+                match format {
+                    Some(format) => println!("{}", format_upload(upload, *format, *thumbnail)),
+                    None => println!(
+                        "Uploaded {} {} (deletehash {})",
+                        upload.kind.name(),
+                        upload.link,
+                        upload.deletehash
+                    ),
+                }
+            })
+            .await?;
+            return Ok(());
+        }
+        Some(Command::Album {
+            command: AlbumCommand::Apply { album, manifest },
+        }) => {
+            let manifest = upload::Manifest::load(manifest)?;
+            let vault = match auth {
+                Auth::ClientId(_) => Some(Vault::open(&vault_file()?)?),
+                Auth::AccessToken(_) => None,
+            };
+            upload::apply_manifest(&api, &auth, album, &manifest, vault.as_ref()).await?;
+            println!("Updated album {}", get_album_id(album));
+            return Ok(());
+        }
+        Some(Command::Uploads { command }) => {
+            let mut vault = Vault::open(&vault_file()?)?;
+            match command {
+                UploadsCommand::List { query } => {
+                    let query = query.as_deref().map(str::to_lowercase);
+                    let matches = |upload: &Upload| {
+                        let Some(query) = &query else {
+                            return true;
+                        };
+                        let path = upload.path.as_ref().map(|path| path.to_string_lossy());
+                        let matched = [upload.title.as_deref(), path.as_deref()]
+                            .into_iter()
+                            .flatten()
+                            .any(|text| text.to_lowercase().contains(query));
+                        matched
+                    };
+                    for upload in vault.uploads.iter().filter(|upload| matches(upload)) {
+                        println!(
+                            "{} {} {} {}{}{}",
+                            DateTime::from_unix(upload.datetime).to_rfc3339(),
+                            upload.kind.name(),
+                            upload.id,
+                            upload.link,
+                            upload
+                                .path
+                                .as_ref()
+                                .map(|path| format!(" {}", path.display()))
+                                .unwrap_or_default(),
+                            upload
+                                .title
+                                .as_ref()
+                                .map(|title| format!(" {}", title))
+                                .unwrap_or_default()
+                        );
+                    }
+                }
+                UploadsCommand::Show {
+                    id,
+                    format,
+                    thumbnail,
+                } => {
+                    let upload = vault
+                        .get(id)
+                        .ok_or_else(|| anyhow!("No upload {} in the vault", id))?;
+                    match format {
+                        Some(format) => print_upload_links(&vault, upload, *format, *thumbnail),
+                        None => print_upload(&vault, upload),
+                    }
+                }
+                UploadsCommand::Delete { id } => {
+                    let upload = vault
+                        .take(id)
+                        .ok_or_else(|| anyhow!("No upload {} in the vault", id))?;
+                    upload::delete(&api, &client_id, upload.kind, &upload.deletehash).await?;
+                    vault.save()?;
+                    println!("Deleted {}", upload.link);
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Delete { deletehash, album }) => {
+            let kind = if *album {
+                UploadKind::Album
+            } else {
+                UploadKind::Image
+            };
+            upload::delete(&api, &client_id, kind, deletehash).await?;
+            println!("Deleted {} {}", kind.name(), deletehash);
+            return Ok(());
+        }
+        Some(Command::Credits) => {
+            let response = fetch_credits(&api, &auth).await?;
+            let credits = response.data.ok_or_else(|| {
+                anyhow!(
+                    "Failed to get API credits with status code: {}",
+                    response.status
+                )
+            })?;
+            println!(
+                "Client: {}/{} requests remaining, reset daily",
+                credits.client_remaining, credits.client_limit
+            );
+            let reset = credits.user_reset;
+            println!(
+                "User: {}/{} requests remaining, reset at {} (in {})",
+                credits.user_remaining,
+                credits.user_limit,
+                DateTime::from_unix(reset + local_offset(reset)).format("%Y-%m-%d %H:%M"),
+                format_duration(Duration::from_secs((reset - unix_now()).max(0) as u64))
+            );
+            return Ok(());
+        }
+        Some(Command::Verify { dir }) => {
+            let verification = torrent::verify_checksums(dir)?;
+            std::process::exit(report_verification(&verification));
+        }
+        Some(Command::VerifyManifest { manifest }) => {
+            let verification = verify_manifest(manifest)?;
+            std::process::exit(report_verification(&verification));
+        }
+        Some(Command::Stats { bandwidth }) => {
+            let state_db = StateDb::load(&state_db_path(&args)?)?;
+            if *bandwidth {
+                print_bandwidth(&state_db.bandwidth);
+            } else {
+                let size: u64 = state_db.media.values().map(|record| record.size).sum();
+                println!(
+                    "{} files recorded, {} in total",
+                    state_db.media.len(),
+                    format_size(size, DECIMAL)
+                );
+            }
+            return Ok(());
+        }
+        Some(Command::Doctor) => {
+            let output = args.output.clone().unwrap_or_else(|| PathBuf::from("."));
+            doctor::run(&api, &auth, &args.api_base_url, &output).await?;
+            return Ok(());
+        }
+        Some(Command::Parse { input }) => {
+            let resolved =
+                unshorten::resolve(input, args.max_redirects, &args.shortener_host).await?;
+            let link = parse_link(&resolved)
+                .ok_or_else(|| anyhow!("Not an album ID or Imgur URL: {}", resolved))?;
+            if resolved != *input {
+                println!("Resolved: {}", resolved);
+            }
+            println!("Type: {}", link.kind());
+            println!("ID: {}", link.id());
+            println!("Endpoint: {}", link.endpoint());
+            return Ok(());
+        }
+        Some(Command::User { username, comments }) => {
+            let dir = args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(safe_filename(username)));
+            if *comments {
+                let comments = user::comments(&api, &auth, username).await?;
+                user::export_comments(&dir, username, &comments)?;
+                println!("Archived {} comments to {}", comments.len(), dir.display());
+                return Ok(());
+            }
+            let posts = user::posts(&api, &auth, username).await?;
+            user::write_index(&dir, username, &posts)?;
+            enqueue_links(&job, posts.into_iter().map(|post| post.link).collect(), dir);
+        }
+        Some(Command::Subreddit {
+            subreddit,
+            pages,
+            sort,
+            window,
+        }) => {
+            let name = gallery::subreddit_name(subreddit)?;
+            let selection = gallery::Selection {
+                pages: *pages,
+                ..Default::default()
+            };
+            let posts =
+                gallery::subreddit_posts(&api, &auth, name, *sort, *window, selection).await?;
+            let dir = args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(safe_filename(name)));
+            enqueue_posts(&job, posts, dir);
+        }
+        Some(Command::Tag {
+            tag,
+            limit,
+            sort,
+            window,
+            skip_nsfw,
+            only_nsfw,
+        }) => {
+            let tag = gallery::tag_name(tag)?;
+            let selection = gallery::Selection {
+                pages: u32::MAX,
+                limit: *limit,
+                nsfw: only_nsfw.then_some(true).or(skip_nsfw.then_some(false)),
+            };
+            let posts = gallery::tag_posts(&api, &auth, tag, *sort, *window, selection).await?;
+            let dir = args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(safe_filename(tag)));
+            enqueue_posts(&job, posts, dir);
+        }
+        Some(Command::Gallery {
+            section,
+            sort,
+            window,
+            pages,
+        }) => {
+            let selection = gallery::Selection {
+                pages: *pages,
+                ..Default::default()
+            };
+            let posts =
+                gallery::section_posts(&api, &auth, *section, *sort, *window, selection).await?;
+            let dir = args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("gallery-{}", section.as_str())));
+            enqueue_posts(&job, posts, dir);
+        }
+        Some(Command::Search {
+            query,
+            limit,
+            sort,
+            window,
+            download,
+        }) => {
+            let selection = gallery::Selection {
+                pages: u32::MAX,
+                limit: *limit,
+                nsfw: None,
+            };
+            let posts = gallery::search(&api, &auth, query, *sort, *window, selection).await?;
+            if !*download {
+                println!("{}", serde_json::to_string_pretty(&posts)?);
+                return Ok(());
+            }
+            let dir = args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(safe_filename(query)));
+            enqueue_posts(&job, posts, dir);
+        }
+        Some(Command::My {
+            command: MyCommand::Find { title },
+        }) => {
+            let album = my::find_album(&api, &auth, title).await?;
+            job.enqueue(QueuedAlbum {
+                input: album.id,
+                destination: match &args.output {
+                    Some(output) => Destination::Exact(output.clone()),
+                    None => Destination::Named,
+                },
+            });
+        }
+        None => {}
+    }
+
+    if let Some(max_duration) = args.max_duration {
+        tokio::spawn({
+            let job = job.clone();
+            async move {
+                tokio::time::sleep(max_duration).await;
+                job.log("Reached --max-duration, finishing in-flight files.");
+                job.stop.cancel();
+            }
+        });
+    }
+
+    // When downloading several albums, one failing does not stop the others.
+    let started = unix_now();
+    let mut total_albums = job.queued().len();
+    let mut failed_albums = 0;
+    let mut prefetched: Option<(String, Metadata)> = None;
+    while let Some(album) = job.next_album() {
+        // Tag, subreddit and user links are downloaded as the posts they list, with the defaults
+        // of the tag, subreddit and user commands.
+        if let Some(link) = parse_link(&album.input).filter(|_| is_listing(&album.input)) {
+            let dir = match &album.destination {
+                Destination::Exact(dir) => dir.clone(),
+                Destination::Under(dir) => dir.join(safe_filename(link.id())),
+                Destination::Named => PathBuf::from(safe_filename(link.id())),
+            };
+            let links = match link {
+                ImgurLink::User(name) => user::posts(&api, &auth, name)
+                    .await
+                    .map(|posts| posts.into_iter().map(|post| post.link).collect()),
+                ImgurLink::Tag(tag) => {
+                    let selection = gallery::Selection {
+                        pages: u32::MAX,
+                        limit: gallery::DEFAULT_TAG_LIMIT,
+                        nsfw: None,
+                    };
+                    let (sort, window) = (GallerySort::default(), TimeWindow::default());
+                    gallery::tag_posts(&api, &auth, tag, sort, window, selection)
+                        .await
+                        .map(gallery_links)
+                }
+                _ => {
+                    let (sort, window) = (SubredditSort::default(), TimeWindow::default());
+                    let selection = gallery::Selection::default();
+                    gallery::subreddit_posts(&api, &auth, link.id(), sort, window, selection)
+                        .await
+                        .map(gallery_links)
+                }
+            };
+            match links {
+                Ok(links) => {
+                    total_albums += links.len();
+                    enqueue_links(&job, links, dir);
+                }
+                Err(e) if total_albums <= 1 => return Err(e.into()),
+                Err(e) => {
+                    println!("Unable to download {}: {:?}\n", album.input, e);
+                    failed_albums += 1;
+                }
+            }
+            continue;
+        }
+        let metadata = prefetched
+            .take()
+            .filter(|(input, _)| *input == album.input)
+            .map(|(_, metadata)| metadata);
+        if let (None, Some(min_credits), Some(remaining)) =
+            (&metadata, args.min_credits, remaining_credits(&api))
+        {
+            if remaining < min_credits && args.wait_for_credits {
+                ensure_credits(&api, &auth, min_credits, true, &job).await?;
+            } else if remaining < min_credits {
+                job.log(format!(
+                    "Only {} API credits remain, fewer than the minimum of {}; stopping.",
+                    remaining, min_credits
+                ));
+                job.requeue(album);
+                job.stop.cancel();
+                break;
+            }
+        }
+
+        let metadata = match metadata {
+            None if !args.offline => {
+                Some(fetch_metadata(&api, &auth, &args, &job, &album.input).await)
             }
-            std::io::ErrorKind::PermissionDenied => {
-                Err(e).with_context(|| "Permission denied when retrieving file metadata")
+            metadata => metadata,
+        };
+
+        // Fetch the next album's metadata while this one downloads, rather than all of it up
+        // front, as long as enough credits would remain.
+        let next = job.next_queued().filter(|next| {
+            !is_listing(&next.input)
+                && !args.offline
+                && remaining_credits(&api)
+                    .is_none_or(|remaining| remaining > args.min_credits.unwrap_or(0))
+        });
+        let prefetch = async {
+            let next = next?;
+            let metadata = fetch_metadata(&api, &auth, &args, &job, &next.input).await;
+            Some((next.input, metadata))
+        };
+        let (result, next) = tokio::join!(
+            download_album(&api, &auth, &args, &config, &job, album.clone(), metadata),
+            prefetch
+        );
+        prefetched = next;
+
+        if let Err(e) = result {
+            if total_albums <= 1 {
+                record_bandwidth(&args, &job, started);
+                return Err(e.into());
             }
-            _ => Err(e).with_context(|| "Unable to retrieve file metadata"),
+            println!("Unable to download {}: {:?}\n", album.input, e);
+            job.audit(
+                "album-failure",
+                json!({ "album": album.input, "error": format!("{:#}", e) }),
+            );
+            failed_albums += 1;
+            continue;
+        }
+        // The album may not have been fully downloaded; files which were are skipped on resume.
+        if job.stop.is_cancelled() {
+            job.requeue(album);
         }
-    } else if metadata.unwrap().is_file() {
-        Err(anyhow!("Destination is a file"))
+    }
+
+    record_bandwidth(&args, &job, started);
+
+    if job.stop.is_cancelled() && !job.cancel.is_cancelled() {
+        let job_file = job_file()?;
+        job::save_albums(&job_file, &job.queued_albums())?;
+        println!(
+            "Saved {} unfinished albums to {}, continue with --resume.",
+            job.queued().len(),
+            job_file.display()
+        );
+    } else if args.resume && !job.cancel.is_cancelled() {
+        std::fs::remove_file(job_file()?)?;
+    }
+
+    if failed_albums > 0 {
+        return Err(format!(
+            "{} of {} albums could not be downloaded",
+            failed_albums, total_albums
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Links to gallery `posts`, which are downloaded as albums even if they are of a single image.
+fn gallery_links(posts: Vec<ImgurGalleryItem>) -> Vec<String> {
+    posts
+        .into_iter()
+        .map(|post| format!("https://imgur.com/gallery/{}", post.id))
+        .collect()
+}
+
+/// Queues gallery `posts` to be downloaded, each into its own directory of `dir`.
+fn enqueue_posts(job: &Job, posts: Vec<ImgurGalleryItem>, dir: PathBuf) {
+    enqueue_links(job, gallery_links(posts), dir);
+}
+
+/// Queues the posts at `links` to be downloaded, each into its own directory of `dir`.
+fn enqueue_links(job: &Job, links: Vec<String>, dir: PathBuf) {
+    println!("Downloading {} posts into {}", links.len(), dir.display());
+    for input in links {
+        job.enqueue(QueuedAlbum {
+            input,
+            destination: Destination::Under(dir.clone()),
+        });
+    }
+}
+
+/// Whether `input` is a tag, subreddit or user, which lists posts rather than being one.
+fn is_listing(input: &str) -> bool {
+    matches!(
+        parse_link(input),
+        Some(ImgurLink::Tag(_) | ImgurLink::Subreddit(_) | ImgurLink::User(_))
+    )
+}
+
+/// Adds the bytes received by this run to the state database. Failing to is only reported, as
+/// the downloads themselves succeeded.
+fn record_bandwidth(args: &Cli, job: &Job, started: i64) {
+    let bytes = job.received_bytes();
+    if bytes == 0 {
+        return;
+    }
+    let run = RunRecord {
+        started,
+        seconds: (unix_now() - started).max(0) as u64,
+        albums: job.status().completed_albums,
+        bytes,
+    };
+    let result = state_db_path(args)
+        .and_then(|path| StateDb::load(&path))
+        .and_then(|mut state_db| {
+            state_db.bandwidth.record(run);
+            state_db.save()
+        });
+    if let Err(e) = result {
+        println!("Unable to record the bandwidth used: {:#}", e);
+    }
+}
+
+/// Prints the bytes downloaded over all runs, in the last 30 days and by each recorded run.
+fn print_bandwidth(bandwidth: &Bandwidth) {
+    const MONTH: i64 = 30 * 24 * 60 * 60;
+    let recent: Vec<&RunRecord> = bandwidth
+        .runs
+        .iter()
+        .filter(|run| run.started > unix_now() - MONTH)
+        .collect();
+    println!(
+        "Total: {} over all runs",
+        format_size(bandwidth.total_bytes, DECIMAL)
+    );
+    println!(
+        "Last 30 days: {} over {} runs",
+        format_size(recent.iter().map(|run| run.bytes).sum::<u64>(), DECIMAL),
+        recent.len()
+    );
+    for run in bandwidth.runs.iter().rev() {
+        println!(
+            "{}  {:>10}  {} albums in {}",
+            DateTime::from_unix(run.started + local_offset(run.started)).format("%Y-%m-%d %H:%M"),
+            format_size(run.bytes, DECIMAL),
+            run.albums,
+            format_duration(Duration::from_secs(run.seconds))
+        );
+    }
+}
+
+/// Prints an upload's links, and those of its album or images.
+fn print_upload(vault: &Vault, upload: &Upload) {
+    println!("{} {}", upload.kind.name(), upload.id);
+    if let Some(title) = &upload.title {
+        println!("Title: {}", title);
+    }
+    println!(
+        "Uploaded: {}",
+        DateTime::from_unix(upload.datetime).to_rfc3339()
+    );
+    println!("Link: {}", upload.link);
+    println!("Deletehash: {}", upload.deletehash);
+    match upload.kind {
+        UploadKind::Image => {
+            if let Some(album) = &upload.album {
+                println!("Album: https://imgur.com/a/{}", album);
+            }
+            println!("Thumbnails:");
+            for size in ThumbnailSize::ALL {
+                println!("  {}: {}", size.name(), thumbnail_link(&upload.id, size));
+            }
+        }
+        UploadKind::Album => {
+            println!("Images:");
+            for image in &vault.uploads {
+                if image.album.as_ref() == Some(&upload.id) {
+                    println!("  {}", image.link);
+                }
+            }
+        }
+    }
+}
+
+/// Prints an upload in the given format, followed by the images of an album.
+fn print_upload_links(
+    vault: &Vault,
+    upload: &Upload,
+    format: LinkFormat,
+    thumbnail: Option<ThumbnailSize>,
+) {
+    println!("{}", format_upload(upload, format, thumbnail));
+    if upload.kind == UploadKind::Album {
+        for image in &vault.uploads {
+            if image.album.as_ref() == Some(&upload.id) {
+                println!("{}", format_upload(image, format, thumbnail));
+            }
+        }
+    }
+}
+
+/// Formats an image as embedded, or an album as a link.
+fn format_upload(upload: &Upload, format: LinkFormat, thumbnail: Option<ThumbnailSize>) -> String {
+    let title = upload.title.as_deref().unwrap_or(&upload.id);
+    match upload.kind {
+        UploadKind::Image => {
+            let thumbnail = thumbnail.map(|size| thumbnail_link(&upload.id, size));
+            links::image(format, &upload.link, title, thumbnail.as_deref())
+        }
+        UploadKind::Album => links::link(format, &upload.link, title),
+    }
+}
+
+/// Prints the API credits which remain, if the API has reported them.
+fn print_rate_limits(limits: &RateLimits) {
+    let credits: Vec<String> = [
+        ("client ID", limits.client_remaining, limits.client_limit),
+        ("user", limits.user_remaining, limits.user_limit),
+    ]
+    .into_iter()
+    .filter_map(|(name, remaining, limit)| match (remaining?, limit) {
+        (remaining, Some(limit)) => Some(format!("{}/{} for the {}", remaining, limit, name)),
+        (remaining, None) => Some(format!("{} for the {}", remaining, name)),
+    })
+    .collect();
+    if !credits.is_empty() {
+        println!("API credits remaining: {}", credits.join(", "));
+    }
+}
+
+/// Prints the number of failures in each category, and whether retrying is likely to help.
+fn print_failure_summary(errors: &[anyhow::Error]) {
+    let mut categories = BTreeMap::new();
+    for error in errors {
+        *categories.entry(categorize(error)).or_insert(0) += 1;
+    }
+    if categories.is_empty() {
+        return;
+    }
+
+    let summary = categories
+        .iter()
+        .map(|(category, count)| format!("{} {}", count, category))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("Failures: {}.", summary);
+    if categories.keys().all(|category| category.is_retryable()) {
+        println!("Retrying is likely to help.");
+    } else if categories.keys().any(|category| category.is_retryable()) {
+        println!("Retrying may help for some failures.");
     } else {
-        Ok(())
+        println!("Retrying is unlikely to help.");
+    }
+}
+
+/// Checks the files listed in the manifest at `path`, by their checksums if there is a
+/// SHA256SUMS file next to it.
+fn verify_manifest(path: &Path) -> Result<torrent::Verification> {
+    let manifest = upload::Manifest::load(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let checksums = if dir.join(torrent::CHECKSUMS_FILENAME).is_file() {
+        torrent::verify_checksums(dir)?
+    } else {
+        torrent::Verification::default()
+    };
+    let mut verification = torrent::Verification::default();
+    for image in manifest.all_images() {
+        let name = image.path.strip_prefix(dir).unwrap_or(&image.path);
+        let name = name.to_string_lossy().into_owned();
+        if !image.path.is_file() {
+            verification.missing.push(name);
+        } else if checksums.mismatched.contains(&name) {
+            verification.mismatched.push(name);
+        } else {
+            verification.verified += 1;
+        }
+    }
+    Ok(verification)
+}
+
+/// Prints the problems found by `verify` or `verify-manifest`, returning the exit code.
+fn report_verification(verification: &torrent::Verification) -> i32 {
+    for name in &verification.missing {
+        println!("Missing: {}", name);
+    }
+    for name in &verification.mismatched {
+        println!("Mismatched: {}", name);
+    }
+    println!(
+        "{} files verified, {} missing, {} mismatched",
+        verification.verified,
+        verification.missing.len(),
+        verification.mismatched.len()
+    );
+    if !verification.mismatched.is_empty() {
+        EXIT_HASH_MISMATCH
+    } else if !verification.missing.is_empty() {
+        EXIT_MISSING_FILES
+    } else {
+        0
+    }
+}
+
+/// Stores the client ID and secret in the keyring, for refreshing account tokens later.
+fn store_client(client_id: &str, client_secret: &str) {
+    let stored = keyring::set(CLIENT_ID_KEY, client_id)
+        .and_then(|_| keyring::set(CLIENT_SECRET_KEY, client_secret));
+    if let Err(e) = stored {
+        println!("Unable to store the client ID in the keyring: {:#}", e);
+    }
+}
+
+/// Returns the access token of the account called `name`, or the default account. Expired
+/// tokens are refreshed first if the client secret is known.
+async fn account_access_token(
+    api: &ApiClient,
+    client_id: &str,
+    client_secret: impl FnOnce() -> Option<String>,
+    accounts: &mut Accounts,
+    name: Option<&str>,
+) -> Result<Option<String>> {
+    let Some((name, account)) = accounts.credentials(name)? else {
+        return Ok(None);
+    };
+    // The client secret is only looked up once the tokens need refreshing.
+    let Some((refresh_token, client_secret)) = account
+        .refresh_token
+        .clone()
+        .filter(|_| account.is_expired())
+        .and_then(|refresh_token| Some((refresh_token, client_secret()?)))
+    else {
+        return Ok(Some(account.access_token));
+    };
+
+    let grant = OAuthGrant::RefreshToken(&refresh_token);
+    match fetch_oauth_tokens(api, client_id, &client_secret, grant).await {
+        Ok(tokens) => {
+            let mut refreshed = Account::from_tokens(tokens);
+            refreshed.username = refreshed.username.or_else(|| account.username.clone());
+            let access_token = refreshed.access_token.clone();
+            accounts.accounts.insert(name, refreshed);
+            accounts.save()?;
+            Ok(Some(access_token))
+        }
+        Err(e) => {
+            println!("Unable to refresh the account's access token: {:#}", e);
+            Ok(Some(account.access_token))
+        }
     }
 }
 
-async fn download_file(
-    client: &Client,
-    pb: &ProgressBar,
-    download_url: String,
-    time_since_epoch: i64,
-    destination: &PathBuf,
-    temp_destination: &PathBuf,
+/// API credits known to remain for the client ID and user, from the headers of earlier
+/// responses.
+fn remaining_credits(api: &ApiClient) -> Option<u64> {
+    let limits = api.rate_limits();
+    [limits.client_remaining, limits.user_remaining]
+        .into_iter()
+        .flatten()
+        .min()
+}
+
+/// Checks that at least `min_credits` API credits remain, optionally waiting until they do.
+async fn ensure_credits(
+    api: &ApiClient,
+    auth: &Auth,
+    min_credits: u64,
+    wait: bool,
+    job: &Job,
 ) -> Result<()> {
-    let download_url = reqwest::Url::parse(&download_url)
-        .with_context(|| format!("Failed to parse URL: {}", download_url))?;
-    let metadata = tokio::fs::metadata(destination.clone()).await;
-
-    // Exit early if destination already exists.
-    if metadata.is_ok() {
-        return if metadata.unwrap().is_file() {
-            Ok(())
+    loop {
+        let response = fetch_credits(api, auth).await?;
+        let credits = response.data.ok_or_else(|| {
+            anyhow!(
+                "Failed to get API credits with status code: {}",
+                response.status
+            )
+        })?;
+        let remaining = credits.client_remaining.min(credits.user_remaining);
+        if remaining >= min_credits {
+            return Ok(());
+        }
+        if !wait {
+            return Err(anyhow!(
+                "Only {} API credits remain, fewer than the minimum of {}",
+                remaining,
+                min_credits
+            ));
+        }
+
+        // User credits reset hourly, client credits daily; recheck at the next user reset.
+        let now = unix_now();
+        let wait_secs = if credits.user_remaining < min_credits && credits.user_reset > now {
+            (credits.user_reset - now) as u64 + 1
         } else {
-            Err(anyhow!("Found existing directory"))
+            15 * 60
         };
+        println!(
+            "Only {} API credits remain, waiting {} minutes for more.",
+            remaining,
+            wait_secs.div_ceil(60)
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(wait_secs)) => {}
+            _ = job.cancel.cancelled() => return Err(anyhow!("Cancelled while waiting for API credits")),
+        }
     }
+}
 
-    match metadata.unwrap_err().kind() {
-        std::io::ErrorKind::NotFound => {
-            // Download file.
-            let mut file = tokio::fs::File::create(temp_destination).await?;
-            let mut res = client.get(download_url).send().await?;
-            while let Some(chunk) = res.chunk().await?.as_deref() {
-                pb.inc(chunk.len() as u64);
-                file.write_all(chunk).await?
-            }
+/// Asks whether to continue on the terminal.
+async fn confirm(message: &str) -> Result<bool> {
+    print!("{}. Continue? [y/N] ", message);
+    std::io::stdout().flush()?;
+    let answer = tokio::task::spawn_blocking(|| {
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).map(|_| answer)
+    })
+    .await??;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
 
-            // Rename file.
-            tokio::fs::rename(temp_destination, destination)
-                .await
-                .with_context(|| "Unable to move temporary file")?;
+/// Checks that downloading `plan` keeps the job within --max-total-size and --max-files, and
+/// confirms albums with more files than --confirm-above.
+async fn check_limits(args: &Cli, job: &Job, title: &str, plan: &DownloadPlan) -> Result<()> {
+    let (planned_bytes, planned_files) = job.planned();
+    let total_size = planned_bytes + plan.total_size();
+    let total_files = planned_files + plan.files.len();
 
-            filetime::set_file_mtime(
-                destination,
-                filetime::FileTime::from_unix_time(time_since_epoch, 0),
-            )
-            .with_context(|| "Could not set file modified time")?;
+    let mut exceeded = vec![];
+    if let Some(max_total_size) = args.max_total_size.filter(|max| total_size > *max) {
+        exceeded.push(format!(
+            "{} exceeds --max-total-size of {}",
+            format_size(total_size, DECIMAL),
+            format_size(max_total_size, DECIMAL)
+        ));
+    }
+    if let Some(max_files) = args.max_files.filter(|max| total_files > *max) {
+        exceeded.push(format!(
+            "{} files exceeds --max-files of {}",
+            total_files, max_files
+        ));
+    }
+
+    if !exceeded.is_empty() {
+        let message = format!("Downloading {}: {}", title, exceeded.join(", "));
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!(message));
+        }
+        if !confirm(&message).await? {
+            return Err(anyhow!("Stopped, as the job would be too large"));
+        }
+    }
 
-            Ok(())
+    if plan.files.len() > args.confirm_above && !args.yes {
+        let message = format!(
+            "{} has {} files, more than --confirm-above of {}",
+            title,
+            plan.files.len(),
+            args.confirm_above
+        );
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!("{}; pass --yes to download it anyway", message));
         }
-        std::io::ErrorKind::PermissionDenied => {
-            Err(anyhow!("Permission denied when retrieving file metadata",))
+        if !confirm(&message).await? {
+            return Err(anyhow!("Stopped, as the album has too many files"));
         }
-        _ => Err(anyhow!("Unable to retrieve file metadata")),
     }
+
+    job.add_planned(plan.total_size(), plan.files.len());
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
+fn state_db_path(args: &Cli) -> Result<PathBuf> {
+    args.state_db
+        .clone()
+        .or_else(|| paths::data_dir().map(|dir| dir.join("state.json")))
+        .ok_or_else(|| anyhow!("Unable to determine state database location"))
+}
 
-    let client_id = args
-        .imgur_client_id
-        .unwrap_or_else(|| std::env::var("IMGUR_CLIENT_ID").unwrap_or_else(|_| "".to_owned()));
-    let client = Client::builder().build()?;
-    let is_display_details_only = args.details;
-    let album_id = get_album_id(&args.album_id);
-
-    let response = client
-        .get(format!("https://api.imgur.com/3/album/{}", album_id))
-        .header("Authorization", format!("Client-ID {}", client_id))
-        .send()
-        .await?
-        .json::<ImgurResponse<ImgurAlbum>>()
-        .await?;
+/// The directory `album` is downloaded to, as a relative path unless `destination` is absolute.
+fn album_destination(args: &Cli, destination: &Destination, album: &ImgurAlbum) -> Result<PathBuf> {
+    let directory_name = match (&args.directory_template, args.layout) {
+        (Some(template), _) => render_directory(template, album),
+        (None, Layout::ByUploader) => render_directory(
+            &Template::parse_directory(BY_UPLOADER_DIRECTORY_TEMPLATE)?,
+            album,
+        ),
+        (None, Layout::Flat) => album_directory_name(album.title.as_deref().unwrap_or(&album.id)),
+    };
+    Ok(match destination {
+        Destination::Named => directory_name,
+        Destination::Under(parent) => parent.join(directory_name),
+        Destination::Exact(destination) => destination.clone(),
+    })
+}
 
-    if let Some(data) = response.data {
-        let title = data.title.unwrap_or_else(|| data.id);
-        println!("Album: {}", title);
+/// Prints an album's title, number of files and size, and with --details, how many of its files
+/// the state database records as downloaded.
+fn print_album_details(args: &Cli, album: &ImgurAlbum, destination: &Path) -> Result<()> {
+    if args.json {
+        let mut details = serde_json::to_value(album)?;
+        details["downloaded_files"] = downloaded_files(args, album)?.into();
+        details["destination"] = destination.to_string_lossy().into();
+        println!("{}", serde_json::to_string_pretty(&details)?);
+        return Ok(());
+    }
 
-        let num_files = data.images.len();
-        println!("Number of files: {}", num_files);
+    let title = album.title.as_deref().unwrap_or(&album.id);
+    println!("Album: {}", title);
+    if let Some(description) = album
+        .description
+        .as_deref()
+        .filter(|d| !d.trim().is_empty())
+    {
+        println!("Description: {}", description.trim_end());
+    }
 
-        let album_size: u64 = data.images.iter().map(|image| image.size).sum();
-        println!("Total size: {}", format_size(album_size, DECIMAL));
+    let num_files = album.images.len();
+    println!("Number of files: {}", num_files);
 
-        if is_display_details_only || num_files == 0 {
+    let album_size: u64 = album.images.iter().map(|image| image.size).sum();
+    println!("Total size: {}", format_size(album_size, DECIMAL));
+    println!("Directory: {}", destination.display());
+
+    if args.details {
+        let downloaded = downloaded_files(args, album)?;
+        println!("Downloaded: {} of {} files", downloaded, num_files);
+    }
+    if args.list {
+        print_image_list(album);
+    }
+    Ok(())
+}
+
+/// Prints a table of the album's images.
+fn print_image_list(album: &ImgurAlbum) {
+    const MAX_TITLE: usize = 40;
+    let rows: Vec<[String; 7]> = album
+        .images
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let title = image.title.as_deref().unwrap_or("").replace('\n', " ");
+            let title = if title.chars().count() > MAX_TITLE {
+                let truncated: String = title.chars().take(MAX_TITLE - 1).collect();
+                format!("{}…", truncated)
+            } else {
+                title
+            };
+            [
+                (index + 1).to_string(),
+                image.id.clone(),
+                title,
+                get_media_type(&image.content_type, &image.link),
+                format_size(image.size, DECIMAL),
+                if image.width == 0 {
+                    "?".to_owned()
+                } else {
+                    format!("{}x{}", image.width, image.height)
+                },
+                DateTime::from_unix(image.datetime).format("%Y-%m-%d"),
+            ]
+        })
+        .collect();
+    let header = ["#", "ID", "Title", "Type", "Size", "Resolution", "Uploaded"].map(String::from);
+    let mut widths = [0; 7];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    println!();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// The number of the album's files recorded in the state database.
+fn downloaded_files(args: &Cli, album: &ImgurAlbum) -> Result<usize> {
+    let state_db = StateDb::load(&state_db_path(args)?)?;
+    Ok(album
+        .images
+        .iter()
+        .filter(|image| state_db.existing(&image.id).is_some())
+        .count())
+}
+
+/// The ID of the album or gallery post `input` refers to.
+fn get_post_id(input: &str) -> &str {
+    parse_link(input).map_or_else(|| get_album_id(input), |link| link.id())
+}
+
+/// Reports the files of `album_id` renamed by `--fix-extensions`.
+fn report_renames(job: &Job, album_id: &str, renamed: Vec<(String, String)>) {
+    for (old, new) in renamed {
+        println!("Renamed {} to {}, as that is what it contains", old, new);
+        job.audit(
+            "rename",
+            json!({ "album": album_id, "from": old, "to": new }),
+        );
+    }
+}
+
+/// Saves the text of a post, which is often lost in its own right, to `post.md`.
+fn write_post_body(dir: &Path, album: &ImgurAlbum) -> Result<()> {
+    let Some(description) = album
+        .description
+        .as_deref()
+        .filter(|d| !d.trim().is_empty())
+    else {
+        return Ok(());
+    };
+    let title = album.title.as_deref().unwrap_or(&album.id);
+    let body = format!(
+        "# {}\n\n{}\n\n<https://imgur.com/a/{}>\n",
+        title,
+        description.trim_end(),
+        album.id
+    );
+    let path = dir.join("post.md");
+    std::fs::write(&path, body).with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// ID and fetched metadata of an album.
+type Metadata = Result<(String, ImgurResponse<ImgurAlbum>)>;
+
+/// Fetches the metadata of the album, gallery post or image `input`, waiting out rate limits.
+async fn fetch_metadata(
+    api: &ApiClient,
+    auth: &Auth,
+    args: &Cli,
+    job: &Job,
+    input: &str,
+) -> Metadata {
+    let input = unshorten::resolve(input, args.max_redirects, &args.shortener_host).await?;
+    let album_id = get_post_id(&input);
+
+    let mut attempt = 0;
+    let response = loop {
+        let response = match parse_link(&input) {
+            Some(ImgurLink::GalleryPost(post_id)) => fetch_gallery_post(api, auth, post_id).await,
+            Some(ImgurLink::Image(image_id)) => fetch_image(api, auth, image_id).await,
+            _ => fetch_album(api, auth, album_id).await,
+        };
+        // Wait as long as the API asks when rate limited, rather than failing the album.
+        match response.as_ref().err().and_then(retry_after) {
+            Some(retry_after)
+                if attempt < args.retries_on_429 && retry_after <= args.max_rate_limit_wait =>
+            {
+                attempt += 1;
+                job.log(format!(
+                    "The Imgur API is rate limiting requests, waiting {}",
+                    format_duration(retry_after)
+                ));
+                tokio::select! {
+                    _ = tokio::time::sleep(retry_after) => {},
+                    _ = job.cancel.cancelled() => return Err(Cancelled.into()),
+                };
+            }
+            _ => break response,
+        }
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            job.audit(
+                "metadata",
+                json!({ "album": album_id, "error": format!("{:#}", e) }),
+            );
+            return Err(e);
+        }
+    };
+    job.audit(
+        "metadata",
+        json!({
+            "album": album_id,
+            "status": response.status,
+            "files": response.data.as_ref().map(|data| data.images.len()),
+        }),
+    );
+    Ok((album_id.to_owned(), response))
+}
+
+/// Downloads `album`, using its `metadata` if it has already been fetched.
+async fn download_album(
+    api: &ApiClient,
+    auth: &Auth,
+    args: &Cli,
+    config: &Config,
+    job: &Job,
+    album: QueuedAlbum,
+    metadata: Option<Metadata>,
+) -> Result<()> {
+    let metadata_cache = args.metadata_cache.clone().or_else(cache::default_dir);
+    if args.offline {
+        let album_id = get_post_id(&album.input);
+        let cache_dir =
+            metadata_cache.ok_or_else(|| anyhow!("Unable to determine metadata cache location"))?;
+        let data = cache::load(&cache_dir, album_id)?;
+        let destination = album_destination(args, &album.destination, &data)?;
+        print_album_details(args, &data, &destination)?;
+        return Ok(());
+    }
+
+    let (album_id, response) = match metadata {
+        Some(metadata) => metadata?,
+        None => fetch_metadata(api, auth, args, job, &album.input).await?,
+    };
+    let album_id = album_id.as_str();
+
+    if let Some(data) = response.data {
+        if let Some(cache_dir) = &metadata_cache {
+            if let Err(e) = cache::store(cache_dir, &data) {
+                println!("Unable to cache album metadata: {:#}", e);
+            }
+        }
+
+        let title = data.title.clone().unwrap_or_else(|| data.id.clone());
+        let destination = album_destination(args, &album.destination, &data)?;
+        print_album_details(args, &data, &destination)?;
+        if args.details {
+            return Ok(());
+        }
+        if data.images.is_empty() {
+            job.audit("empty", json!({ "album": album_id }));
+            match args.empty_album {
+                EmptyAlbum::Error => return Err(anyhow!("Album {} has no images", album_id)),
+                EmptyAlbum::Warn => println!("Album {} has no images, skipping.", album_id),
+                EmptyAlbum::Ok => {}
+            }
             return Ok(());
         }
 
-        let destination = args.output.unwrap_or_else(|| {
-            PathBuf::from(
-                title
-                    .clone()
-                    .replace("\n", " ")
-                    .replace(" : ", " - ")
-                    .replace(": ", " - ")
-                    .replace(":", "-")
-                    .replace("/", "-"),
-            )
+        let mut builder = DownloadPlan::builder(&data)
+            .destination(destination)
+            .shared_parallelism(&job.parallelism)
+            .overwrite(args.on_conflict)
+            .partial(if args.keep_partial {
+                PartialPolicy::Keep
+            } else if args.clean_partial {
+                PartialPolicy::Clean
+            } else {
+                PartialPolicy::Auto
+            })
+            .retries(args.retries)
+            .rate_limit_retries(args.retries_on_429)
+            .max_rate_limit_wait(args.max_rate_limit_wait);
+        if !job.queued().is_empty() || job.status().completed_albums > 0 {
+            builder = builder.label(&title);
+        }
+        if let Some(template) = &args.filename_template {
+            builder = builder.template(template);
+        }
+        for mirror in &args.mirror {
+            builder = builder.mirror(mirror);
+        }
+        if !args.allow_external_hosts {
+            let allowed_hosts: Vec<String> = args
+                .allow_host
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect();
+            builder = builder.filter(move |media| {
+                let host = reqwest::Url::parse(&media.link)
+                    .ok()
+                    .and_then(|url| Some(url.host_str()?.to_owned()));
+                let allowed = host.as_deref().is_some_and(|host| {
+                    is_imgur_host(host)
+                        || allowed_hosts.iter().any(|allowed| {
+                            host == allowed
+                                || host
+                                    .strip_suffix(allowed.as_str())
+                                    .is_some_and(|prefix| prefix.ends_with('.'))
+                        })
+                });
+                if !allowed {
+                    println!(
+                        "Skipping {}, which is hosted outside Imgur: {}",
+                        media.id, media.link
+                    );
+                }
+                allowed
+            });
+        }
+        // Media with unknown dimensions is always included.
+        if let Some(min_width) = args.min_width {
+            builder = builder.filter(move |media| media.width == 0 || media.width >= min_width);
+        }
+        if let Some(min_height) = args.min_height {
+            builder = builder.filter(move |media| media.height == 0 || media.height >= min_height);
+        }
+        if let Some(max_dimension) = args.max_dimension {
+            builder = builder.filter(move |media| media.width.max(media.height) <= max_dimension);
+        }
+        if args.only_animated {
+            builder = builder.filter(|media| media.animated);
+        }
+        if args.no_animated {
+            builder = builder.filter(|media| !media.animated);
+        }
+        if args.no_video {
+            builder = builder.filter(|media| !media.content_type.starts_with("video/"));
+        }
+        if let Some(size) = args.downscale {
+            builder = builder.downscale(size);
+        }
+        if let Some(limit_rate) = args.limit_rate {
+            builder = builder.max_bytes_per_sec(limit_rate);
+        }
+        if let Some(max_buffer) = args.max_buffer {
+            builder = builder.max_buffered_bytes(max_buffer);
+        }
+        let mut plan = builder.build()?;
+        plan.files.retain(|file| {
+            let matches = |pattern: &String| glob_matches(pattern, &file.filename);
+            (args.include.is_empty() || args.include.iter().any(matches))
+                && !args.exclude.iter().any(matches)
         });
+        if args.probe {
+            for (file, e) in plan.probe(api.http()).await {
+                println!("Unavailable: {} ({}): {}", file.filename, file.url, e);
+                job.audit(
+                    "unavailable",
+                    json!({
+                        "album": album_id,
+                        "id": file.id,
+                        "url": file.url,
+                        "error": format!("{:#}", e),
+                    }),
+                );
+            }
+        }
+        let planned_files = plan.files.len();
+        // Files downloaded by an earlier run may also have the wrong extension.
+        if args.fix_extensions {
+            report_renames(job, album_id, plan.fix_extensions()?);
+        }
 
-        prepare_directory(destination.clone()).await?;
-
-        let width = {
-            let mut width = num_files as i32;
-            let mut count = 0;
-            while width > 0 {
-                width /= 10;
-                count += 1;
+        // Every download is recorded, so that later runs can deduplicate against it.
+        let mut state_db = StateDb::load(&state_db_path(args)?)?;
+        if let Some(policy) = args.dedup {
+            let reused = state_db.dedup(&mut plan, policy)?;
+            for (file, existing) in &reused {
+                job.audit(
+                    "reuse",
+                    json!({
+                        "album": album_id,
+                        "id": file.id,
+                        "path": plan.destination.join(&file.filename),
+                        "existing": existing,
+                    }),
+                );
             }
-            count
-        };
+            if !reused.is_empty() {
+                println!("Reused {} previously downloaded files.", reused.len());
+            }
+        }
 
-        let media = data.images.iter().enumerate().map(|(index, media)| {
-            let title = media
-                .title
-                .as_ref()
-                .map(|title| format!(" - {}", title))
-                .unwrap_or("".to_string());
-            let description = media
-                .description
-                .as_ref()
-                .map(|description| format!(" - {}", description))
-                .unwrap_or("".to_string());
-            let filename = format!(
-                "{:0>width$} - {}{}{}.{}",
-                index + 1,
-                media.id,
-                title,
-                description,
-                get_media_type(&media.content_type),
-                width = width
-            );
-            let url = media.link.clone();
+        check_limits(args, job, &title, &plan).await?;
 
-            (url, media.size, filename, media.datetime)
+        let _inhibitor = args
+            .inhibit_sleep
+            .then(SleepInhibitor::acquire)
+            .transpose()
+            .with_context(|| "Unable to inhibit sleep")?;
+
+        let wayback = args.save_to_wayback.then(|| {
+            let mut urls = vec![format!("https://imgur.com/a/{}", data.id)];
+            if args.save_images_to_wayback {
+                urls.extend(plan.files.iter().map(|file| file.url.clone()));
+            }
+            tokio::spawn(wayback::save_all(
+                api.http().clone(),
+                urls,
+                archive::Credentials::from_env(),
+            ))
         });
 
-        let m = MultiProgress::new();
-        let sty = ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {msg}")
-        .unwrap()
-        .progress_chars("#>-");
-
-        let errors = stream::iter(media)
-            .map(|(url, download_size, filename, time_since_epoch)| {
-                let pb = m.clone().add(ProgressBar::new(download_size));
-                pb.set_style(sty.clone());
-                pb.set_message(filename.clone());
-                let temp_filename = format!("~!{}", filename);
-
-                let client = client.clone();
-                let destination = destination.clone();
-                let time_since_epoch = time_since_epoch.clone();
-
-                async move {
-                    let temp_path = destination.join(temp_filename);
-                    let path = destination.join(filename.clone());
-
-                    let result =
-                        download_file(&client, &pb, url, time_since_epoch, &path, &temp_path).await;
-                    if result.is_err() {
-                        // TODO: log error?
-                        let _success = tokio::fs::remove_file(temp_path).await.is_ok();
-                    } else {
-                        pb.finish_and_clear();
+        job.start_album(&title);
+        let Execution {
+            errors,
+            not_started,
+        } = plan
+            .execute_with_events(
+                api.http(),
+                &job.progress,
+                &job.cancel,
+                &job.stop,
+                &job.pause,
+                |event| match event {
+                    Event::Progress(progress) => job.update_progress(progress),
+                    Event::FileDone { file, path, result } => {
+                        let mut record = json!({
+                            "album": album_id,
+                            "id": file.id,
+                            "url": file.url,
+                            "path": path,
+                        });
+                        let action = match result {
+                            Ok(FileOutcome::Downloaded) => {
+                                record["bytes"] = std::fs::metadata(path)
+                                    .map_or(file.size, |m| m.len())
+                                    .into();
+                                "download"
+                            }
+                            Ok(FileOutcome::Skipped) => "skip",
+                            Err(e) => {
+                                record["error"] = format!("{:#}", e).into();
+                                record["category"] = categorize(e).to_string().into();
+                                "failure"
+                            }
+                        };
+                        job.audit(action, record);
+                    }
+                    Event::Warning { file, error } => {
+                        job.log(format!("Warning: {}: {:#}", file.filename, error));
+                        job.audit(
+                            "warning",
+                            json!({
+                                "album": album_id,
+                                "id": file.id,
+                                "error": format!("{:#}", error),
+                            }),
+                        );
                     }
+                    Event::HostUnavailable { host, retry_in } => {
+                        job.log(format!(
+                            "{} keeps failing, retrying its files in {}",
+                            host,
+                            format_duration(retry_in)
+                        ));
+                        job.audit("host-unavailable", json!({ "host": host }));
+                    }
+                    Event::HostRecovered(outage) => {
+                        job.log(format!(
+                            "{} recovered after {}, {} files were retried",
+                            outage.host,
+                            format_duration(outage.duration),
+                            outage.retried_files
+                        ));
+                        job.audit(
+                            "host-recovered",
+                            json!({
+                                "host": outage.host,
+                                "seconds": outage.duration.as_secs(),
+                                "retried_files": outage.retried_files,
+                            }),
+                        );
+                    }
+                    Event::Retrying {
+                        file,
+                        error,
+                        attempt,
+                        retry_in,
+                    } => {
+                        job.log(format!(
+                            "Retrying {} in {:.1}s ({}/{}): {:#}",
+                            file.filename,
+                            retry_in.as_secs_f64(),
+                            attempt,
+                            args.retries,
+                            error
+                        ));
+                        job.audit(
+                            "retry",
+                            json!({
+                                "album": album_id,
+                                "id": file.id,
+                                "attempt": attempt,
+                                "error": format!("{:#}", error),
+                            }),
+                        );
+                    }
+                    Event::RateLimited { host, retry_in } => {
+                        job.log(format!(
+                            "{} is rate limiting downloads, waiting {}",
+                            host,
+                            format_duration(retry_in)
+                        ));
+                        job.audit(
+                            "rate-limited",
+                            json!({ "host": host, "seconds": retry_in.as_secs() }),
+                        );
+                    }
+                    Event::HostGaveUp { host } => {
+                        job.log(format!(
+                            "{} has been unavailable for too long, failing its files",
+                            host
+                        ));
+                        job.audit("host-gave-up", json!({ "host": host }));
+                    }
+                },
+            )
+            .await?;
+        job.finish_album();
 
-                    result.with_context(|| format!("Error downloading file {}", filename))
-                }
-            })
-            .buffer_unordered(args.parallelism)
-            .filter_map(|result| async {
-                match result {
-                    Ok(_) => None,
-                    Err(err) => Some(err),
-                }
-            })
-            .collect::<Vec<_>>()
-            .await;
+        if args.fix_extensions {
+            report_renames(job, album_id, plan.fix_extensions()?);
+        }
+
+        if let Err(e) = write_post_body(&plan.destination, &data) {
+            println!("Unable to save the post's text: {:#}", e);
+        }
+
+        state_db.record_plan(&plan);
+        state_db.save()?;
+
+        if args.make_torrent {
+            if errors.is_empty() && not_started.is_empty() && !job.cancel.is_cancelled() {
+                let destination = plan.destination.clone();
+                let trackers = args.torrent_tracker.clone();
+                let torrent = tokio::task::spawn_blocking(move || {
+                    torrent::write_checksums(&destination)?;
+                    torrent::make_torrent(&destination, &trackers)
+                })
+                .await??;
+                println!("Created torrent: {}", torrent.display());
+            } else {
+                println!("Not creating torrent, as the album was not fully downloaded.");
+            }
+        }
+
+        if let Some(name) = &args.pipeline {
+            let steps = config.pipeline(name)?.to_vec();
+            let plan = plan.clone();
+            let data = data.clone();
+            tokio::task::spawn_blocking(move || pipeline::run(&steps, &plan, &data))
+                .await?
+                .with_context(|| format!("Pipeline {} failed", name))?;
+        }
 
         println!(
-            "Downloaded {}/{} files.\n",
-            num_files - errors.len(),
-            num_files
+            "Downloaded {}/{} files to {}\n",
+            planned_files - errors.len() - not_started.len(),
+            planned_files,
+            std::fs::canonicalize(&plan.destination)
+                .unwrap_or_else(|_| plan.destination.clone())
+                .display()
         );
-        for error in errors {
+        if !not_started.is_empty() {
+            println!("{} files were not started.\n", not_started.len());
+        }
+        for error in &errors {
             println!("{:?}\n", error);
         }
+        print_failure_summary(&errors);
+        print_rate_limits(&api.rate_limits());
+
+        if let Some(wayback) = wayback {
+            println!("Waiting for Wayback Machine submissions to finish.");
+            let failed = wayback.await?;
+            for (url, e) in &failed {
+                println!("Unable to save {} to the Wayback Machine: {:#}", url, e);
+                job.audit(
+                    "wayback",
+                    json!({ "album": album_id, "url": url, "error": format!("{:#}", e) }),
+                );
+            }
+        }
 
         Ok(())
     } else {
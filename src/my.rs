@@ -0,0 +1,149 @@
+//! `imgurs my` and `imgurs account`: commands acting on the authenticated user's account.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use serde_json::{json, Value};
+
+use imgurs::api::{
+    all_pages, fetch_account_albums, fetch_account_blocks, fetch_account_favorites,
+    fetch_account_settings, fetch_notifications, ApiClient, Auth, ImgurAlbum, ImgurGalleryItem,
+    ImgurNotification, ImgurNotifications, ImgurResponse,
+};
+use imgurs::date::DateTime;
+
+fn require_access_token(auth: &Auth) -> Result<()> {
+    if !matches!(auth, Auth::AccessToken(_)) {
+        return Err(anyhow!(
+            "An access token is required, see --access-token or $IMGUR_ACCESS_TOKEN"
+        ));
+    }
+    Ok(())
+}
+
+fn data<T>(response: ImgurResponse<T>, what: &str) -> Result<T> {
+    response.data.ok_or_else(|| {
+        anyhow!(
+            "Failed to get {} with status code: {}",
+            what,
+            response.status
+        )
+    })
+}
+
+/// Fetches all of the authenticated user's albums, without their images.
+pub async fn albums(api: &ApiClient, auth: &Auth) -> Result<Vec<ImgurAlbum>> {
+    require_access_token(auth)?;
+    all_pages("albums", |page| fetch_account_albums(api, auth, page)).await
+}
+
+/// Fetches all of the authenticated user's favorites, with only some of their albums' images.
+pub async fn favorites(api: &ApiClient, auth: &Auth) -> Result<Vec<ImgurGalleryItem>> {
+    require_access_token(auth)?;
+    all_pages("favorites", |page| fetch_account_favorites(api, auth, page)).await
+}
+
+/// Finds the authenticated user's album whose title contains `query`, ignoring case.
+pub async fn find_album(api: &ApiClient, auth: &Auth, query: &str) -> Result<ImgurAlbum> {
+    let needle = query.to_lowercase();
+    let mut matches: Vec<ImgurAlbum> = albums(api, auth)
+        .await?
+        .into_iter()
+        .filter(|album| {
+            album
+                .title
+                .as_deref()
+                .is_some_and(|title| title.to_lowercase().contains(&needle))
+        })
+        .collect();
+
+    match matches.len() {
+        0 => Err(anyhow!("No album has a title containing \"{}\"", query)),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            for album in &matches {
+                println!("{}: {}", album.id, album.title.as_deref().unwrap_or(""));
+            }
+            Err(anyhow!(
+                "{} albums have a title containing \"{}\", pass the album ID instead",
+                matches.len(),
+                query
+            ))
+        }
+    }
+}
+
+/// Exports the authenticated user's settings, which include their notification preferences, and
+/// blocked users, for backing up what is not media.
+pub async fn export_settings(api: &ApiClient, auth: &Auth) -> Result<Value> {
+    require_access_token(auth)?;
+    let settings = data(fetch_account_settings(api, auth).await?, "account settings")?;
+    let blocked = data(fetch_account_blocks(api, auth).await?, "blocked users")?;
+    Ok(json!({ "settings": settings, "blocked": blocked }))
+}
+
+/// Fetches the authenticated user's notifications, including those already viewed.
+pub async fn notifications(api: &ApiClient, auth: &Auth) -> Result<ImgurNotifications> {
+    require_access_token(auth)?;
+    data(fetch_notifications(api, auth).await?, "notifications")
+}
+
+fn notification_line(notification: &ImgurNotification) -> String {
+    let content = &notification.content;
+    let who = content
+        .author
+        .as_deref()
+        .or(content.from.as_deref())
+        .unwrap_or("unknown");
+    let text = content
+        .comment
+        .as_deref()
+        .or(content.last_message.as_deref())
+        .unwrap_or("");
+    let date = DateTime::from_unix(content.datetime).format("%Y-%m-%d %H:%M");
+    let mut line = format!("{} {}: {}", date, who, text.replace('\n', " "));
+    if let Some(post) = &content.image_id {
+        line.push_str(&format!(" (<https://imgur.com/gallery/{}>)", post));
+    }
+    line
+}
+
+/// Prints notifications, one per line.
+pub fn print_notifications(notifications: &ImgurNotifications) {
+    for notification in notifications.replies.iter().chain(&notifications.messages) {
+        println!("{}", notification_line(notification));
+    }
+}
+
+/// Archives notifications to `notifications.json`, with every field returned by the API, and a
+/// readable `notifications.md` in `dir`.
+pub fn export_notifications(dir: &Path, notifications: &ImgurNotifications) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+
+    let mut markdown = String::new();
+    for (heading, list) in [
+        ("Replies and mentions", &notifications.replies),
+        ("Messages", &notifications.messages),
+    ] {
+        markdown.push_str(&format!("# {}\n\n", heading));
+        for notification in list {
+            markdown.push_str(&format!("- {}\n", notification_line(notification)));
+        }
+        markdown.push('\n');
+    }
+
+    for (name, contents) in [
+        (
+            "notifications.json",
+            serde_json::to_vec_pretty(notifications)?,
+        ),
+        ("notifications.md", markdown.into_bytes()),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+    }
+    Ok(())
+}
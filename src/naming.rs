@@ -0,0 +1,559 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::api::{ImgurAlbum, ImgurTag};
+use crate::date::DateTime;
+
+/// Filename template used when none is given.
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{index} - {id}[ - {title}][ - {description}].{ext}";
+
+/// Placeholders which can be used in a filename template. `{date}` is the media's upload date,
+/// which takes a strftime format like `{album_date}`.
+pub const PLACEHOLDERS: &[&str] = &[
+    "index",
+    "id",
+    "title",
+    "description",
+    "tags",
+    "date",
+    "album_id",
+    "ext",
+];
+
+/// Placeholders which can be used in an album directory template. `{title}` falls back to the
+/// album's ID, and `{album_date}` takes a strftime format, e.g. `{album_date:%Y/%m}`.
+pub const DIRECTORY_PLACEHOLDERS: &[&str] = &["id", "title", "uploader", "album_date", "tags"];
+
+/// Directory template nesting albums under their uploader's username.
+pub const BY_UPLOADER_DIRECTORY_TEMPLATE: &str = "[{uploader}/]{title}";
+
+/// Directory template of gallery posts downloaded from a gallery section, whose titles often
+/// repeat.
+pub const POST_DIRECTORY_TEMPLATE: &str = "{title} ({id})";
+
+/// Placeholders which take a format after a colon.
+const DATE_PLACEHOLDERS: &[&str] = &["album_date", "date"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// A placeholder name and its format, if any.
+    Placeholder(String, Option<String>),
+    /// Segments which are dropped entirely if any placeholder within them has no value.
+    Optional(Vec<Segment>),
+}
+
+/// A parsed filename template.
+///
+/// `{name}` is replaced with the value of the placeholder `name`, and `{name:format}` with its value
+/// formatted by `format`. Text in square brackets is only included if every placeholder inside has
+/// a value, e.g. `[ - {title}]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parses a filename template.
+    pub fn parse(template: &str) -> Result<Template> {
+        Template::parse_with_placeholders(template, PLACEHOLDERS)
+    }
+
+    /// Parses an album directory template, in which `/` separates nested directories.
+    pub fn parse_directory(template: &str) -> Result<Template> {
+        Template::parse_with_placeholders(template, DIRECTORY_PLACEHOLDERS)
+    }
+
+    fn parse_with_placeholders(template: &str, placeholders: &[&str]) -> Result<Template> {
+        let mut segments = vec![];
+        let mut optional: Option<Vec<Segment>> = None;
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            let current = optional.as_mut().unwrap_or(&mut segments);
+            match c {
+                '{' => {
+                    if !literal.is_empty() {
+                        current.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow!("Unclosed placeholder in template")),
+                        }
+                    }
+                    let (name, format) = match name.split_once(':') {
+                        Some((name, format)) => (name.to_owned(), Some(format.to_owned())),
+                        None => (name, None),
+                    };
+                    if !placeholders.contains(&name.as_str()) {
+                        return Err(anyhow!("Unknown placeholder in template: {{{}}}", name));
+                    }
+                    if format.is_some() && !DATE_PLACEHOLDERS.contains(&name.as_str()) {
+                        return Err(anyhow!("Placeholder {{{}}} does not take a format", name));
+                    }
+                    current.push(Segment::Placeholder(name, format));
+                }
+                '}' => return Err(anyhow!("Unmatched '}}' in template")),
+                '[' => {
+                    if optional.is_some() {
+                        return Err(anyhow!("Nested optional segments are not supported"));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    optional = Some(vec![]);
+                }
+                ']' => {
+                    let mut inner = optional
+                        .take()
+                        .ok_or_else(|| anyhow!("Unmatched ']' in template"))?;
+                    if !literal.is_empty() {
+                        inner.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Optional(inner));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if optional.is_some() {
+            return Err(anyhow!("Unclosed optional segment in template"));
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Renders the template, looking up placeholder values with `fields`.
+    pub fn render(&self, fields: impl Fn(&str) -> Option<String>) -> String {
+        self.render_formatted(|name, _| fields(name))
+    }
+
+    /// Renders the template, looking up placeholder values with `fields`, which is also given each
+    /// placeholder's format.
+    pub fn render_formatted(
+        &self,
+        fields: impl Fn(&str, Option<&str>) -> Option<String>,
+    ) -> String {
+        let mut output = String::new();
+        render_segments(&self.segments, &fields, &mut output);
+        output
+    }
+}
+
+/// Renders an album directory template into a relative path, in which every component is made
+/// safe with [`safe_filename`].
+pub fn render_directory(template: &Template, album: &ImgurAlbum) -> PathBuf {
+    let rendered = template.render_formatted(|name, format| match name {
+        "id" => Some(safe_filename(&album.id)),
+        "title" => Some(safe_filename(album.title.as_deref().unwrap_or(&album.id))),
+        "uploader" => Some(safe_filename(album.account_url.as_deref()?)),
+        "tags" => joined_tags(&album.tags),
+        "album_date" => {
+            let date = DateTime::from_unix(album.datetime);
+            Some(match format {
+                Some(format) => date.format(format),
+                None => date.format("%Y-%m-%d"),
+            })
+        }
+        _ => None,
+    });
+    let path: PathBuf = rendered
+        .split(['/', '\\'])
+        .filter(|component| !component.trim().is_empty())
+        .map(safe_filename)
+        .collect();
+    // Never download directly into the parent directory.
+    if path.as_os_str().is_empty() {
+        PathBuf::from(safe_filename(&album.id))
+    } else {
+        path
+    }
+}
+
+/// The value of the `{tags}` placeholder: the names of `tags`, each made safe with
+/// [`safe_filename`] and joined with commas, or `None` if there are no tags.
+pub fn joined_tags(tags: &[ImgurTag]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    let names: Vec<String> = tags.iter().map(|tag| safe_filename(&tag.name)).collect();
+    Some(names.join(","))
+}
+
+impl Default for Template {
+    fn default() -> Template {
+        Template::parse(DEFAULT_FILENAME_TEMPLATE).unwrap()
+    }
+}
+
+/// Makes `name` safe to use as a single path component.
+///
+/// Path separators and drive letter colons are replaced, as are other characters Windows does
+/// not allow. Control characters and trailing dots and spaces are removed, and leading `.` or `-`
+/// and Windows device names such as `CON` are escaped, so the result can never refer to another
+/// directory or device, or be mistaken for a command-line option.
+pub fn safe_filename(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter_map(|c| match c {
+            '/' | '\\' | ':' => Some('-'),
+            '*' | '?' | '"' | '<' | '>' | '|' => Some('_'),
+            '\n' | '\r' | '\t' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+    let name = name.trim().trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        "_".to_owned()
+    } else if name.starts_with(['.', '-']) || is_device_name(name) {
+        format!("_{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Whether Windows would open a device rather than a file called `name`, as it does for `NUL`
+/// or `com1.txt`.
+fn is_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => {
+            let number = upper
+                .strip_prefix("COM")
+                .or_else(|| upper.strip_prefix("LPT"));
+            matches!(
+                number,
+                Some("0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "¹" | "²" | "³")
+            )
+        }
+    }
+}
+
+/// Longest filename, in bytes, which media is saved under. Most filesystems allow 255 bytes,
+/// which leaves room for the `~!` prefix of temporary files and `.txt` sidecars.
+pub const MAX_FILENAME_LENGTH: usize = 240;
+
+/// Shortens `name` to at most [`MAX_FILENAME_LENGTH`] bytes, cutting the text before `.{ext}`
+/// and marking the cut with `…`.
+pub fn shorten_filename(name: &str, ext: &str) -> String {
+    if name.len() <= MAX_FILENAME_LENGTH {
+        return name.to_owned();
+    }
+    let suffix = format!("….{}", ext);
+    let stem = name.strip_suffix(&format!(".{}", ext)).unwrap_or(name);
+    let mut end = MAX_FILENAME_LENGTH
+        .saturating_sub(suffix.len())
+        .min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end(), suffix)
+}
+
+/// Whether `name` matches the shell-style glob `pattern`, where `*` matches any text, `?` any
+/// single character and `[...]` any character in a set such as `[abc]`, `[a-z]` or `[!0-9]`.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it is matched up to, for backtracking.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let matched = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern[p..], name[n]).map(|len| p + len),
+            Some(c) => (*c == name[n]).then_some(p + 1),
+            None => None,
+        };
+        match (matched, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against the character class at the start of `pattern`, returning the length of the
+/// class if it matches. Unclosed classes match a literal `[`.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let Some(end) = pattern
+        .iter()
+        .skip(2)
+        .position(|c| *c == ']')
+        .map(|i| i + 2)
+    else {
+        return (c == '[').then_some(1);
+    };
+    let (negated, set) = match pattern[1] {
+        '!' | '^' => (true, &pattern[2..end]),
+        _ => (false, &pattern[1..end]),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(end + 1)
+}
+
+/// Appends the rendered segments to `output`, returning `false` if a placeholder had no value.
+fn render_segments(
+    segments: &[Segment],
+    fields: &impl Fn(&str, Option<&str>) -> Option<String>,
+    output: &mut String,
+) -> bool {
+    let mut complete = true;
+    for segment in segments {
+        match segment {
+            Segment::Literal(literal) => output.push_str(literal),
+            Segment::Placeholder(name, format) => match fields(name, format.as_deref()) {
+                Some(value) => output.push_str(&value),
+                None => complete = false,
+            },
+            Segment::Optional(inner) => {
+                let mut rendered = String::new();
+                if render_segments(inner, fields, &mut rendered) {
+                    output.push_str(&rendered);
+                }
+            }
+        }
+    }
+    complete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_filename_keeps_ordinary_names() {
+        assert_eq!(
+            safe_filename("01 - abc - My title.jpg"),
+            "01 - abc - My title.jpg"
+        );
+        assert_eq!(safe_filename("ünïcödé 🎉.png"), "ünïcödé 🎉.png");
+        assert_eq!(safe_filename("a.b.c"), "a.b.c");
+    }
+
+    #[test]
+    fn safe_filename_rejects_traversal() {
+        for name in [
+            "..",
+            ".",
+            "../../etc/passwd",
+            "..\\..\\windows",
+            "/etc/passwd",
+            "a/../../b",
+        ] {
+            let safe = safe_filename(name);
+            assert!(!safe.contains(['/', '\\']), "{:?} -> {:?}", name, safe);
+            assert!(safe != "." && safe != "..", "{:?} -> {:?}", name, safe);
+            assert!(!safe.starts_with('.'), "{:?} -> {:?}", name, safe);
+        }
+        assert_eq!(safe_filename(".."), "_");
+        assert_eq!(safe_filename("../x"), "_..-x");
+    }
+
+    #[test]
+    fn safe_filename_is_safe_on_windows() {
+        assert_eq!(safe_filename("C:foo"), "C-foo");
+        assert_eq!(safe_filename("C:\\Windows"), "C--Windows");
+        assert_eq!(safe_filename("\\\\?\\C:\\x"), "_--_-C--x");
+        assert_eq!(
+            safe_filename("what? *really* <b>|\"x\""),
+            "what_ _really_ _b___x_"
+        );
+        assert_eq!(safe_filename("Wait... "), "Wait");
+        assert_eq!(safe_filename(". ."), "_");
+        for name in [
+            "CON",
+            "con",
+            "nul.txt",
+            "Aux .png",
+            "COM1",
+            "lpt9.tar.gz",
+            "COM¹",
+            "conin$",
+        ] {
+            assert_eq!(safe_filename(name), format!("_{}", name.trim()));
+        }
+        for name in ["CONSOLE", "com10", "disk con", "nul-x"] {
+            assert_eq!(safe_filename(name), name);
+        }
+    }
+
+    #[test]
+    fn safe_filename_escapes_options() {
+        assert_eq!(safe_filename("-rf"), "_-rf");
+        assert_eq!(safe_filename("--help.jpg"), "_--help.jpg");
+        assert_eq!(safe_filename(" -x"), "_-x");
+    }
+
+    #[test]
+    fn safe_filename_removes_control_characters() {
+        assert_eq!(safe_filename("a\nb\tc"), "a b c");
+        assert_eq!(safe_filename("a\0b\x1b[31mc"), "ab[31mc");
+        assert_eq!(safe_filename(""), "_");
+        assert_eq!(safe_filename(" \n "), "_");
+    }
+
+    #[test]
+    fn long_filenames_are_shortened() {
+        assert_eq!(shorten_filename("1 - abc.jpg", "jpg"), "1 - abc.jpg");
+        let long = format!("1 - abc - {}.jpg", "ü".repeat(200));
+        let short = shorten_filename(&long, "jpg");
+        assert!(short.len() <= MAX_FILENAME_LENGTH);
+        assert!(short.starts_with("1 - abc - üü"));
+        assert!(short.ends_with("ü….jpg"));
+    }
+
+    #[test]
+    fn glob_matches_filenames() {
+        assert!(glob_matches("*.mp4", "01 - abc.mp4"));
+        assert!(!glob_matches("*.mp4", "01 - abc.mp4.jpg"));
+        assert!(glob_matches("*sketch*", "02 - def - sketch 3.png"));
+        assert!(!glob_matches("*sketch*", "02 - def - Sketch.png"));
+        assert!(glob_matches("0?-*", "01-x"));
+        assert!(!glob_matches("0?-*", "1-x"));
+        assert!(glob_matches("[0-4]*", "3 - a.gif"));
+        assert!(!glob_matches("[!0-4]*", "3 - a.gif"));
+        assert!(glob_matches("*.[gj]*", "a.gif"));
+        assert!(glob_matches("a[b", "a[b"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("**a*b**", "xxaxxbxx"));
+        assert!(!glob_matches("*a*b", "xxaxxbxx"));
+    }
+
+    #[test]
+    fn directory_templates_nest_by_date() {
+        let album = ImgurAlbum {
+            id: "abc".to_owned(),
+            title: Some("Cats/dogs: ../..".to_owned()),
+            description: None,
+            account_url: None,
+            tags: vec![],
+            datetime: 1683000000,
+            images: vec![],
+        };
+        let template = Template::parse_directory("{album_date:%Y/%m}/{title}").unwrap();
+        assert_eq!(
+            render_directory(&template, &album),
+            PathBuf::from("2023/05/Cats-dogs- ..-")
+        );
+        let template = Template::parse_directory("../{id}[ - {title}]/..").unwrap();
+        assert_eq!(
+            render_directory(&template, &album),
+            PathBuf::from("_/abc - Cats-dogs- ..-/_")
+        );
+        let template = Template::parse_directory(BY_UPLOADER_DIRECTORY_TEMPLATE).unwrap();
+        assert_eq!(
+            render_directory(&template, &album),
+            PathBuf::from("Cats-dogs- ..-")
+        );
+        let album = ImgurAlbum {
+            title: None,
+            account_url: Some("someone".to_owned()),
+            ..album
+        };
+        assert_eq!(
+            render_directory(&template, &album),
+            PathBuf::from("someone/abc")
+        );
+
+        let template = Template::parse_directory("{title}").unwrap();
+        assert_eq!(render_directory(&template, &album), PathBuf::from("abc"));
+        assert!(Template::parse_directory("{title:%Y}").is_err());
+        assert!(Template::parse("{album_date}").is_err());
+        assert!(Template::parse("{album_id}/{date:%Y}-{index}.{ext}").is_ok());
+        assert!(Template::parse_directory("{date}").is_err());
+
+        let template = Template::parse_directory("[{tags}/]{id}").unwrap();
+        assert_eq!(render_directory(&template, &album), PathBuf::from("abc"));
+        let tag = |name: &str| ImgurTag {
+            name: name.to_owned(),
+        };
+        let album = ImgurAlbum {
+            tags: vec![tag("cats"), tag("../aww")],
+            ..album
+        };
+        assert_eq!(
+            render_directory(&template, &album),
+            PathBuf::from("cats,_..-aww/abc")
+        );
+    }
+
+    #[test]
+    fn rendered_titles_stay_in_one_component() {
+        let template = Template::default();
+        let filename = safe_filename(&template.render(|name| match name {
+            "index" => Some("1".to_owned()),
+            "id" => Some("abc".to_owned()),
+            "title" => Some("../../../.ssh/authorized_keys".to_owned()),
+            "description" => Some("line one\nline/two".to_owned()),
+            "ext" => Some("jpg".to_owned()),
+            _ => None,
+        }));
+        assert_eq!(
+            filename,
+            "1 - abc - ..-..-..-.ssh-authorized_keys - line one line-two.jpg"
+        );
+
+        let template = Template::parse("{title}.{ext}").unwrap();
+        let filename = safe_filename(&template.render(|name| match name {
+            "title" => Some("..".to_owned()),
+            "ext" => Some("".to_owned()),
+            _ => None,
+        }));
+        assert_eq!(filename, "_");
+
+        // A drive-relative path would replace the destination it is joined to on Windows.
+        let filename = safe_filename(&template.render(|name| match name {
+            "title" => Some("C:foo".to_owned()),
+            "ext" => Some("png".to_owned()),
+            _ => None,
+        }));
+        assert_eq!(filename, "C-foo.png");
+        let album = ImgurAlbum {
+            id: "abc".to_owned(),
+            title: Some("D:\\x".to_owned()),
+            description: None,
+            account_url: None,
+            tags: vec![],
+            datetime: 0,
+            images: vec![],
+        };
+        let directory = render_directory(&Template::parse_directory("{title}").unwrap(), &album);
+        assert_eq!(directory, PathBuf::from("D--x"));
+    }
+}
@@ -0,0 +1,136 @@
+//! `imgurs account login`: authorizes imgurs to access the API as a user, with Imgur's PIN flow
+//! or by redirecting the browser to a local server.
+
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use reqwest::Url;
+use tokio::sync::oneshot;
+
+use imgurs::api::{fetch_oauth_tokens, oauth_authorize_url, ApiClient, OAuthGrant, OAuthTokens};
+
+/// Random value tying the authorization to this login, so that other pages cannot complete it.
+fn random_state() -> String {
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    format!("{:016x}", random)
+}
+
+/// Asks the user to authorize imgurs and returns their tokens. Without `redirect_port`, the
+/// user enters the PIN Imgur shows them; otherwise Imgur redirects to
+/// `http://localhost:<port>/`, which must be the application's callback URL.
+pub async fn login(
+    api: &ApiClient,
+    client_id: &str,
+    client_secret: &str,
+    redirect_port: Option<u16>,
+) -> Result<OAuthTokens> {
+    let state = random_state();
+    match redirect_port {
+        None => {
+            println!(
+                "Open this URL, authorize imgurs and enter the PIN shown:\n\n{}\n",
+                oauth_authorize_url(api, client_id, "pin", &state)
+            );
+            print!("PIN: ");
+            std::io::stdout().flush()?;
+            let mut pin = String::new();
+            std::io::stdin().read_line(&mut pin)?;
+            let pin = pin.trim();
+            if pin.is_empty() {
+                return Err(anyhow!("No PIN entered"));
+            }
+            fetch_oauth_tokens(api, client_id, client_secret, OAuthGrant::Pin(pin)).await
+        }
+        Some(port) => {
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let (sender, receiver) = oneshot::channel();
+            let sender = Arc::new(Mutex::new(Some(sender)));
+            let expected_state = state.clone();
+            let server = Server::try_bind(&addr)
+                .with_context(|| format!("Unable to listen on {}", addr))?
+                .serve(make_service_fn(move |_| {
+                    let sender = sender.clone();
+                    let expected_state = expected_state.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |request| {
+                            let result = callback_code(&request, &expected_state);
+                            let response = match &result {
+                                Some(Ok(_)) => "Authorized imgurs, this window can be closed.",
+                                Some(Err(_)) => "Authorization failed, see imgurs for details.",
+                                None => "Waiting for authorization.",
+                            };
+                            if let Some(result) = result {
+                                if let Some(sender) = sender.lock().unwrap().take() {
+                                    let _ = sender.send(result);
+                                }
+                            }
+                            async move {
+                                Ok::<_, Infallible>(
+                                    Response::builder()
+                                        .status(StatusCode::OK)
+                                        .body(Body::from(response))
+                                        .unwrap(),
+                                )
+                            }
+                        }))
+                    }
+                }));
+            println!(
+                "Open this URL and authorize imgurs:\n\n{}\n",
+                oauth_authorize_url(api, client_id, "code", &state)
+            );
+            println!(
+                "Waiting for the browser to be redirected to http://localhost:{}/",
+                port
+            );
+
+            let mut code = None;
+            server
+                .with_graceful_shutdown(async {
+                    code = receiver.await.ok();
+                })
+                .await
+                .with_context(|| "Authorization server failed")?;
+            let code = code.ok_or_else(|| anyhow!("Authorization was not completed"))??;
+            fetch_oauth_tokens(
+                api,
+                client_id,
+                client_secret,
+                OAuthGrant::AuthorizationCode(&code),
+            )
+            .await
+        }
+    }
+}
+
+/// Reads the authorization code from a request to the callback URL, or `None` for other
+/// requests, such as for a favicon.
+fn callback_code(request: &Request<Body>, expected_state: &str) -> Option<Result<String>> {
+    let url = Url::parse(&format!("http://localhost{}", request.uri())).ok()?;
+    if url.path() != "/" {
+        return None;
+    }
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    if let Some(error) = query("error") {
+        return Some(Err(anyhow!("Authorization was denied: {}", error)));
+    }
+    let code = query("code")?;
+    if query("state").as_deref() != Some(expected_state) {
+        return Some(Err(anyhow!(
+            "Authorization came from another login attempt"
+        )));
+    }
+    Some(Ok(code))
+}
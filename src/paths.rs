@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+/// Per-user directory for persistent data, e.g. `~/.local/share/imgurs` on Linux.
+pub fn data_dir() -> Option<PathBuf> {
+    data_home().map(|base| base.join("imgurs"))
+}
+
+/// Base per-user data directory shared with other applications, e.g. `~/.local/share` on Linux.
+pub fn data_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local/share")))
+    }
+}
+
+/// Per-user directory for configuration, e.g. `~/.config/imgurs` on Linux.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) || cfg!(target_os = "macos") {
+        data_home()
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".config")))
+    };
+    base.map(|base| base.join("imgurs"))
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
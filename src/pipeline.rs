@@ -0,0 +1,153 @@
+//! Runs configured post-processing steps on downloaded albums.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+
+use imgurs::api::ImgurAlbum;
+use imgurs::date::DateTime;
+use imgurs::plan::{DownloadPlan, PlannedFile};
+use imgurs::torrent;
+
+use crate::config::Step;
+use crate::upload::{Manifest, ManifestAlbum, ManifestImage};
+
+/// Runs `steps` in order on the files of `plan`, downloaded from `album`, which have been
+/// downloaded.
+pub fn run(steps: &[Step], plan: &DownloadPlan, album: &ImgurAlbum) -> Result<()> {
+    let tags: Vec<String> = album.tags.iter().map(|tag| tag.name.clone()).collect();
+    let files: Vec<&PlannedFile> = plan
+        .files
+        .iter()
+        .filter(|file| plan.destination.join(&file.filename).is_file())
+        .collect();
+
+    for step in steps {
+        match step {
+            Step::Hook { command, per_file } => {
+                if *per_file {
+                    for file in &files {
+                        let path = plan.destination.join(&file.filename);
+                        run_hook(command, &plan.destination, Some(&path))?;
+                    }
+                } else {
+                    run_hook(command, &plan.destination, None)?;
+                }
+            }
+            Step::Xmp => {
+                for file in &files {
+                    let path = plan.destination.join(format!("{}.xmp", file.filename));
+                    std::fs::write(&path, xmp_sidecar(file, &tags))
+                        .with_context(|| format!("Unable to write {}", path.display()))?;
+                }
+            }
+            Step::Manifest => {
+                let manifest = Manifest {
+                    images: vec![],
+                    albums: vec![ManifestAlbum {
+                        title: album.title.clone(),
+                        description: album.description.clone(),
+                        tags: tags.clone(),
+                        images: files
+                            .iter()
+                            .map(|file| ManifestImage {
+                                path: PathBuf::from(&file.filename),
+                                id: Some(file.id.clone()),
+                                title: file.title.clone(),
+                                description: file.description.clone(),
+                            })
+                            .collect(),
+                    }],
+                };
+                let path = plan.destination.join("manifest.toml");
+                std::fs::write(&path, toml::to_string_pretty(&manifest)?)
+                    .with_context(|| format!("Unable to write {}", path.display()))?;
+            }
+            Step::Checksums => {
+                torrent::write_checksums(&plan.destination)?;
+            }
+            Step::Torrent { trackers } => {
+                torrent::write_checksums(&plan.destination)?;
+                let path = torrent::make_torrent(&plan.destination, trackers)?;
+                println!("Created torrent: {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_hook(command: &[String], dir: &Path, file: Option<&Path>) -> Result<()> {
+    let mut args = command.iter().map(|arg| {
+        let arg = arg.replace("{dir}", &dir.to_string_lossy());
+        match file {
+            Some(file) => arg.replace("{file}", &file.to_string_lossy()),
+            None => arg,
+        }
+    });
+    let program = args
+        .next()
+        .ok_or_else(|| anyhow!("Hook has an empty command"))?;
+    let status = Command::new(&program)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .with_context(|| format!("Unable to run hook {}", program))?;
+    if !status.success() {
+        return Err(anyhow!("Hook {} failed with {}", program, status));
+    }
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xmp_sidecar(file: &PlannedFile, tags: &[String]) -> String {
+    let mut properties = String::new();
+    if let Some(title) = &file.title {
+        properties.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            escape_xml(title)
+        ));
+    }
+    if let Some(description) = &file.description {
+        properties.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            escape_xml(description)
+        ));
+    }
+    if !tags.is_empty() {
+        let items: String = tags
+            .iter()
+            .map(|tag| format!("<rdf:li>{}</rdf:li>", escape_xml(tag)))
+            .collect();
+        properties.push_str(&format!(
+            "   <dc:subject><rdf:Bag>{}</rdf:Bag></dc:subject>\n",
+            items
+        ));
+    }
+    format!(
+        r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+{}   <dc:identifier>{}</dc:identifier>
+   <dc:source>{}</dc:source>
+   <xmp:CreateDate>{}</xmp:CreateDate>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#,
+        properties,
+        escape_xml(&file.id),
+        escape_xml(&file.url),
+        DateTime::from_unix(file.datetime).to_rfc3339()
+    )
+}
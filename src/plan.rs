@@ -0,0 +1,993 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::Client;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::api::{get_media_type, thumbnail_link, ImgurAlbum, ImgurMedia, ThumbnailSize};
+use crate::breaker::{CircuitBreaker, Failure, Outage};
+use crate::date::{format_duration, DateTime};
+use crate::download::{
+    album_directory_name, download_file, is_missing, prepare_directory, renamed_path,
+    set_modified_time, sniff_file, FileOutcome, OverwritePolicy, Parallelism, PartialPolicy,
+    PauseToken, SNIFFED_EXTENSIONS,
+};
+use crate::error::{categorize, retry_after, Cancelled, ErrorCategory, Removed};
+use crate::naming::{joined_tags, safe_filename, shorten_filename, Template, MAX_FILENAME_LENGTH};
+use crate::speed::{EtaColumn, SpeedColumn, SpeedEstimator};
+use crate::throttle::Throttle;
+use crate::writer::BufferLimit;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10 * 60);
+
+type MediaFilter = Box<dyn Fn(&ImgurMedia) -> bool + Send + Sync>;
+
+/// A single file to be downloaded as part of a [`DownloadPlan`].
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    /// 1-based position of the media within the album.
+    pub index: usize,
+    pub id: String,
+    pub url: String,
+    pub filename: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub size: u64,
+    pub datetime: i64,
+    /// Whether the title or description did not fit in the filename, so they are saved to a
+    /// `.txt` sidecar next to the file.
+    pub overflowed: bool,
+}
+
+/// Overall progress of a [`DownloadPlan`] being executed.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub downloaded_bytes: u64,
+    /// Bytes received from the network, including those of attempts which were started over.
+    pub received_bytes: u64,
+    pub total_bytes: u64,
+    /// Number of files which have either been downloaded or failed.
+    pub completed_files: usize,
+    pub total_files: usize,
+    /// Number of files being downloaded at the moment, not counting those waiting to start.
+    pub active_files: usize,
+    /// Smoothed download speed in bytes per second, once it can be estimated.
+    pub bytes_per_sec: Option<f64>,
+    /// Estimated time until the remaining bytes are downloaded.
+    pub eta: Option<Duration>,
+}
+
+/// What became of the files of an executed [`DownloadPlan`].
+#[derive(Debug, Default)]
+pub struct Execution {
+    /// Errors of the files which failed.
+    pub errors: Vec<anyhow::Error>,
+    /// Files which had not been started when downloads were stopped or cancelled, in the order
+    /// they were planned.
+    pub not_started: Vec<PlannedFile>,
+}
+
+/// Something which happened while a [`DownloadPlan`] was being executed.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Data was received or a file completed.
+    Progress(Progress),
+    /// A file was downloaded, skipped, or failed.
+    FileDone {
+        file: &'a PlannedFile,
+        path: &'a Path,
+        result: Result<FileOutcome, &'a anyhow::Error>,
+    },
+    /// Something went wrong which did not fail a file, such as setting its modified time.
+    Warning {
+        file: &'a PlannedFile,
+        error: &'a anyhow::Error,
+    },
+    /// Downloads from a host kept failing with network errors, so files from it are held back
+    /// and retried after `retry_in`.
+    HostUnavailable { host: &'a str, retry_in: Duration },
+    /// A host which was unavailable works again.
+    HostRecovered(&'a Outage),
+    /// A host has been unavailable for too long, so its files are failed instead of retried.
+    HostGaveUp { host: &'a str },
+    /// A host rate limited a download, so files from it are held back for `retry_in`.
+    RateLimited { host: &'a str, retry_in: Duration },
+    /// A file failed with a network error, and will be retried after `retry_in`. `attempt`
+    /// counts from 1.
+    Retrying {
+        file: &'a PlannedFile,
+        error: &'a anyhow::Error,
+        attempt: usize,
+        retry_in: Duration,
+    },
+}
+
+/// A fully resolved album download, which can be inspected before being executed.
+#[derive(Debug, Clone)]
+pub struct DownloadPlan {
+    pub destination: PathBuf,
+    pub files: Vec<PlannedFile>,
+    pub parallelism: Parallelism,
+    pub overwrite: OverwritePolicy,
+    pub partial: PartialPolicy,
+    /// URL templates tried when Imgur no longer has a file, see [`DownloadPlanBuilder::mirror`].
+    pub mirrors: Vec<String>,
+    /// Shown before each file's name in progress bars, see [`DownloadPlanBuilder::label`].
+    pub label: Option<String>,
+    /// How many times each file is retried after network errors.
+    pub retries: usize,
+    /// How many times each file is retried after being rate limited, separately from `retries`.
+    pub rate_limit_retries: usize,
+    /// Longest wait asked for by a rate limited response before the file is failed instead.
+    pub max_rate_limit_wait: Duration,
+    /// Combined download speed limit, see [`DownloadPlanBuilder::max_bytes_per_sec`].
+    pub max_bytes_per_sec: Option<u64>,
+    /// Cap on data received but not yet written, see [`DownloadPlanBuilder::max_buffered_bytes`].
+    pub max_buffered_bytes: Option<u64>,
+}
+
+/// Configures a [`DownloadPlan`] for an album.
+pub struct DownloadPlanBuilder<'a> {
+    album: &'a ImgurAlbum,
+    destination: Option<PathBuf>,
+    filters: Vec<MediaFilter>,
+    template: Option<String>,
+    parallelism: Parallelism,
+    overwrite: OverwritePolicy,
+    partial: PartialPolicy,
+    mirrors: Vec<String>,
+    label: Option<String>,
+    retries: usize,
+    rate_limit_retries: usize,
+    max_rate_limit_wait: Duration,
+    max_bytes_per_sec: Option<u64>,
+    max_buffered_bytes: Option<u64>,
+    downscale: Option<ThumbnailSize>,
+}
+
+impl DownloadPlan {
+    pub fn builder(album: &ImgurAlbum) -> DownloadPlanBuilder<'_> {
+        DownloadPlanBuilder {
+            album,
+            destination: None,
+            filters: vec![],
+            template: None,
+            parallelism: Parallelism::default(),
+            overwrite: OverwritePolicy::default(),
+            partial: PartialPolicy::default(),
+            mirrors: vec![],
+            label: None,
+            retries: 0,
+            rate_limit_retries: 0,
+            max_rate_limit_wait: DEFAULT_MAX_RATE_LIMIT_WAIT,
+            max_bytes_per_sec: None,
+            max_buffered_bytes: None,
+            downscale: None,
+        }
+    }
+
+    /// Total size of all planned files in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    /// Renames downloaded files whose contents do not match their extension, such as a ".png"
+    /// which is really a JPEG, returning their old and new names. Files renamed by an earlier run
+    /// are planned under their new name instead, so that they are not downloaded again.
+    pub fn fix_extensions(&mut self) -> Result<Vec<(String, String)>> {
+        let mut renamed = vec![];
+        for file in &mut self.files {
+            let (stem, extension) = file
+                .filename
+                .rsplit_once('.')
+                .unwrap_or((&file.filename, ""));
+            let extension = match extension.to_ascii_lowercase().as_str() {
+                "jpeg" => "jpg".to_owned(),
+                extension => extension.to_owned(),
+            };
+            let path = self.destination.join(&file.filename);
+            let filename = if path.is_file() {
+                let sniffed = sniff_file(&path)
+                    .with_context(|| format!("Unable to read {}", path.display()))?;
+                match sniffed {
+                    Some(actual) if actual != extension => {
+                        let filename = format!("{}.{}", stem, actual);
+                        let new_path = self.destination.join(&filename);
+                        if new_path.exists() {
+                            continue;
+                        }
+                        std::fs::rename(&path, &new_path).with_context(|| {
+                            format!("Unable to rename {} to {}", path.display(), filename)
+                        })?;
+                        renamed.push((file.filename.clone(), filename.clone()));
+                        filename
+                    }
+                    _ => continue,
+                }
+            } else {
+                let previous = SNIFFED_EXTENSIONS.iter().find_map(|&candidate| {
+                    let filename = format!("{}.{}", stem, candidate);
+                    let path = self.destination.join(&filename);
+                    (candidate != extension
+                        && path.is_file()
+                        && matches!(sniff_file(&path), Ok(Some(actual)) if actual == candidate))
+                    .then_some(filename)
+                });
+                match previous {
+                    Some(filename) => filename,
+                    None => continue,
+                }
+            };
+            file.filename = filename;
+        }
+        Ok(renamed)
+    }
+
+    /// Sends a HEAD request for each planned file, replacing its size with the one reported by
+    /// the server. Files which are unavailable are removed from the plan, and returned with the
+    /// reason.
+    pub async fn probe(&mut self, client: &Client) -> Vec<(PlannedFile, anyhow::Error)> {
+        let has_mirrors = !self.mirrors.is_empty();
+        let results = stream::iter(&self.files)
+            .map(|file| async move {
+                let response = client.head(&file.url).send().await?;
+                if is_missing(&response) {
+                    // The file may still be downloaded from a mirror.
+                    if has_mirrors {
+                        return Ok(None);
+                    }
+                    return Err(Removed.into());
+                }
+                Ok(response.error_for_status()?.content_length())
+            })
+            .buffered(self.parallelism.get().max(1))
+            .collect::<Vec<Result<Option<u64>>>>()
+            .await;
+
+        let mut unavailable = vec![];
+        let files = std::mem::take(&mut self.files);
+        for (mut file, result) in files.into_iter().zip(results) {
+            match result {
+                Ok(size) => {
+                    file.size = size.unwrap_or(file.size);
+                    self.files.push(file);
+                }
+                Err(e) => unavailable.push((file, e)),
+            }
+        }
+        unavailable
+    }
+
+    /// Downloads all planned files, returning the errors of any failed files.
+    ///
+    /// Once `cancel` is triggered, no new files are started and in-flight files are aborted,
+    /// keeping their temporary files to resume from unless [`PartialPolicy::Clean`] is used.
+    pub async fn execute(
+        &self,
+        client: &Client,
+        progress: &MultiProgress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<anyhow::Error>> {
+        let stop = CancellationToken::new();
+        let execution = self
+            .execute_with_events(
+                client,
+                progress,
+                cancel,
+                &stop,
+                &PauseToken::default(),
+                |_| {},
+            )
+            .await?;
+        Ok(execution.errors)
+    }
+
+    /// Like [`DownloadPlan::execute`], but can also be paused with `pause`, and calls `on_event`
+    /// whenever data is received or a file completes.
+    ///
+    /// Once `stop` is triggered, no new files are started but in-flight files are completed.
+    /// Files which were not started are left untouched, and returned apart from failed ones.
+    pub async fn execute_with_events(
+        &self,
+        client: &Client,
+        progress: &MultiProgress,
+        cancel: &CancellationToken,
+        stop: &CancellationToken,
+        pause: &PauseToken,
+        on_event: impl Fn(Event) + Send + Sync,
+    ) -> Result<Execution> {
+        prepare_directory(self.destination.clone()).await?;
+
+        let style = ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {speed} {msg}")
+            .unwrap()
+            .with_key("speed", SpeedColumn::default())
+            .progress_chars("#>-");
+
+        let total_bytes = self.total_size();
+        let total_files = self.files.len();
+        // With several files, also show the overall speed and how long is left.
+        let total = (total_files > 1).then(|| {
+            let total = progress.insert(0, ProgressBar::new(total_bytes));
+            total.set_style(
+                ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.green/blue}] {bytes}/{total_bytes} {speed} {smooth_eta} {msg}")
+                    .unwrap()
+                    .with_key("speed", SpeedColumn::default())
+                    .with_key("smooth_eta", EtaColumn::default())
+                    .progress_chars("#>-"),
+            );
+            total.set_message(format!("0/{} files", total_files));
+            total
+        });
+
+        let (sender, mut messages) = mpsc::channel(MESSAGE_CAPACITY);
+        let worker = Arc::new(Worker {
+            plan: self.clone(),
+            client: client.clone(),
+            progress: progress.clone(),
+            style,
+            cancel: cancel.clone(),
+            stop: stop.clone(),
+            pause: pause.clone(),
+            breaker: CircuitBreaker::default(),
+            throttle: self.max_bytes_per_sec.map(Throttle::new),
+            buffer: self.max_buffered_bytes.map(BufferLimit::new),
+            downloaded_bytes: AtomicU64::new(0),
+            received_bytes: AtomicU64::new(0),
+            active_files: AtomicUsize::new(0),
+            received: Notify::new(),
+            messages: sender,
+        });
+
+        let mut speed = SpeedEstimator::default();
+        let mut report = |completed_files: usize| {
+            let downloaded_bytes = worker.downloaded_bytes.load(Ordering::Relaxed);
+            if let Some(total) = &total {
+                total.set_position(downloaded_bytes);
+            }
+            speed.record(downloaded_bytes, Instant::now());
+            on_event(Event::Progress(Progress {
+                downloaded_bytes,
+                received_bytes: worker.received_bytes.load(Ordering::Relaxed),
+                total_bytes,
+                completed_files,
+                total_files,
+                active_files: worker.active_files.load(Ordering::Relaxed),
+                bytes_per_sec: speed.rate(),
+                eta: speed.eta(total_bytes.saturating_sub(downloaded_bytes)),
+            }))
+        };
+
+        // Files are started in order as earlier ones finish, so that no more than `parallelism`
+        // tasks exist at once. It is checked again whenever it changes.
+        let mut parallelism = self.parallelism.subscribe();
+        let mut pending = 0..total_files;
+        let mut tasks = JoinSet::new();
+        let mut completed_files = 0;
+        let mut errors = vec![];
+        let mut not_started = vec![];
+        loop {
+            let limit = *parallelism.borrow_and_update();
+            while tasks.len() < limit.max(1) && !cancel.is_cancelled() && !stop.is_cancelled() {
+                match pending.next() {
+                    Some(index) => tasks.spawn(worker.clone().download(index)),
+                    None => break,
+                };
+            }
+            tokio::select! {
+                biased;
+                Some(message) = messages.recv() => message.emit(&self.files, &on_event),
+                joined = tasks.join_next() => {
+                    let (index, path, result) = match joined {
+                        Some(Ok((index, Some((path, result))))) => (index, path, result),
+                        Some(Ok((index, None))) => {
+                            not_started.push(index);
+                            continue;
+                        }
+                        Some(Err(e)) => std::panic::resume_unwind(e.into_panic()),
+                        None => break,
+                    };
+                    // Everything the file sent happened before it finished.
+                    while let Ok(message) = messages.try_recv() {
+                        message.emit(&self.files, &on_event);
+                    }
+                    on_event(Event::FileDone {
+                        file: &self.files[index],
+                        path: &path,
+                        result: result.as_ref().map(|outcome| *outcome),
+                    });
+                    completed_files += 1;
+                    if let Some(total) = &total {
+                        total.set_message(format!("{}/{} files", completed_files, total_files));
+                    }
+                    report(completed_files);
+                    if let Err(error) = result {
+                        errors.push(error);
+                    }
+                }
+                _ = worker.received.notified() => report(completed_files),
+                Ok(()) = parallelism.changed() => {}
+            }
+        }
+        if let Some(total) = total {
+            total.finish_and_clear();
+        }
+
+        not_started.extend(pending);
+        not_started.sort_unstable();
+        Ok(Execution {
+            errors,
+            not_started: not_started
+                .into_iter()
+                .map(|index| self.files[index].clone())
+                .collect(),
+        })
+    }
+}
+
+/// Number of events which may be waiting to be handled before downloads wait for them.
+const MESSAGE_CAPACITY: usize = 64;
+
+/// An [`Event`] sent from a download task, referring to files by their position in the plan.
+enum Message {
+    Warning {
+        index: usize,
+        error: anyhow::Error,
+    },
+    Retrying {
+        index: usize,
+        error: anyhow::Error,
+        attempt: usize,
+        retry_in: Duration,
+    },
+    RateLimited {
+        host: String,
+        retry_in: Duration,
+    },
+    HostUnavailable {
+        host: String,
+        retry_in: Duration,
+    },
+    HostRecovered(Outage),
+    HostGaveUp {
+        host: String,
+    },
+}
+
+impl Message {
+    fn emit(self, files: &[PlannedFile], on_event: &impl Fn(Event)) {
+        on_event(match &self {
+            Message::Warning { index, error } => Event::Warning {
+                file: &files[*index],
+                error,
+            },
+            Message::Retrying {
+                index,
+                error,
+                attempt,
+                retry_in,
+            } => Event::Retrying {
+                file: &files[*index],
+                error,
+                attempt: *attempt,
+                retry_in: *retry_in,
+            },
+            Message::RateLimited { host, retry_in } => Event::RateLimited {
+                host,
+                retry_in: *retry_in,
+            },
+            Message::HostUnavailable { host, retry_in } => Event::HostUnavailable {
+                host,
+                retry_in: *retry_in,
+            },
+            Message::HostRecovered(outage) => Event::HostRecovered(outage),
+            Message::HostGaveUp { host } => Event::HostGaveUp { host },
+        })
+    }
+}
+
+/// State shared by the download tasks of a [`DownloadPlan`] being executed.
+struct Worker {
+    plan: DownloadPlan,
+    client: Client,
+    progress: MultiProgress,
+    style: ProgressStyle,
+    cancel: CancellationToken,
+    stop: CancellationToken,
+    pause: PauseToken,
+    breaker: CircuitBreaker,
+    throttle: Option<Throttle>,
+    buffer: Option<BufferLimit>,
+    downloaded_bytes: AtomicU64,
+    received_bytes: AtomicU64,
+    /// Number of files being downloaded, rather than waiting to start.
+    active_files: AtomicUsize,
+    /// Notified whenever data is received, so that progress is reported.
+    received: Notify,
+    messages: mpsc::Sender<Message>,
+}
+
+impl Worker {
+    async fn send(&self, message: Message) {
+        // Only fails once execution has ended, when nobody is listening anyway.
+        let _ = self.messages.send(message).await;
+    }
+
+    /// Downloads the file at `index` of the plan, returning where it was saved, or nothing if it
+    /// was stopped or cancelled before it started.
+    async fn download(
+        self: Arc<Self>,
+        index: usize,
+    ) -> (usize, Option<(PathBuf, Result<FileOutcome>)>) {
+        let plan = &self.plan;
+        let file = &plan.files[index];
+        let mut path = plan.destination.join(&file.filename);
+        if plan.overwrite == OverwritePolicy::Rename && path.is_file() {
+            path = renamed_path(&path);
+        }
+        let temp_path = path.with_file_name(format!(
+            "~!{}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        tokio::select! {
+            _ = self.pause.resumed() => {},
+            _ = self.cancel.cancelled() => {},
+            _ = self.stop.cancelled() => {},
+        };
+        if self.cancel.is_cancelled() || self.stop.is_cancelled() {
+            return (index, None);
+        }
+        self.active_files.fetch_add(1, Ordering::Relaxed);
+        let result = self
+            .attempt(index, &path, &temp_path)
+            .await
+            .with_context(|| format!("Error downloading file {}", file.filename));
+        self.active_files.fetch_sub(1, Ordering::Relaxed);
+        if result.is_err() && plan.partial == PartialPolicy::Clean {
+            self.remove_temp_file(index, &temp_path).await;
+        }
+        (index, Some((path, result)))
+    }
+
+    /// Downloads the file at `index` to `path`, retrying as the plan allows.
+    async fn attempt(
+        &self,
+        index: usize,
+        path: &PathBuf,
+        temp_path: &PathBuf,
+    ) -> Result<FileOutcome> {
+        let plan = &self.plan;
+        let file = &plan.files[index];
+        let (cancel, stop, breaker) = (&self.cancel, &self.stop, &self.breaker);
+        let pb = self.progress.add(ProgressBar::new(file.size));
+        pb.set_style(self.style.clone());
+        pb.set_message(match &plan.label {
+            Some(label) => format!("{}: {}", label, file.filename),
+            None => file.filename.clone(),
+        });
+
+        let attempt_bytes = AtomicU64::new(0);
+        let on_chunk = |len: u64| {
+            pb.inc(len);
+            attempt_bytes.fetch_add(len, Ordering::Relaxed);
+            self.received_bytes.fetch_add(len, Ordering::Relaxed);
+            self.downloaded_bytes.fetch_add(len, Ordering::Relaxed);
+            self.received.notify_one();
+        };
+        // What an earlier attempt left in the temporary file counts towards progress, but was
+        // not received by this one.
+        let on_resume = |len: u64| {
+            pb.inc(len);
+            attempt_bytes.fetch_add(len, Ordering::Relaxed);
+            self.downloaded_bytes.fetch_add(len, Ordering::Relaxed);
+            self.received.notify_one();
+        };
+        let host = reqwest::Url::parse(&file.url)
+            .ok()
+            .and_then(|url| Some(url.host_str()?.to_owned()))
+            .unwrap_or_default();
+        let mut retries = 0;
+        let mut rate_limited = 0;
+        loop {
+            if let Some(retry_at) = breaker.retry_at(&host) {
+                let reason = if breaker.is_held(&host) {
+                    "waiting for rate limit".to_owned()
+                } else {
+                    format!("waiting for {}", host)
+                };
+                if !wait_until(&pb, &reason, retry_at, cancel, stop).await {
+                    return Err(Cancelled.into());
+                }
+            }
+            let result = download_file(
+                &self.client,
+                &on_chunk,
+                &on_resume,
+                file,
+                path,
+                temp_path,
+                plan.overwrite,
+                &plan.mirrors,
+                cancel,
+                &self.pause,
+                self.throttle.as_ref(),
+                self.buffer.as_ref(),
+            )
+            .await;
+            let error = match result {
+                Ok(outcome) => {
+                    pb.finish_and_clear();
+                    if outcome == FileOutcome::Downloaded {
+                        if let Err(error) = set_modified_time(path, file.datetime) {
+                            self.send(Message::Warning { index, error }).await;
+                        }
+                    }
+                    if file.overflowed {
+                        if let Err(error) = write_overflow_sidecar(path, file) {
+                            self.send(Message::Warning { index, error }).await;
+                        }
+                    }
+                    if let Some(outage) = breaker.record_success(&host) {
+                        self.send(Message::HostRecovered(outage)).await;
+                    }
+                    return Ok(outcome);
+                }
+                Err(error) => error,
+            };
+            // Keep what was downloaded if the file may be retried, so that the download can
+            // continue where it left off.
+            let category = categorize(&error);
+            if plan.partial == PartialPolicy::Auto && !category.is_retryable() {
+                self.remove_temp_file(index, temp_path).await;
+            }
+            if category == ErrorCategory::RateLimited {
+                let retry_in = retry_after(&error).unwrap_or_else(|| backoff(rate_limited + 1));
+                if rate_limited >= plan.rate_limit_retries || retry_in > plan.max_rate_limit_wait {
+                    return Err(error);
+                }
+                // Hold back every file from the host for as long as it asked.
+                rate_limited += 1;
+                breaker.hold(&host, retry_in);
+                let host = host.clone();
+                self.send(Message::RateLimited { host, retry_in }).await;
+                let received = attempt_bytes.swap(0, Ordering::Relaxed);
+                self.downloaded_bytes.fetch_sub(received, Ordering::Relaxed);
+                pb.set_position(0);
+                continue;
+            }
+            if category != ErrorCategory::Network {
+                return Err(error);
+            }
+            match breaker.record_failure(&host) {
+                Failure::Fail if retries < plan.retries => {
+                    retries += 1;
+                    let retry_in = backoff(retries);
+                    self.send(Message::Retrying {
+                        index,
+                        error,
+                        attempt: retries,
+                        retry_in,
+                    })
+                    .await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(retry_in) => {},
+                        _ = cancel.cancelled() => return Err(Cancelled.into()),
+                    };
+                }
+                Failure::Fail => return Err(error),
+                Failure::GaveUp => {
+                    let host = host.clone();
+                    self.send(Message::HostGaveUp { host }).await;
+                    return Err(error);
+                }
+                Failure::Tripped(retry_in) => {
+                    let host = host.clone();
+                    self.send(Message::HostUnavailable { host, retry_in }).await;
+                }
+                Failure::Retry => {}
+            }
+            // Start the file over once it may succeed.
+            let received = attempt_bytes.swap(0, Ordering::Relaxed);
+            self.downloaded_bytes.fetch_sub(received, Ordering::Relaxed);
+            pb.set_position(0);
+        }
+    }
+
+    /// Removes the temporary file of a failed download, if there is one.
+    async fn remove_temp_file(&self, index: usize, path: &Path) {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                let error = anyhow::Error::new(e).context("Unable to remove temporary file");
+                self.send(Message::Warning { index, error }).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Saves the title and description of a file whose name had to be shortened to
+/// `<filename>.txt`, unless it already exists.
+fn write_overflow_sidecar(path: &Path, file: &PlannedFile) -> Result<()> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".txt");
+    let sidecar = PathBuf::from(sidecar);
+    if sidecar.exists() {
+        return Ok(());
+    }
+    let text = [file.title.as_deref(), file.description.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    std::fs::write(&sidecar, format!("{}\n", text))
+        .with_context(|| format!("Unable to write {}", sidecar.display()))
+}
+
+/// Waits until `until` while showing how long is left in `pb`'s message, returning `false` if
+/// cancelled or stopped first.
+async fn wait_until(
+    pb: &ProgressBar,
+    reason: &str,
+    until: Instant,
+    cancel: &CancellationToken,
+    stop: &CancellationToken,
+) -> bool {
+    let message = pb.message();
+    let waited = loop {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break true;
+        }
+        // Round up, so that the countdown ends at 1s rather than 0s.
+        let shown = Duration::from_secs(left.as_secs() + u64::from(left.subsec_nanos() > 0));
+        pb.set_message(format!(
+            "{} - {} ({})",
+            message,
+            reason,
+            format_duration(shown)
+        ));
+        tokio::select! {
+            _ = tokio::time::sleep(left.min(Duration::from_secs(1))) => {},
+            _ = cancel.cancelled() => break false,
+            _ = stop.cancelled() => break false,
+        };
+    };
+    pb.set_message(message);
+    waited
+}
+
+/// How long to wait before retry number `attempt`: 1s, 2s, 4s, ... up to 1 minute, randomized
+/// by up to half either way so that failed files are not all retried at once.
+fn backoff(attempt: usize) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+    let base = Duration::from_secs(1 << attempt.saturating_sub(1).min(6)).min(MAX_BACKOFF);
+    // Only used for jitter, so a randomly seeded hash is random enough.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    base.mul_f64(0.5 + (random % 1000) as f64 / 1000.0)
+}
+
+impl<'a> DownloadPlanBuilder<'a> {
+    /// Directory to download into. Defaults to a directory named after the album's title.
+    pub fn destination(mut self, destination: impl Into<PathBuf>) -> Self {
+        self.destination = Some(destination.into());
+        self
+    }
+
+    /// Only includes media for which `filter` returns `true`. Can be called multiple times.
+    pub fn filter(mut self, filter: impl Fn(&ImgurMedia) -> bool + Send + Sync + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Filename template, see [`Template`].
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    /// Number of files to download in parallel.
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = Parallelism::new(parallelism);
+        self
+    }
+
+    /// Downloads as many files in parallel as `parallelism` allows, following any changes to it
+    /// while the plan is executed.
+    pub fn shared_parallelism(mut self, parallelism: &Parallelism) -> Self {
+        self.parallelism = parallelism.clone();
+        self
+    }
+
+    /// Adds a mirror to download files from when Imgur reports them as missing, tried in the
+    /// order added. `{url}` in the template is replaced with the file's Imgur URL and `{id}` with
+    /// its ID, e.g. `https://web.archive.org/web/2id_/{url}` for Wayback Machine snapshots.
+    pub fn mirror(mut self, template: impl Into<String>) -> Self {
+        self.mirrors.push(template.into());
+        self
+    }
+
+    /// Text shown before each file's name in progress bars, e.g. the album's title when several
+    /// albums are downloaded.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// How many times to retry each file after network errors, waiting exponentially longer
+    /// between attempts. Defaults to 0.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How many times to retry each file after being rate limited, waiting as long as the
+    /// server asks. These do not count towards [`DownloadPlanBuilder::retries`]. Defaults to 0.
+    pub fn rate_limit_retries(mut self, retries: usize) -> Self {
+        self.rate_limit_retries = retries;
+        self
+    }
+
+    /// Longest wait asked for by a rate limited response which is waited out, rather than
+    /// failing the file. Defaults to 10 minutes.
+    pub fn max_rate_limit_wait(mut self, wait: Duration) -> Self {
+        self.max_rate_limit_wait = wait;
+        self
+    }
+
+    /// Limits the combined speed of the album's downloads to `bytes_per_sec` on average.
+    pub fn max_bytes_per_sec(mut self, bytes_per_sec: u64) -> Self {
+        self.max_bytes_per_sec = Some(bytes_per_sec);
+        self
+    }
+
+    /// Limits the data received by the album's downloads which is waiting to be written to disk
+    /// to `bytes` in total, slowing downloads to the speed of the disk once it is reached.
+    pub fn max_buffered_bytes(mut self, bytes: u64) -> Self {
+        self.max_buffered_bytes = Some(bytes);
+        self
+    }
+
+    /// Downloads Imgur's thumbnail of this size in place of still images which are larger than
+    /// it, as a JPEG. Their sizes are estimated from their dimensions.
+    pub fn downscale(mut self, size: ThumbnailSize) -> Self {
+        self.downscale = Some(size);
+        self
+    }
+
+    /// What to do with the temporary files of failed downloads. Defaults to keeping them when
+    /// retrying may help.
+    pub fn partial(mut self, partial: PartialPolicy) -> Self {
+        self.partial = partial;
+        self
+    }
+
+    /// What to do when a file already exists at the destination.
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn build(self) -> Result<DownloadPlan> {
+        if self.parallelism.get() == 0 {
+            return Err(anyhow!("Parallelism must be at least 1"));
+        }
+        if let Some(mirror) = self
+            .mirrors
+            .iter()
+            .find(|mirror| !mirror.contains("{url}") && !mirror.contains("{id}"))
+        {
+            return Err(anyhow!("Mirror {} must contain {{url}} or {{id}}", mirror));
+        }
+
+        let template = match &self.template {
+            Some(template) => Template::parse(template)?,
+            None => Template::default(),
+        };
+
+        let album = self.album;
+        let destination = self
+            .destination
+            .unwrap_or_else(|| album_directory_name(album.title.as_deref().unwrap_or(&album.id)));
+
+        let width = album.images.len().to_string().len();
+        let tags = joined_tags(&album.tags);
+        let mut filenames = HashSet::new();
+        let files = album
+            .images
+            .iter()
+            .enumerate()
+            .filter(|(_, media)| self.filters.iter().all(|filter| filter(media)))
+            .map(|(index, media)| {
+                let long_side = media.width.max(media.height);
+                let downscaled = self
+                    .downscale
+                    .filter(|size| !media.animated && long_side > size.max_dimension());
+                let ext = match downscaled {
+                    Some(_) => "jpg".to_owned(),
+                    None => get_media_type(&media.content_type, &media.link),
+                };
+                let render = |with_description: bool| {
+                    let rendered = template.render_formatted(|name, format| match name {
+                        "index" => Some(format!("{:0>width$}", index + 1, width = width)),
+                        "id" => Some(media.id.clone()),
+                        "title" => media.title.clone(),
+                        "description" => media.description.clone().filter(|_| with_description),
+                        "tags" => tags.clone(),
+                        "date" => Some(
+                            DateTime::from_unix(media.datetime)
+                                .format(format.unwrap_or("%Y-%m-%d")),
+                        ),
+                        "album_id" => Some(album.id.clone()),
+                        "ext" => Some(ext.clone()),
+                        _ => None,
+                    });
+                    // Fall back to the media ID when the fields before the extension were all
+                    // missing.
+                    if rendered.trim().is_empty() || rendered.trim().starts_with('.') {
+                        safe_filename(&format!("{}.{}", media.id, ext))
+                    } else {
+                        safe_filename(&rendered)
+                    }
+                };
+                let mut filename = render(true);
+                // Save titles and descriptions which do not fit to a sidecar instead.
+                let overflowed = filename.len() > MAX_FILENAME_LENGTH;
+                if overflowed {
+                    filename = shorten_filename(&render(false), &ext);
+                }
+                // Templates without {index} or {id} can give several files the same name.
+                if !filenames.insert(filename.clone()) {
+                    filename = safe_filename(&format!("{} - {}.{}", index + 1, media.id, ext));
+                    filenames.insert(filename.clone());
+                }
+
+                PlannedFile {
+                    index: index + 1,
+                    id: media.id.clone(),
+                    url: match downscaled {
+                        Some(size) => thumbnail_link(&media.id, size),
+                        None => media.link.clone(),
+                    },
+                    filename,
+                    title: media.title.clone(),
+                    description: media.description.clone(),
+                    size: match downscaled {
+                        Some(size) => {
+                            let scale = size.max_dimension() as f64 / long_side as f64;
+                            (media.size as f64 * scale * scale) as u64
+                        }
+                        None => media.size,
+                    },
+                    datetime: media.datetime,
+                    overflowed,
+                }
+            })
+            .collect();
+
+        Ok(DownloadPlan {
+            destination,
+            files,
+            parallelism: self.parallelism,
+            overwrite: self.overwrite,
+            partial: self.partial,
+            mirrors: self.mirrors,
+            label: self.label,
+            retries: self.retries,
+            rate_limit_retries: self.rate_limit_retries,
+            max_rate_limit_wait: self.max_rate_limit_wait,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            max_buffered_bytes: self.max_buffered_bytes,
+        })
+    }
+}
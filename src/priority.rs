@@ -0,0 +1,39 @@
+//! `--nice`: lowers the process's CPU and I/O priority, so large background downloads don't slow
+//! down an interactive machine.
+
+use anyhow::Result;
+
+/// Lowers the priority of the current process. On Linux priorities are per-thread and only
+/// inherited by new threads, so this must be called before the runtime is started.
+#[cfg(unix)]
+pub fn lower_priority() -> Result<()> {
+    // SAFETY: these calls only change the scheduling priority of the current process.
+    unsafe {
+        // `nice` can only fail when raising the priority.
+        libc::nice(10);
+
+        // Idle I/O class, like `ionice -c 3`.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+            const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+            let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+            if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        // Background QoS, throttling both CPU and I/O.
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        if libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG) == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower_priority() -> Result<()> {
+    Err(anyhow::anyhow!("--nice is only supported on Unix"))
+}
@@ -0,0 +1,172 @@
+//! `--proxy`: sends requests through an HTTP proxy, or the proxy settings of macOS and Windows.
+//! Proxy auto-config (PAC) files are not evaluated.
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::{ClientBuilder, Proxy};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyOption {
+    /// Connect directly, ignoring `$HTTPS_PROXY` and the like.
+    None,
+    /// The proxy configured in the system settings, if any.
+    System,
+    Url(String),
+}
+
+impl FromStr for ProxyOption {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ProxyOption, String> {
+        match value {
+            "none" => Ok(ProxyOption::None),
+            "system" => Ok(ProxyOption::System),
+            _ if value.contains("://") => Ok(ProxyOption::Url(value.to_owned())),
+            _ => Err(format!(
+                "expected a proxy URL, \"system\" or \"none\", got \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+/// Warns that the PAC file at `url` is ignored, as only manually configured proxies are used.
+fn ignore_pac(url: &str) {
+    println!(
+        "The system proxy settings use the PAC file {}, which is not supported; pass --proxy to \
+         use a proxy",
+        url
+    );
+}
+
+/// Parses the output of `scutil --proxy`, a dictionary of `key : value` lines.
+fn parse_scutil(output: &str) -> Option<ProxyOption> {
+    let settings: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.split_once(" : "))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let enabled = |key: &str| settings.get(key) == Some(&"1");
+    if enabled("ProxyAutoConfigEnable") {
+        if let Some(url) = settings.get("ProxyAutoConfigURLString") {
+            ignore_pac(url);
+        }
+    }
+    ["HTTPS", "HTTP"].into_iter().find_map(|scheme| {
+        let host = settings.get(format!("{}Proxy", scheme).as_str())?;
+        let port = settings.get(format!("{}Port", scheme).as_str())?;
+        enabled(&format!("{}Enable", scheme))
+            .then(|| ProxyOption::Url(format!("http://{}:{}", host, port)))
+    })
+}
+
+/// Parses the output of `reg query` for the Internet Settings key, whose `ProxyServer` is either
+/// `host:port` or per scheme, as in `http=host:port;https=host:port`.
+fn parse_reg_query(output: &str) -> Option<ProxyOption> {
+    let settings: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let _type = fields.next().filter(|kind| kind.starts_with("REG_"))?;
+            Some((name, fields.next().unwrap_or("")))
+        })
+        .collect();
+    if let Some(url) = settings.get("AutoConfigURL") {
+        ignore_pac(url);
+    }
+    if settings.get("ProxyEnable") != Some(&"0x1") {
+        return None;
+    }
+    let server = settings.get("ProxyServer")?;
+    let address = match server
+        .split(';')
+        .find_map(|entry| entry.strip_prefix("https="))
+    {
+        Some(address) => address,
+        None if !server.contains('=') => server,
+        None => server
+            .split(';')
+            .find_map(|entry| entry.strip_prefix("http="))?,
+    };
+    Some(ProxyOption::Url(format!("http://{}", address)))
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Unable to run {}", program))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads the system's proxy settings, returning `None` if no proxy is configured.
+fn system_proxy() -> Result<Option<ProxyOption>> {
+    if cfg!(target_os = "macos") {
+        Ok(parse_scutil(&run("scutil", &["--proxy"])?))
+    } else if cfg!(windows) {
+        let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+        Ok(parse_reg_query(&run("reg", &["query", key])?))
+    } else {
+        Err(anyhow!(
+            "System proxy settings are only read on macOS and Windows"
+        ))
+    }
+}
+
+/// Sets up `builder` to use the proxy given by `option`. Without one, the system's settings are
+/// used on macOS and Windows, and `$HTTPS_PROXY` and the like everywhere.
+pub fn configure(builder: ClientBuilder, option: Option<&ProxyOption>) -> Result<ClientBuilder> {
+    let option = match option {
+        Some(ProxyOption::System) => system_proxy()?,
+        Some(option) => Some(option.clone()),
+        None if cfg!(any(target_os = "macos", windows)) => system_proxy().unwrap_or_else(|e| {
+            println!("Unable to read the system proxy settings: {:#}", e);
+            None
+        }),
+        None => None,
+    };
+    Ok(match option {
+        None | Some(ProxyOption::System) => builder,
+        Some(ProxyOption::None) => builder.no_proxy(),
+        Some(ProxyOption::Url(url)) => {
+            builder.proxy(Proxy::all(&url).with_context(|| format!("Invalid proxy {}", url))?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_system_settings() {
+        let scutil = "<dictionary> {\n  HTTPEnable : 1\n  HTTPPort : 8080\n  \
+                      HTTPProxy : proxy.example.com\n  HTTPSEnable : 0\n}\n";
+        assert_eq!(
+            parse_scutil(scutil),
+            Some(ProxyOption::Url("http://proxy.example.com:8080".to_owned()))
+        );
+        let pac =
+            "  ProxyAutoConfigEnable : 1\n  ProxyAutoConfigURLString : http://wpad/wpad.dat\n";
+        assert_eq!(parse_scutil(pac), None);
+
+        let reg = "\nHKEY_CURRENT_USER\\...\\Internet Settings\n    ProxyEnable    REG_DWORD    \
+                   0x1\n    ProxyServer    REG_SZ    http=a:80;https=b:443\n";
+        assert_eq!(
+            parse_reg_query(reg),
+            Some(ProxyOption::Url("http://b:443".to_owned()))
+        );
+        assert_eq!(parse_reg_query(&reg.replace("0x1", "0x0")), None);
+    }
+}
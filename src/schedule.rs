@@ -0,0 +1,83 @@
+//! `--schedule-window`: only transfer data during certain hours of the day.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use imgurs::date::{DateTime, LOCAL_TIME_KNOWN};
+
+use crate::job::Job;
+
+/// A daily window of local time, e.g. "01:00-07:00". Windows ending before they start span
+/// midnight.
+#[derive(Debug, Clone, Copy)]
+pub struct ScheduleWindow {
+    /// Minutes after midnight.
+    start: u32,
+    end: u32,
+}
+
+impl FromStr for ScheduleWindow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ScheduleWindow, String> {
+        // Rather than running on UTC, which would be the wrong hours for most.
+        if !LOCAL_TIME_KNOWN {
+            return Err("the local time is unknown on this platform".to_owned());
+        }
+        let parse_time = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let hours: u32 = hours.parse().ok()?;
+            let minutes: u32 = minutes.parse().ok()?;
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        let window = value.split_once('-').and_then(|(start, end)| {
+            Some(ScheduleWindow {
+                start: parse_time(start)?,
+                end: parse_time(end)?,
+            })
+        });
+        match window {
+            Some(window) if window.start != window.end => Ok(window),
+            _ => Err(format!(
+                "expected a window like \"01:00-07:00\", got \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+impl ScheduleWindow {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Pauses downloads outside `window` and resumes them inside it, until cancelled.
+pub async fn enforce(window: ScheduleWindow, job: Arc<Job>) {
+    let (pause, cancel) = (&job.pause, &job.cancel);
+    loop {
+        let now = DateTime::now_local();
+        let minute = now.hour * 60 + now.minute;
+        if window.contains(minute) {
+            if pause.is_paused() {
+                job.log("Inside the schedule window, resuming downloads.");
+                pause.resume();
+            }
+        } else if !pause.is_paused() {
+            job.log("Outside the schedule window, pausing downloads.");
+            pause.pause();
+        }
+
+        // Recheck at the start of every minute, in case the clock or timezone changes.
+        let wait = 60 - now.second.min(59);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(wait.into())) => {},
+            _ = cancel.cancelled() => return,
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! `imgurs serve`: a local HTTP server which queues album URLs and downloads them in order.
+//!
+//! `POST /` with a URL per line (or a `url` form field) adds albums to the queue, which is saved
+//! to disk so it survives restarts. `GET /` shows a status page and `GET /status` returns the
+//! same information as JSON.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use humansize::{format_size, DECIMAL};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use tokio::sync::Notify;
+
+use imgurs::api::{ApiClient, Auth};
+
+use crate::config::Config;
+use crate::job::{Destination, Job, QueuedAlbum};
+use crate::Cli;
+
+/// Maximum size of a request body.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+/// Number of finished albums shown on the status page.
+const HISTORY_SIZE: usize = 20;
+
+/// Album URLs waiting to be downloaded, persisted to a JSON file.
+struct Queue {
+    path: PathBuf,
+    pending: Mutex<VecDeque<String>>,
+    history: Mutex<VecDeque<(String, String)>>,
+    added: Notify,
+}
+
+impl Queue {
+    fn load(path: PathBuf) -> Result<Queue> {
+        let pending = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Unable to parse queue file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read queue file {}", path.display()))
+            }
+        };
+
+        Ok(Queue {
+            path,
+            pending: Mutex::new(pending),
+            history: Mutex::new(VecDeque::new()),
+            added: Notify::new(),
+        })
+    }
+
+    fn save(&self, pending: &VecDeque<String>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(pending)?)?;
+        std::fs::rename(&temp_path, &self.path).with_context(|| "Unable to save queue file")?;
+        Ok(())
+    }
+
+    fn push(&self, urls: Vec<String>) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.extend(urls);
+        self.save(&pending)?;
+        self.added.notify_one();
+        Ok(())
+    }
+
+    fn front(&self) -> Option<String> {
+        self.pending.lock().unwrap().front().cloned()
+    }
+
+    /// Removes a downloaded album from the queue, recording how it went.
+    fn complete(&self, url: &str, outcome: String) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(index) = pending.iter().position(|pending| pending == url) {
+            pending.remove(index);
+        }
+        self.save(&pending)?;
+
+        let mut history = self.history.lock().unwrap();
+        history.push_front((url.to_owned(), outcome));
+        history.truncate(HISTORY_SIZE);
+        Ok(())
+    }
+}
+
+struct State {
+    queue: Queue,
+    job: Arc<Job>,
+}
+
+/// Default location of the persistent queue.
+pub fn default_queue_file() -> Option<PathBuf> {
+    crate::paths::data_dir().map(|dir| dir.join("queue.json"))
+}
+
+pub async fn serve(
+    api: &ApiClient,
+    auth: &Auth,
+    args: &Cli,
+    config: &Config,
+    job: Arc<Job>,
+    listen: SocketAddr,
+    queue_file: &Path,
+) -> Result<()> {
+    let state = Arc::new(State {
+        queue: Queue::load(queue_file.to_owned())?,
+        job: job.clone(),
+    });
+
+    let server = Server::try_bind(&listen)
+        .with_context(|| format!("Unable to listen on {}", listen))?
+        .serve(make_service_fn({
+            let state = state.clone();
+            move |_| {
+                let state = state.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        handle_request(request, state.clone())
+                    }))
+                }
+            }
+        }))
+        .with_graceful_shutdown({
+            let job = job.clone();
+            async move { job.cancel.cancelled().await }
+        });
+    let server = tokio::spawn(server);
+    println!("Listening on http://{}", listen);
+
+    let destination = match &args.output {
+        Some(output) => Destination::Under(output.clone()),
+        None => Destination::Named,
+    };
+
+    loop {
+        let Some(input) = state.queue.front() else {
+            tokio::select! {
+                _ = state.queue.added.notified() => continue,
+                _ = job.cancel.cancelled() => break,
+            }
+        };
+
+        let album = QueuedAlbum {
+            input: input.clone(),
+            destination: destination.clone(),
+        };
+        let outcome = match crate::download_album(api, auth, args, config, &job, album, None).await
+        {
+            Ok(()) => "Done".to_owned(),
+            Err(e) => {
+                println!("{:?}\n", e);
+                format!("Failed: {}", e)
+            }
+        };
+
+        // Leave interrupted albums in the queue so they are resumed on restart.
+        if job.cancel.is_cancelled() {
+            break;
+        }
+        state.queue.complete(&input, outcome)?;
+    }
+
+    server.await??;
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    state: Arc<State>,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") => Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(status_page(&state))),
+        (&Method::GET, "/status") => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(status_json(&state).to_string())),
+        (&Method::POST, "/") => match add_urls(request, &state).await {
+            Ok((count, true)) => Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(LOCATION, "/")
+                .body(Body::from(format!("Queued {} album(s)\n", count))),
+            Ok((count, false)) => Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::from(format!("Queued {} album(s)\n", count))),
+            Err(e) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("{}\n", e))),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found\n")),
+    };
+
+    Ok(response.expect("response is valid"))
+}
+
+/// Queues the URLs in a request body, returning how many were added and whether the request came
+/// from the status page's form.
+async fn add_urls(request: Request<Body>, state: &State) -> Result<(usize, bool)> {
+    let too_large = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+        .is_none_or(|length| length > MAX_BODY_SIZE);
+    if too_large {
+        return Err(anyhow!("Request body is missing a length or is too large"));
+    }
+
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+
+    let urls: Vec<String> = if is_form {
+        url::form_urlencoded::parse(&body)
+            .filter(|(key, _)| key == "url")
+            .map(|(_, value)| value.trim().to_owned())
+            .filter(|url| !url.is_empty())
+            .collect()
+    } else {
+        std::str::from_utf8(&body)?
+            .lines()
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    if urls.is_empty() {
+        return Err(anyhow!("No album URLs given"));
+    }
+    if state.job.cancel.is_cancelled() {
+        return Err(anyhow!("Server is shutting down"));
+    }
+
+    let count = urls.len();
+    state.queue.push(urls)?;
+    Ok((count, is_form))
+}
+
+fn status_json(state: &State) -> serde_json::Value {
+    let status = state.job.status();
+    // Locked in the same order as when completing an album, so neither list misses it.
+    let pending = state.queue.pending.lock().unwrap();
+    let history = state.queue.history.lock().unwrap();
+    json!({
+        "album": status.album,
+        "downloaded_bytes": status.downloaded_bytes,
+        "total_bytes": status.total_bytes,
+        "completed_files": status.completed_files,
+        "total_files": status.total_files,
+        "completed_albums": status.completed_albums,
+        "bytes_per_sec": status.bytes_per_sec,
+        "eta_secs": status.eta.map(|eta| eta.as_secs()),
+        "paused": state.job.pause.is_paused(),
+        "queued": *pending,
+        "history": history
+            .iter()
+            .map(|(url, outcome)| json!({ "url": url, "outcome": outcome }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn status_page(state: &State) -> String {
+    let status = state.job.status();
+    let current = match &status.album {
+        Some(album) => format!(
+            "<p>Downloading <b>{}</b>: {}/{} files, {}/{}</p>",
+            escape_html(album),
+            status.completed_files,
+            status.total_files,
+            format_size(status.downloaded_bytes, DECIMAL),
+            format_size(status.total_bytes, DECIMAL),
+        ),
+        None => "<p>Idle.</p>".to_owned(),
+    };
+    let current = match (&status.album, status.bytes_per_sec, status.eta) {
+        (Some(_), Some(bytes_per_sec), Some(eta)) => format!(
+            "{}<p>{}/s, {} left.</p>",
+            current,
+            format_size(bytes_per_sec as u64, DECIMAL),
+            indicatif::HumanDuration(eta),
+        ),
+        _ => current,
+    };
+    let current = if state.job.pause.is_paused() {
+        format!("{}<p>Paused.</p>", current)
+    } else {
+        current
+    };
+    let queued: String = state
+        .queue
+        .pending
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|url| format!("<li>{}</li>", escape_html(url)))
+        .collect();
+    let history: String = state
+        .queue
+        .history
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(url, outcome)| format!("<li>{}: {}</li>", escape_html(url), escape_html(outcome)))
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><meta http-equiv="refresh" content="5"><title>imgurs</title></head>
+<body>
+<h1>imgurs</h1>
+{}
+<form method="post" action="/"><input name="url" size="60" placeholder="Album URL"> <button>Queue</button></form>
+<h2>Queue</h2>
+<ul>{}</ul>
+<h2>Finished</h2>
+<ul>{}</ul>
+</body>
+</html>
+"#,
+        current, queued, history
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
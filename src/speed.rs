@@ -0,0 +1,163 @@
+//! Smoothed transfer speeds, so that the speed and ETA shown do not jump around with every
+//! burst from the CDN.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use indicatif::style::ProgressTracker;
+use indicatif::{HumanBytes, ProgressState};
+
+/// How long it takes for a change in speed to be half reflected in the estimate.
+const HALF_LIFE: Duration = Duration::from_secs(3);
+/// Samples closer together than this are merged, as bursts of small chunks arrive all at once.
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exponentially-weighted estimate of a transfer's speed, along with its average speed.
+#[derive(Debug, Clone, Default)]
+pub struct SpeedEstimator {
+    start: Option<Instant>,
+    last: Option<(u64, Instant)>,
+    /// Bytes transferred since the first sample, including any which were started over.
+    transferred: u64,
+    rate: Option<f64>,
+}
+
+impl SpeedEstimator {
+    /// Records that `bytes` had been transferred in total at `now`.
+    pub fn record(&mut self, bytes: u64, now: Instant) {
+        let Some((last_bytes, last_time)) = self.last else {
+            self.start = Some(now);
+            self.last = Some((bytes, now));
+            return;
+        };
+        if bytes < last_bytes {
+            // Some of the transfer is being started over, which says nothing about its speed.
+            self.last = Some((bytes, now));
+            return;
+        }
+        let elapsed = now.saturating_duration_since(last_time);
+        if elapsed < MIN_SAMPLE_INTERVAL {
+            return;
+        }
+        let elapsed = elapsed.as_secs_f64();
+        self.transferred += bytes - last_bytes;
+        let instant = (bytes - last_bytes) as f64 / elapsed;
+        let weight = 1.0 - 0.5f64.powf(elapsed / HALF_LIFE.as_secs_f64());
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + weight * (instant - rate),
+            None => instant,
+        });
+        self.last = Some((bytes, now));
+    }
+
+    /// Smoothed current speed in bytes per second, once there are enough samples.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Average speed in bytes per second since the first sample.
+    pub fn average(&self) -> Option<f64> {
+        let (start, (_, last)) = (self.start?, self.last?);
+        let elapsed = last.saturating_duration_since(start).as_secs_f64();
+        (elapsed > 0.0).then(|| self.transferred as f64 / elapsed)
+    }
+
+    /// Estimated time to transfer `remaining` more bytes at the current speed.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        let rate = self.rate().filter(|rate| *rate > 0.0)?;
+        Duration::try_from_secs_f64(remaining as f64 / rate).ok()
+    }
+}
+
+/// Progress bar key showing the current and average speed, as `{speed}`.
+#[derive(Debug, Clone, Default)]
+pub struct SpeedColumn(SpeedEstimator);
+
+impl ProgressTracker for SpeedColumn {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        self.0.record(state.pos(), now);
+    }
+
+    fn reset(&mut self, _: &ProgressState, _: Instant) {
+        self.0 = SpeedEstimator::default();
+    }
+
+    fn write(&self, _: &ProgressState, w: &mut dyn Write) {
+        if let (Some(rate), Some(average)) = (self.0.rate(), self.0.average()) {
+            let _ = write!(
+                w,
+                "{}/s (avg {}/s)",
+                HumanBytes(rate as u64),
+                HumanBytes(average as u64)
+            );
+        }
+    }
+}
+
+/// Progress bar key showing the time left at the smoothed current speed, as `{smooth_eta}`.
+#[derive(Debug, Clone, Default)]
+pub struct EtaColumn(SpeedEstimator);
+
+impl ProgressTracker for EtaColumn {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        self.0.record(state.pos(), now);
+    }
+
+    fn reset(&mut self, _: &ProgressState, _: Instant) {
+        self.0 = SpeedEstimator::default();
+    }
+
+    fn write(&self, state: &ProgressState, w: &mut dyn Write) {
+        let remaining = state.len().unwrap_or(0).saturating_sub(state.pos());
+        if let Some(eta) = self.0.eta(remaining) {
+            let _ = write!(w, "ETA {}", indicatif::HumanDuration(eta));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooths_bursts() {
+        let start = Instant::now();
+        let mut speed = SpeedEstimator::default();
+        for second in 0..=10 {
+            speed.record(second * 1000, start + Duration::from_secs(second));
+        }
+        assert_eq!(speed.rate(), Some(1000.0));
+        assert_eq!(speed.average(), Some(1000.0));
+        assert_eq!(speed.eta(5000), Some(Duration::from_secs(5)));
+
+        // A one second burst at ten times the speed moves the estimate by a fraction of it.
+        speed.record(20_000, start + Duration::from_secs(11));
+        let rate = speed.rate().unwrap();
+        assert!(rate > 2000.0 && rate < 5000.0, "{}", rate);
+
+        // Samples in quick succession are merged into the next one.
+        speed.record(30_000, start + Duration::from_millis(11_010));
+        assert_eq!(speed.rate(), Some(rate));
+    }
+
+    #[test]
+    fn keeps_speed_when_restarted() {
+        let start = Instant::now();
+        let mut speed = SpeedEstimator::default();
+        speed.record(0, start);
+        speed.record(5000, start + Duration::from_secs(1));
+        speed.record(0, start + Duration::from_secs(2));
+        assert_eq!(speed.rate(), Some(5000.0));
+        speed.record(5000, start + Duration::from_secs(3));
+        assert_eq!(speed.rate(), Some(5000.0));
+        assert_eq!(speed.average(), Some(10_000.0 / 3.0));
+    }
+}
@@ -0,0 +1,185 @@
+//! Persistent record of downloaded media, shared across runs and albums.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::plan::{DownloadPlan, PlannedFile};
+
+/// A downloaded media file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaRecord {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Bytes downloaded by one run of imgurs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// When the run started, as a Unix timestamp.
+    pub started: i64,
+    pub seconds: u64,
+    pub albums: usize,
+    /// Bytes received from the network, including retried attempts.
+    pub bytes: u64,
+}
+
+/// Bytes downloaded over all runs, and by the most recent ones.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bandwidth {
+    pub total_bytes: u64,
+    /// Oldest first, at most [`Bandwidth::MAX_RUNS`].
+    pub runs: Vec<RunRecord>,
+}
+
+impl Bandwidth {
+    /// Number of runs which are kept. Older ones only count towards the total.
+    pub const MAX_RUNS: usize = 100;
+
+    pub fn record(&mut self, run: RunRecord) {
+        self.total_bytes += run.bytes;
+        self.runs.push(run);
+        let excess = self.runs.len().saturating_sub(Bandwidth::MAX_RUNS);
+        self.runs.drain(..excess);
+    }
+}
+
+/// Downloaded media, keyed by media ID, and the bandwidth used, stored as a JSON file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateDb {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub media: BTreeMap<String, MediaRecord>,
+    #[serde(default)]
+    pub bandwidth: Bandwidth,
+}
+
+/// What to do with media which has already been downloaded elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DedupPolicy {
+    /// Don't download it again.
+    Skip,
+    /// Hard link the existing file, copying it if that is not possible.
+    Link,
+    /// Copy the existing file.
+    Copy,
+}
+
+impl StateDb {
+    /// Loads the database at `path`, or an empty one if it does not exist yet.
+    pub fn load(path: &Path) -> Result<StateDb> {
+        let mut db: StateDb = match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Unable to parse state database {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StateDb::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read state database {}", path.display()))
+            }
+        };
+        db.path = path.to_owned();
+        Ok(db)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temp_path, &self.path).with_context(|| "Unable to save state database")?;
+        Ok(())
+    }
+
+    /// Returns the path of a previously downloaded media file, if it still exists.
+    pub fn existing(&self, id: &str) -> Option<&Path> {
+        let record = self.media.get(id)?;
+        record.path.is_file().then_some(record.path.as_path())
+    }
+
+    /// Records every file of `plan` which exists at its destination.
+    pub fn record_plan(&mut self, plan: &DownloadPlan) {
+        for file in &plan.files {
+            let path = plan.destination.join(&file.filename);
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                let path = path.canonicalize().unwrap_or(path);
+                let record = MediaRecord {
+                    path,
+                    size: metadata.len(),
+                };
+                self.media.insert(file.id.clone(), record);
+            }
+        }
+    }
+
+    /// Removes files which were already downloaded elsewhere from `plan`, linking or copying them
+    /// into place according to `policy`. Returns the removed files and the files they duplicate.
+    pub fn dedup(
+        &self,
+        plan: &mut DownloadPlan,
+        policy: DedupPolicy,
+    ) -> Result<Vec<(PlannedFile, PathBuf)>> {
+        std::fs::create_dir_all(&plan.destination)?;
+
+        let mut removed = vec![];
+        let mut files = std::mem::take(&mut plan.files);
+        files.retain(|file| {
+            let destination = plan.destination.join(&file.filename);
+            let existing = match self.existing(&file.id) {
+                Some(existing) if !same_file(existing, &destination) => existing,
+                _ => return true,
+            };
+            if destination.exists() {
+                removed.push((file.clone(), existing.to_owned()));
+                return false;
+            }
+
+            let result = match policy {
+                DedupPolicy::Skip => Ok(()),
+                DedupPolicy::Link => std::fs::hard_link(existing, &destination)
+                    .or_else(|_| std::fs::copy(existing, &destination).map(|_| ())),
+                DedupPolicy::Copy => std::fs::copy(existing, &destination).map(|_| ()),
+            };
+            // Fall back to downloading the file again if it could not be reused.
+            if result.is_ok() {
+                removed.push((file.clone(), existing.to_owned()));
+            }
+            result.is_err()
+        });
+        plan.files = files;
+
+        Ok(removed)
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_total_of_forgotten_runs() {
+        let mut bandwidth = Bandwidth::default();
+        for started in 0..Bandwidth::MAX_RUNS as i64 + 5 {
+            bandwidth.record(RunRecord {
+                started,
+                seconds: 1,
+                albums: 1,
+                bytes: 10,
+            });
+        }
+        assert_eq!(bandwidth.runs.len(), Bandwidth::MAX_RUNS);
+        assert_eq!(bandwidth.runs[0].started, 5);
+        assert_eq!(bandwidth.total_bytes, 10 * (Bandwidth::MAX_RUNS as u64 + 5));
+    }
+}
@@ -0,0 +1,55 @@
+//! Limits the combined speed of downloads, e.g. to leave bandwidth for other uses of a slow or
+//! metered connection.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Spaces out data shared by any number of downloads so that, on average, no more than
+/// `bytes_per_sec` are received.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    /// When the data accounted for so far would have been received at the limit.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Throttle {
+        Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next: Mutex::default(),
+        }
+    }
+
+    /// Accounts for `bytes` received at `now`, returning how long to wait before receiving more.
+    pub fn delay(&self, bytes: u64, now: Instant) -> Duration {
+        let mut next = self.next.lock().unwrap();
+        // Time spent idle is not saved up for later bursts.
+        let start = next.filter(|next| *next > now).unwrap_or(now);
+        let end = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        *next = Some(end);
+        end - now
+    }
+
+    /// Waits until `bytes` more may be received.
+    pub async fn consume(&self, bytes: u64) {
+        let delay = self.delay(bytes, Instant::now());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_the_limit() {
+        let throttle = Throttle::new(1000);
+        let now = Instant::now();
+        assert_eq!(throttle.delay(500, now), Duration::from_millis(500));
+        // A second download waits behind the first.
+        assert_eq!(throttle.delay(500, now), Duration::from_secs(1));
+        let later = now + Duration::from_secs(5);
+        assert_eq!(throttle.delay(100, later), Duration::from_millis(100));
+    }
+}
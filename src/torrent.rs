@@ -0,0 +1,270 @@
+//! Torrents and checksum files for downloaded album directories.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::date::unix_now;
+use crate::download::album_files;
+
+/// Name of the checksum file written into album directories, in `sha256sum` format.
+pub const CHECKSUMS_FILENAME: &str = "SHA256SUMS";
+
+const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Piece lengths are increased until a torrent has at most this many pieces.
+const TARGET_PIECES: u64 = 1500;
+
+enum Bencode {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl Bencode {
+    fn string(value: &str) -> Bencode {
+        Bencode::Bytes(value.as_bytes().to_vec())
+    }
+
+    fn dict<'a>(entries: impl IntoIterator<Item = (&'a str, Bencode)>) -> Bencode {
+        Bencode::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        match self {
+            Bencode::Int(value) => output.extend(format!("i{}e", value).as_bytes()),
+            Bencode::Bytes(bytes) => {
+                output.extend(format!("{}:", bytes.len()).as_bytes());
+                output.extend(bytes);
+            }
+            Bencode::List(items) => {
+                output.push(b'l');
+                items.iter().for_each(|item| item.encode(output));
+                output.push(b'e');
+            }
+            Bencode::Dict(entries) => {
+                output.push(b'd');
+                for (key, value) in entries {
+                    Bencode::Bytes(key.clone()).encode(output);
+                    value.encode(output);
+                }
+                output.push(b'e');
+            }
+        }
+    }
+}
+
+/// Formats bytes as lowercase hexadecimal.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn sha256_file(path: &Path) -> Result<[u8; 32]> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buffer[..len]);
+    }
+}
+
+/// Writes a `SHA256SUMS` file listing every file in `dir`, returning its path.
+pub fn write_checksums(dir: &Path) -> Result<PathBuf> {
+    let mut checksums = String::new();
+    for (name, _) in album_files(dir)? {
+        if name == CHECKSUMS_FILENAME {
+            continue;
+        }
+        let hash = sha256_file(&dir.join(&name))?;
+        checksums.push_str(&format!("{}  {}\n", to_hex(&hash), name));
+    }
+
+    let path = dir.join(CHECKSUMS_FILENAME);
+    std::fs::write(&path, checksums).with_context(|| "Unable to write checksums")?;
+    Ok(path)
+}
+
+/// Result of checking the files of a directory against its `SHA256SUMS` file.
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// Number of files which match their checksums.
+    pub verified: usize,
+    pub missing: Vec<String>,
+    pub mismatched: Vec<String>,
+}
+
+/// Checks the files listed in the `SHA256SUMS` file of `dir` against their checksums.
+pub fn verify_checksums(dir: &Path) -> Result<Verification> {
+    let path = dir.join(CHECKSUMS_FILENAME);
+    let checksums = std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    let mut verification = Verification::default();
+    for line in checksums.lines().filter(|line| !line.is_empty()) {
+        // `sha256sum` marks files hashed in binary mode with `*`.
+        let (hash, name) = line
+            .split_once("  ")
+            .or_else(|| line.split_once(" *"))
+            .ok_or_else(|| anyhow!("Invalid line in {}: {}", path.display(), line))?;
+        // Names are only ever of files directly in `dir`, which checking must not leave.
+        let mut components = Path::new(name).components();
+        let plain = matches!(components.next(), Some(Component::Normal(_)))
+            && components.next().is_none()
+            && !name.contains(['/', '\\']);
+        if !plain {
+            return Err(anyhow!("Invalid file name in {}: {}", path.display(), name));
+        }
+        let file = dir.join(name);
+        if !file.is_file() {
+            verification.missing.push(name.to_owned());
+        } else if !to_hex(&sha256_file(&file)?).eq_ignore_ascii_case(hash) {
+            verification.mismatched.push(name.to_owned());
+        } else {
+            verification.verified += 1;
+        }
+    }
+    Ok(verification)
+}
+
+/// Writes a torrent of every file in `dir` next to it as `$dir.torrent`, returning its path.
+///
+/// The torrent has no web seeds. Without `trackers`, peers can only be found through DHT.
+pub fn make_torrent(dir: &Path, trackers: &[String]) -> Result<PathBuf> {
+    let dir = dir.canonicalize()?;
+    let name = dir
+        .file_name()
+        .ok_or_else(|| anyhow!("Album directory has no name"))?
+        .to_string_lossy()
+        .into_owned();
+    let torrent = encode_torrent(&dir, &name, trackers, unix_now())?;
+    let path = dir.with_file_name(format!("{}.torrent", name));
+    std::fs::write(&path, torrent).with_context(|| "Unable to write torrent")?;
+    Ok(path)
+}
+
+/// Encodes a torrent named `name` of every file in `dir`.
+fn encode_torrent(
+    dir: &Path,
+    name: &str,
+    trackers: &[String],
+    creation_date: i64,
+) -> Result<Vec<u8>> {
+    let files = album_files(dir)?;
+    if files.is_empty() {
+        return Err(anyhow!("Album directory is empty"));
+    }
+
+    let total_size: u64 = files.iter().map(|(_, size)| size).sum();
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while total_size / piece_length > TARGET_PIECES && piece_length < MAX_PIECE_LENGTH {
+        piece_length *= 2;
+    }
+
+    // Pieces span file boundaries, so hash all files as one stream.
+    let mut pieces = vec![];
+    let mut piece = Sha1::new();
+    let mut piece_len = 0;
+    let mut buffer = vec![0; piece_length as usize];
+    for (file_name, _) in &files {
+        let mut file = std::fs::File::open(dir.join(file_name))
+            .with_context(|| format!("Unable to read {}", file_name))?;
+        loop {
+            let len = file.read(&mut buffer[..(piece_length - piece_len) as usize])?;
+            if len == 0 {
+                break;
+            }
+            piece.update(&buffer[..len]);
+            piece_len += len as u64;
+            if piece_len == piece_length {
+                pieces.extend(std::mem::take(&mut piece).finalize());
+                piece_len = 0;
+            }
+        }
+    }
+    if piece_len > 0 {
+        pieces.extend(piece.finalize());
+    }
+
+    let info = Bencode::dict([
+        (
+            "files",
+            Bencode::List(
+                files
+                    .iter()
+                    .map(|(file_name, size)| {
+                        Bencode::dict([
+                            ("length", Bencode::Int(*size as i64)),
+                            ("path", Bencode::List(vec![Bencode::string(file_name)])),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+        ("name", Bencode::string(name)),
+        ("piece length", Bencode::Int(piece_length as i64)),
+        ("pieces", Bencode::Bytes(pieces)),
+    ]);
+
+    let mut torrent = vec![
+        ("created by", Bencode::string("imgurs")),
+        ("creation date", Bencode::Int(creation_date)),
+        ("info", info),
+    ];
+    if let Some(tracker) = trackers.first() {
+        torrent.push(("announce", Bencode::string(tracker)));
+    }
+    if trackers.len() > 1 {
+        let tiers = trackers
+            .iter()
+            .map(|tracker| Bencode::List(vec![Bencode::string(tracker)]))
+            .collect();
+        torrent.push(("announce-list", Bencode::List(tiers)));
+    }
+
+    let mut output = vec![];
+    Bencode::dict(torrent).encode(&mut output);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_torrents() {
+        // 1.png ends partway through the first of the 16 KiB pieces, and the second is short.
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/album");
+        let trackers = [
+            "udp://tracker.example:1337/announce".to_owned(),
+            "https://tracker.example/announce".to_owned(),
+        ];
+        let torrent = encode_torrent(&dir, "album", &trackers, 1_600_000_000).unwrap();
+        assert_eq!(torrent, include_bytes!("../tests/fixtures/album.torrent"));
+    }
+
+    #[test]
+    fn rejects_paths_in_checksums() {
+        let dir = std::env::temp_dir().join(format!("imgurs-checksums-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["../1.png", "/etc/passwd", "a/1.png", "a\\1.png", ".."] {
+            let line = format!("{}  {}\n", "0".repeat(64), name);
+            std::fs::write(dir.join(CHECKSUMS_FILENAME), line).unwrap();
+            assert!(verify_checksums(&dir).is_err(), "{}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
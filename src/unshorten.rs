@@ -0,0 +1,183 @@
+//! Follows short links (t.co, bit.ly, redd.it, ...) to the Imgur URL they redirect to.
+//!
+//! Inputs may come from untrusted link lists, so only known link shorteners are requested, and
+//! never at private or local addresses.
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+
+use imgurs::api::is_imgur_host;
+
+/// Link shorteners which are followed by default.
+pub const SHORTENER_HOSTS: &[&str] = &[
+    "t.co",
+    "bit.ly",
+    "redd.it",
+    "tinyurl.com",
+    "goo.gl",
+    "ow.ly",
+    "buff.ly",
+    "is.gd",
+];
+
+fn is_imgur(url: &Url) -> bool {
+    url.host_str().is_some_and(is_imgur_host)
+}
+
+/// Whether `host` is one of `hosts` or a subdomain of one.
+fn is_allowed(host: &str, hosts: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    SHORTENER_HOSTS
+        .iter()
+        .copied()
+        .chain(hosts.iter().map(String::as_str))
+        .any(|allowed| host == allowed || host.ends_with(&format!(".{}", allowed)))
+}
+
+/// Whether `ip` is reachable on the public internet, rather than loopback, private, link-local
+/// or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // Shared address space for carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local addresses, fc00::/7, and link-local addresses, fe80::/10.
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Looks up the address to request `url` at, failing if the URL is not an allowed shortener or
+/// any of its host's addresses are not public.
+async fn public_address(url: &Url, hosts: &[String]) -> Result<SocketAddr> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("Refusing to follow {} link {}", url.scheme(), url));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    if !is_allowed(host, hosts) {
+        return Err(anyhow!(
+            "Refusing to follow {}, which is not a known link shortener; see --shortener-host",
+            url
+        ));
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Unable to look up {}", host))?
+        .collect();
+    if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        return Err(anyhow!(
+            "Refusing to follow {}, which resolves to non-public address {}",
+            url,
+            address.ip()
+        ));
+    }
+    addresses
+        .first()
+        .copied()
+        .ok_or_else(|| anyhow!("{} has no addresses", host))
+}
+
+/// Returns `input` unchanged if it is an album ID or Imgur URL, otherwise the Imgur URL it
+/// redirects to, following at most `max_redirects` redirects. Only [`SHORTENER_HOSTS`] and
+/// `hosts` are followed.
+pub async fn resolve(input: &str, max_redirects: usize, hosts: &[String]) -> Result<String> {
+    let Ok(mut url) = Url::parse(input) else {
+        return Ok(input.to_owned());
+    };
+    if is_imgur(&url) {
+        return Ok(input.to_owned());
+    }
+
+    for _ in 0..max_redirects {
+        let address = public_address(&url, hosts).await?;
+        // Connect to the checked address, so that the host cannot resolve elsewhere in between.
+        let client = Client::builder()
+            .redirect(Policy::none())
+            .resolve(url.host_str().unwrap_or_default(), address)
+            .build()?;
+        let response = client
+            .get(url.clone())
+            .send()
+            .await
+            .with_context(|| format!("Unable to follow {}", url))?;
+        if !response.status().is_redirection() {
+            break;
+        }
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| anyhow!("Redirect from {} has no location", url))?;
+        url = url
+            .join(location)
+            .with_context(|| format!("Invalid redirect from {}", url))?;
+        if is_imgur(&url) {
+            return Ok(url.into());
+        }
+    }
+    Err(anyhow!(
+        "{} does not redirect to an Imgur URL within {} redirects",
+        input,
+        max_redirects
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses_are_refused() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public("151.101.1.140".parse().unwrap()));
+        assert!(is_public("2a04:4e42::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn only_shorteners_are_allowed() {
+        assert!(is_allowed("t.co", &[]));
+        assert!(is_allowed("www.bit.ly", &[]));
+        assert!(!is_allowed("example.com", &[]));
+        assert!(!is_allowed("evilt.co", &[]));
+        assert!(is_allowed("example.com", &["example.com".to_owned()]));
+    }
+}
@@ -0,0 +1,362 @@
+//! `imgurs upload` and `imgurs album apply`: uploading images, optionally grouped into albums,
+//! and editing uploaded albums.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use imgurs::api::{
+    create_album, delete_album, delete_image, get_album_id, update_album, update_image,
+    upload_image, ApiClient, Auth, ImageUpload, ImgurResponse, ImgurUpload,
+};
+use imgurs::date::unix_now;
+
+use crate::vault::{Upload, UploadKind, Vault};
+
+/// Describes images to upload or albums to edit, stored as TOML, as in `manifest.toml`:
+///
+/// ```toml
+/// [[images]]
+/// path = "cover.jpg"
+/// title = "Cover"
+///
+/// [[albums]]
+/// title = "Holiday"
+///
+/// [[albums.images]]
+/// path = "holiday/1.jpg"
+/// description = "Sunrise"
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Images uploaded outside any album.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ManifestImage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub albums: Vec<ManifestAlbum>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestAlbum {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Tags of the gallery post the album was downloaded from. Not uploaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Uploaded in order.
+    pub images: Vec<ManifestImage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestImage {
+    /// Relative to the manifest's directory.
+    pub path: PathBuf,
+    /// ID of the uploaded image, needed to edit albums.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Manifest {
+    /// Loads a manifest, resolving image paths against its directory.
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read manifest {}", path.display()))?;
+        let mut manifest: Manifest = toml::from_str(&contents)
+            .with_context(|| format!("Unable to parse manifest {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for image in manifest.all_images_mut() {
+            image.path = dir.join(&image.path);
+        }
+        Ok(manifest)
+    }
+
+    /// A manifest uploading `paths` outside any album, each with `title` and `description`.
+    pub fn images(paths: &[PathBuf], title: Option<&str>, description: Option<&str>) -> Manifest {
+        let images = paths
+            .iter()
+            .map(|path| ManifestImage {
+                path: path.clone(),
+                id: None,
+                title: title.map(str::to_owned),
+                description: description.map(str::to_owned),
+            })
+            .collect();
+        Manifest {
+            images,
+            albums: vec![],
+        }
+    }
+
+    /// Moves the images uploaded outside any album into a new album titled `title`.
+    pub fn into_album(mut self, title: &str) -> Manifest {
+        let images = std::mem::take(&mut self.images);
+        self.albums.push(ManifestAlbum {
+            title: Some(title.to_owned()),
+            description: None,
+            tags: vec![],
+            images,
+        });
+        self
+    }
+
+    pub fn all_images(&self) -> impl Iterator<Item = &ManifestImage> {
+        self.images
+            .iter()
+            .chain(self.albums.iter().flat_map(|album| &album.images))
+    }
+
+    fn all_images_mut(&mut self) -> impl Iterator<Item = &mut ManifestImage> {
+        self.images
+            .iter_mut()
+            .chain(self.albums.iter_mut().flat_map(|album| &mut album.images))
+    }
+
+    /// Fails if any of the manifest's images do not exist.
+    pub fn check_files(&mut self) -> Result<()> {
+        for image in self.all_images_mut() {
+            if !image.path.is_file() {
+                return Err(anyhow!("Image {} not found", image.path.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn uploaded(response: ImgurResponse<ImgurUpload>, what: &str) -> Result<ImgurUpload> {
+    response.data.ok_or_else(|| {
+        anyhow!(
+            "Failed to upload {} with status code: {}",
+            what,
+            response.status
+        )
+    })
+}
+
+/// Uploads everything in a manifest, calling `on_upload` after each image is uploaded or album
+/// created. Anonymous uploads are recorded in `vault` as they are made.
+pub async fn upload_manifest(
+    api: &ApiClient,
+    auth: &Auth,
+    manifest: &Manifest,
+    mut vault: Option<&mut Vault>,
+    on_upload: &mut dyn FnMut(&Upload),
+) -> Result<()> {
+    let mut record = |upload: Upload| -> Result<()> {
+        on_upload(&upload);
+        match vault.as_deref_mut() {
+            Some(vault) => vault.record(upload),
+            None => Ok(()),
+        }
+    };
+
+    let groups = std::iter::once((None, &manifest.images)).chain(
+        manifest
+            .albums
+            .iter()
+            .map(|album| (Some(album), &album.images)),
+    );
+    for (album, images) in groups {
+        let album = match album {
+            Some(album) => {
+                let response = create_album(
+                    api,
+                    auth,
+                    album.title.as_deref(),
+                    album.description.as_deref(),
+                )
+                .await?;
+                let created = uploaded(response, "album")?;
+                record(Upload {
+                    kind: UploadKind::Album,
+                    link: format!("https://imgur.com/a/{}", created.id),
+                    id: created.id.clone(),
+                    deletehash: created.deletehash.clone(),
+                    title: album.title.clone(),
+                    path: None,
+                    album: None,
+                    datetime: unix_now(),
+                })?;
+                Some(created)
+            }
+            None => None,
+        };
+
+        for image in images.iter() {
+            let data = tokio::fs::read(&image.path)
+                .await
+                .with_context(|| format!("Unable to read {}", image.path.display()))?;
+            let response = upload_image(
+                api,
+                auth,
+                &ImageUpload {
+                    data: &data,
+                    title: image.title.as_deref(),
+                    description: image.description.as_deref(),
+                    // Anonymous albums can only be added to with their deletehash.
+                    album: album.as_ref().map(|album| match auth {
+                        Auth::ClientId(_) => album.deletehash.as_str(),
+                        Auth::AccessToken(_) => album.id.as_str(),
+                    }),
+                },
+            )
+            .await?;
+            let created = uploaded(response, &image.path.display().to_string())?;
+            record(Upload {
+                kind: UploadKind::Image,
+                link: created
+                    .link
+                    .unwrap_or_else(|| format!("https://imgur.com/{}", created.id)),
+                id: created.id,
+                deletehash: created.deletehash,
+                title: image.title.clone(),
+                path: Some(
+                    std::fs::canonicalize(&image.path).unwrap_or_else(|_| image.path.clone()),
+                ),
+                album: album.as_ref().map(|album| album.id.clone()),
+                datetime: unix_now(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes an anonymous upload given its deletehash. Uploads which were already deleted are not
+/// an error.
+pub async fn delete(
+    api: &ApiClient,
+    client_id: &str,
+    kind: UploadKind,
+    deletehash: &str,
+) -> Result<()> {
+    // Deletehashes only work anonymously, even if an access token is given.
+    let auth = Auth::ClientId(client_id.to_owned());
+    let response = match kind {
+        UploadKind::Image => delete_image(api, &auth, deletehash).await?,
+        UploadKind::Album => delete_album(api, &auth, deletehash).await?,
+    };
+    // Imgur returns 404 for uploads which have already been deleted.
+    if response.status != 404 && response.data != Some(true) {
+        return Err(anyhow!(
+            "Failed to delete {} {} with status code: {}",
+            kind.name(),
+            deletehash,
+            response.status
+        ));
+    }
+    Ok(())
+}
+
+fn updated(response: ImgurResponse<bool>, what: &str) -> Result<()> {
+    if response.data != Some(true) {
+        return Err(anyhow!(
+            "Failed to update {} with status code: {}",
+            what,
+            response.status
+        ));
+    }
+    Ok(())
+}
+
+/// Sets the title, description and images of an existing album from a manifest describing a
+/// single album, then the titles and descriptions of its images. Images not in the manifest are
+/// removed from the album, but not deleted.
+///
+/// Anonymous albums can only be edited with their deletehashes, which are looked up in `vault`.
+pub async fn apply_manifest(
+    api: &ApiClient,
+    auth: &Auth,
+    album: &str,
+    manifest: &Manifest,
+    vault: Option<&Vault>,
+) -> Result<()> {
+    let ([album_manifest], []) = (&manifest.albums[..], &manifest.images[..]) else {
+        return Err(anyhow!("The manifest must describe exactly one album"));
+    };
+    let ids = album_manifest
+        .images
+        .iter()
+        .map(|image| {
+            image
+                .id
+                .as_deref()
+                .ok_or_else(|| anyhow!("Manifest image {} has no id", image.path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let hash = |id: &str| -> Result<String> {
+        match vault {
+            Some(vault) => vault
+                .get(id)
+                .map(|upload| upload.deletehash.clone())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} is not in the upload vault; editing it requires an access token",
+                        id
+                    )
+                }),
+            None => Ok(id.to_owned()),
+        }
+    };
+    let album = get_album_id(album);
+    let album_hash = hash(album)?;
+    let image_hashes = ids.iter().map(|id| hash(id)).collect::<Result<Vec<_>>>()?;
+
+    let image_hash_refs: Vec<&str> = image_hashes.iter().map(String::as_str).collect();
+    let response = update_album(
+        api,
+        auth,
+        &album_hash,
+        album_manifest.title.as_deref(),
+        album_manifest.description.as_deref(),
+        &image_hash_refs,
+    )
+    .await?;
+    updated(response, &format!("album {}", album))?;
+
+    for ((image, id), hash) in album_manifest.images.iter().zip(&ids).zip(&image_hashes) {
+        if image.title.is_none() && image.description.is_none() {
+            continue;
+        }
+        let response = update_image(
+            api,
+            auth,
+            hash,
+            image.title.as_deref(),
+            image.description.as_deref(),
+        )
+        .await?;
+        updated(response, &format!("image {}", id))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_manifests() {
+        let dir = std::env::temp_dir().join(format!("imgurs-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut manifest =
+            Manifest::images(&[PathBuf::from("1.png")], None, None).into_album("Album");
+        manifest.albums[0].images[0].id = Some("image1".to_owned());
+        let path = dir.join("manifest.toml");
+        std::fs::write(&path, toml::to_string_pretty(&manifest).unwrap()).unwrap();
+
+        let loaded = Manifest::load(&path).unwrap();
+        let album = &loaded.albums[0];
+        assert_eq!(album.title.as_deref(), Some("Album"));
+        assert_eq!(album.images[0].path, dir.join("1.png"));
+        assert_eq!(album.images[0].id.as_deref(), Some("image1"));
+        assert_eq!(album.images[0].title, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! `imgurs user`: archiving what a user has posted publicly.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use imgurs::api::{
+    all_pages, fetch_account_comments, fetch_account_submissions, fetch_user_albums, ApiClient,
+    Auth, ImgurComment,
+};
+use imgurs::date::DateTime;
+
+use crate::links::{self, LinkFormat};
+
+/// Fetches all of a user's public comments, newest first.
+pub async fn comments(api: &ApiClient, auth: &Auth, username: &str) -> Result<Vec<ImgurComment>> {
+    all_pages(&format!("comments of {}", username), |page| {
+        fetch_account_comments(api, auth, username, page)
+    })
+    .await
+}
+
+/// A gallery post or public album of a user.
+#[derive(Debug, Serialize)]
+pub struct Post {
+    pub id: String,
+    pub title: Option<String>,
+    pub datetime: i64,
+    /// URL of the gallery post or album, which it is downloaded from.
+    pub link: String,
+}
+
+/// Fetches all of a user's gallery posts, and their public albums which were not posted to the
+/// gallery, newest first.
+pub async fn posts(api: &ApiClient, auth: &Auth, username: &str) -> Result<Vec<Post>> {
+    let submissions = all_pages(&format!("submissions of {}", username), |page| {
+        fetch_account_submissions(api, auth, username, page)
+    })
+    .await?;
+    let albums = all_pages(&format!("albums of {}", username), |page| {
+        fetch_user_albums(api, auth, username, page)
+    })
+    .await?;
+
+    let mut posts: Vec<Post> = submissions
+        .into_iter()
+        .map(|item| Post {
+            link: format!("https://imgur.com/gallery/{}", item.id),
+            id: item.id,
+            title: item.title,
+            datetime: item.datetime,
+        })
+        .collect();
+    for album in albums {
+        if !posts.iter().any(|post| post.id == album.id) {
+            posts.push(Post {
+                link: format!("https://imgur.com/a/{}", album.id),
+                id: album.id,
+                title: album.title,
+                datetime: album.datetime,
+            });
+        }
+    }
+    posts.sort_by_key(|post| std::cmp::Reverse(post.datetime));
+    Ok(posts)
+}
+
+/// Writes an index of `posts` to `index.json` and a readable `index.md` in `dir`, which the
+/// posts are downloaded into.
+pub fn write_index(dir: &Path, username: &str, posts: &[Post]) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+
+    let mut markdown = format!("# Posts by {}\n\n", username);
+    for post in posts {
+        markdown.push_str(&format!(
+            "- {} {}\n",
+            DateTime::from_unix(post.datetime).format("%Y-%m-%d"),
+            links::link(
+                LinkFormat::Markdown,
+                &post.link,
+                post.title.as_deref().unwrap_or(&post.id)
+            )
+        ));
+    }
+
+    for (name, contents) in [
+        ("index.json", serde_json::to_vec_pretty(posts)?),
+        ("index.md", markdown.into_bytes()),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Archives comments to `comments.json`, with every field returned by the API, and a readable
+/// `comments.md` linking to the posts commented on, in `dir`.
+pub fn export_comments(dir: &Path, username: &str, comments: &[ImgurComment]) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+
+    let mut markdown = format!("# Comments by {}\n\n", username);
+    for comment in comments {
+        markdown.push_str(&format!(
+            "## {} on <https://imgur.com/gallery/{}/comment/{}>\n\n{}\n\n",
+            DateTime::from_unix(comment.datetime).format("%Y-%m-%d %H:%M"),
+            comment.image_id,
+            comment.id,
+            comment.comment.trim_end()
+        ));
+    }
+
+    for (name, contents) in [
+        ("comments.json", serde_json::to_vec_pretty(comments)?),
+        ("comments.md", markdown.into_bytes()),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+    }
+    Ok(())
+}
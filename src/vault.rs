@@ -0,0 +1,326 @@
+//! Encrypted record of anonymous uploads, whose deletehashes are the only way to delete them.
+//!
+//! The vault is encrypted and authenticated with ChaCha20-Poly1305, using a key derived from a
+//! passphrase with Argon2id. The passphrase is read from `$IMGURS_VAULT_PASSPHRASE`, or
+//! asked for on the terminal. Account bundles are encrypted in the same way.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use argon2::Argon2;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Followed by a format version byte.
+const MAGIC: &[u8; 7] = b"IMGURSV";
+const VERSION: u8 = b'2';
+/// Files written with hand-rolled ChaCha20 and HMAC-SHA256, see [`decrypt_legacy`].
+const LEGACY_VERSION: u8 = b'1';
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Memory used to derive keys, the minimum recommended for Argon2id by OWASP.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+/// Passes of Argon2id used to derive keys for new files. Each file records its own count.
+pub const KDF_ITERATIONS: u32 = 2;
+
+/// What was uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadKind {
+    Image,
+    Album,
+}
+
+impl UploadKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            UploadKind::Image => "image",
+            UploadKind::Album => "album",
+        }
+    }
+}
+
+/// An anonymous upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub kind: UploadKind,
+    pub id: String,
+    pub deletehash: String,
+    pub link: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Where an uploaded image was read from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// ID of the album an image was uploaded into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Unix timestamp of the upload.
+    pub datetime: i64,
+}
+
+pub struct Vault {
+    path: PathBuf,
+    passphrase: String,
+    pub uploads: Vec<Upload>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    crate::paths::data_dir().map(|dir| dir.join("uploads.vault"))
+}
+
+impl Vault {
+    /// Opens the vault at `path`, or an empty one if it does not exist yet.
+    pub fn open(path: &Path) -> Result<Vault> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Unable to read vault {}", path.display()))
+            }
+        };
+        let passphrase = read_passphrase("IMGURS_VAULT_PASSPHRASE", "upload vault")?;
+        let uploads = match contents {
+            Some(contents) => serde_json::from_slice(&decrypt(&passphrase, &contents)?)
+                .with_context(|| format!("Unable to parse vault {}", path.display()))?,
+            None => vec![],
+        };
+        Ok(Vault {
+            path: path.to_owned(),
+            passphrase,
+            uploads,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents = encrypt(
+            &self.passphrase,
+            &serde_json::to_vec(&self.uploads)?,
+            KDF_ITERATIONS,
+        )?;
+        let temp_path = self.path.with_extension("vault.tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&temp_path)?.write_all(&contents)?;
+        std::fs::rename(&temp_path, &self.path).with_context(|| "Unable to save vault")?;
+        Ok(())
+    }
+
+    /// Adds an upload and saves the vault, so that it is not lost if a later upload fails.
+    pub fn record(&mut self, upload: Upload) -> Result<()> {
+        self.uploads.push(upload);
+        self.save()
+    }
+
+    /// Returns the upload with the given ID or deletehash.
+    pub fn get(&self, id: &str) -> Option<&Upload> {
+        self.uploads
+            .iter()
+            .find(|upload| upload.id == id || upload.deletehash == id)
+    }
+
+    /// Removes and returns the upload with the given ID or deletehash.
+    pub fn take(&mut self, id: &str) -> Option<Upload> {
+        let index = self
+            .uploads
+            .iter()
+            .position(|upload| upload.id == id || upload.deletehash == id)?;
+        Some(self.uploads.remove(index))
+    }
+}
+
+/// Reads the passphrase of `what` from the environment variable `var`, or the terminal.
+pub fn read_passphrase(var: &str, what: &str) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(var) {
+        return Ok(passphrase);
+    }
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        return Err(anyhow!("Set ${} to give the {} passphrase", var, what));
+    }
+    print!("Passphrase for the {}: ", what);
+    std::io::stdout().flush()?;
+    let passphrase = read_line_hidden()?;
+    println!();
+    if passphrase.is_empty() {
+        return Err(anyhow!("The {} passphrase must not be empty", what));
+    }
+    Ok(passphrase)
+}
+
+/// Reads a line from the terminal without echoing it.
+#[cfg(unix)]
+fn read_line_hidden() -> Result<String> {
+    // SAFETY: `tcgetattr` initializes the zeroed `termios`, which is only passed back to
+    // `tcsetattr` for standard input.
+    let original = unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut hidden = termios;
+        hidden.c_lflag &= !libc::ECHO;
+        hidden.c_lflag |= libc::ECHONL;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden);
+        termios
+    };
+    let mut line = String::new();
+    let result = std::io::stdin().read_line(&mut line);
+    // SAFETY: as above.
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original) };
+    result?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+#[cfg(not(unix))]
+fn read_line_hidden() -> Result<String> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Derives a key with Argon2id, using `iterations` passes over `ARGON2_MEMORY_KIB` of memory.
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<[u8; 32]> {
+    let params = argon2::Params::new(ARGON2_MEMORY_KIB, iterations, 1, Some(32))
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    let mut key = [0; 32];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Unable to derive key: {}", e))?;
+    Ok(key)
+}
+
+/// Returns `MAGIC || VERSION || iterations || salt || nonce || ciphertext`, where the
+/// ciphertext is sealed with ChaCha20-Poly1305 and the header is authenticated along with it.
+/// The key is derived from `passphrase` with `iterations` passes of Argon2id.
+pub fn encrypt(passphrase: &str, plaintext: &[u8], iterations: u32) -> Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut salt)
+        .and_then(|()| getrandom::getrandom(&mut nonce))
+        .map_err(|e| anyhow!("Unable to generate random bytes: {}", e))?;
+    let key = derive_key(passphrase, &salt, iterations)?;
+
+    let mut output = MAGIC.to_vec();
+    output.push(VERSION);
+    output.extend(iterations.to_be_bytes());
+    output.extend(salt);
+    output.extend(nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let payload = Payload {
+        msg: plaintext,
+        aad: &output,
+    };
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| anyhow!("Unable to encrypt"))?;
+    output.extend(ciphertext);
+    Ok(output)
+}
+
+pub fn decrypt(passphrase: &str, contents: &[u8]) -> Result<Vec<u8>> {
+    let not_encrypted = || anyhow!("Not encrypted by imgurs");
+    let rest = contents.strip_prefix(MAGIC).ok_or_else(not_encrypted)?;
+    match rest.split_first() {
+        Some((&VERSION, rest)) => {
+            let header_len = contents.len() - rest.len() + 4 + SALT_LEN + NONCE_LEN;
+            if contents.len() < header_len + TAG_LEN {
+                return Err(not_encrypted());
+            }
+            let (header, ciphertext) = contents.split_at(header_len);
+            let (iterations, rest) = rest.split_at(4);
+            let (salt, rest) = rest.split_at(SALT_LEN);
+            let nonce = &rest[..NONCE_LEN];
+            let iterations = u32::from_be_bytes(iterations.try_into().unwrap());
+            let key = derive_key(passphrase, salt, iterations)?;
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+            let payload = Payload {
+                msg: ciphertext,
+                aad: header,
+            };
+            cipher
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .map_err(|_| anyhow!("Wrong passphrase, or the file has been modified"))
+        }
+        Some((&LEGACY_VERSION, rest)) => decrypt_legacy(passphrase, contents, rest),
+        _ => Err(not_encrypted()),
+    }
+}
+
+/// Decrypts files written before the current version: `MAGIC || '1' || salt || nonce ||
+/// ciphertext || HMAC(everything before)`, encrypted with ChaCha20 starting at block 1 and
+/// authenticated with HMAC-SHA256, with keys derived with 100,000 rounds of PBKDF2.
+fn decrypt_legacy(passphrase: &str, contents: &[u8], rest: &[u8]) -> Result<Vec<u8>> {
+    const LEGACY_ITERATIONS: u32 = 100_000;
+    const LEGACY_TAG_LEN: usize = 32;
+    if rest.len() < SALT_LEN + NONCE_LEN + LEGACY_TAG_LEN {
+        return Err(anyhow!("Not encrypted by imgurs"));
+    }
+    let (authenticated, tag) = contents.split_at(contents.len() - LEGACY_TAG_LEN);
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, _) = rest.split_at(NONCE_LEN);
+    let mut keys = [0; 64];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, LEGACY_ITERATIONS, &mut keys);
+    let (encryption_key, mac_key) = keys.split_at(32);
+
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(mac_key).expect("HMAC takes keys of any length");
+    mac.update(authenticated);
+    mac.verify_slice(tag)
+        .map_err(|_| anyhow!("Wrong passphrase, or the file has been modified"))?;
+
+    let header_len = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+    let mut plaintext = authenticated[header_len..].to_vec();
+    let mut cipher = ChaCha20::new(encryption_key.into(), nonce.into());
+    cipher.seek(64);
+    cipher.apply_keystream(&mut plaintext);
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_contents_round_trip() {
+        let encrypted = encrypt("passphrase", b"[]", 1).unwrap();
+        assert_eq!(decrypt("passphrase", &encrypted).unwrap(), b"[]");
+        assert!(decrypt("wrong", &encrypted).is_err());
+
+        let mut modified = encrypted.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(decrypt("passphrase", &modified).is_err());
+        // The iteration count is authenticated too.
+        let mut modified = encrypted;
+        modified[MAGIC.len() + 4] ^= 1;
+        assert!(decrypt("passphrase", &modified).is_err());
+    }
+
+    #[test]
+    fn derives_keys_at_full_cost() {
+        let encrypted = encrypt("passphrase", b"[]", KDF_ITERATIONS).unwrap();
+        let iterations = &encrypted[MAGIC.len() + 1..MAGIC.len() + 5];
+        assert_eq!(iterations, KDF_ITERATIONS.to_be_bytes());
+        assert_eq!(decrypt("passphrase", &encrypted).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn reads_legacy_vaults() {
+        // Written by the previous format with the passphrase "passphrase".
+        let legacy = include_bytes!("../tests/fixtures/legacy.vault");
+        assert_eq!(decrypt("passphrase", legacy).unwrap(), b"[]");
+    }
+}
@@ -0,0 +1,74 @@
+//! Submits URLs to the Wayback Machine's Save Page Now API, so that public snapshots exist
+//! alongside downloaded copies.
+//!
+//! See <https://docs.google.com/document/d/1Nsv52MvSjbLb2PCpHlat0gkzw0EvtSgpKHu4mk0MnrA>.
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::archive::Credentials;
+
+const SAVE_URL: &str = "https://web.archive.org/save";
+
+#[derive(Debug, Deserialize)]
+struct SaveResponse {
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Requests a snapshot of `url`. With credentials, the capture is queued through the
+/// authenticated API, which has higher rate limits; otherwise the anonymous endpoint is used.
+pub async fn save(client: &Client, url: &str, credentials: Option<&Credentials>) -> Result<()> {
+    match credentials {
+        Some(credentials) => {
+            let response = client
+                .post(SAVE_URL)
+                .header("Accept", "application/json")
+                .header(
+                    "Authorization",
+                    format!("LOW {}:{}", credentials.access_key, credentials.secret_key),
+                )
+                .form(&[("url", url)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<SaveResponse>()
+                .await
+                .with_context(|| "Unable to parse Save Page Now response")?;
+            if response.status.as_deref() == Some("error") {
+                return Err(anyhow!(
+                    "{}",
+                    response
+                        .message
+                        .unwrap_or_else(|| "Unknown error".to_owned())
+                ));
+            }
+        }
+        None => {
+            client
+                .get(format!("{}/{}", SAVE_URL, url))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves each of `urls` in order, returning the URLs which could not be saved.
+pub async fn save_all(
+    client: Client,
+    urls: Vec<String>,
+    credentials: Option<Credentials>,
+) -> Vec<(String, anyhow::Error)> {
+    let mut failed = vec![];
+    for url in urls {
+        if let Err(e) = save(&client, &url, credentials.as_ref()).await {
+            failed.push((url, e));
+        }
+    }
+    failed
+}
@@ -0,0 +1,280 @@
+//! Writes downloaded files on tokio's blocking thread pool, so that a slow disk, such as a
+//! network share, does not hold up reading from the network, and a slow network does not hold up
+//! writing. With the `io-uring` feature on Linux, files are instead written through io_uring on
+//! a thread of its own, falling back to the thread pool where io_uring is unavailable.
+
+use std::fs::File;
+use std::io::{IoSlice, Write};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+/// Number of chunks which may wait to be written before reading from the network waits too.
+const QUEUED_CHUNKS: usize = 32;
+
+/// Caps the number of bytes received but not yet written by any number of [`FileWriter`]s
+/// sharing it, so that a fast network and a slow disk do not use up memory.
+#[derive(Debug, Clone)]
+pub struct BufferLimit {
+    permits: Arc<Semaphore>,
+    max_bytes: u32,
+}
+
+impl BufferLimit {
+    pub fn new(max_bytes: u64) -> BufferLimit {
+        let max_bytes = max_bytes.clamp(1, u64::from(u32::MAX)) as u32;
+        BufferLimit {
+            permits: Arc::new(Semaphore::new(max_bytes as usize)),
+            max_bytes,
+        }
+    }
+
+    /// Waits until `bytes` more may be buffered, until the returned permit is dropped.
+    async fn reserve(&self, bytes: usize) -> OwnedSemaphorePermit {
+        // A chunk larger than the whole limit waits for everything else to be written instead.
+        let bytes = (bytes as u64).min(u64::from(self.max_bytes)) as u32;
+        let permits = self.permits.clone();
+        permits
+            .acquire_many_owned(bytes)
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+type Chunk = (Bytes, Option<OwnedSemaphorePermit>);
+
+/// Where chunks are being written.
+enum Task {
+    Blocking(JoinHandle<Result<()>>),
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    Uring(tokio::sync::oneshot::Receiver<Result<()>>),
+}
+
+/// Writes chunks to a file in the background, in the order they are given.
+pub struct FileWriter {
+    chunks: mpsc::Sender<Chunk>,
+    task: Task,
+    limit: Option<BufferLimit>,
+}
+
+impl FileWriter {
+    pub fn new(file: File, limit: Option<BufferLimit>) -> FileWriter {
+        let (chunks, queue) = mpsc::channel::<Chunk>(QUEUED_CHUNKS);
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let (file, queue) = match uring::write(file, queue) {
+            Ok(done) => {
+                return FileWriter {
+                    chunks,
+                    task: Task::Uring(done),
+                    limit,
+                }
+            }
+            Err(unsubmitted) => unsubmitted,
+        };
+        FileWriter {
+            chunks,
+            task: Task::Blocking(write_blocking(file, queue)),
+            limit,
+        }
+    }
+
+    /// Queues `chunk` to be written, waiting while the queue or the buffer limit is full.
+    /// Returns `false` if writing has failed, see [`FileWriter::finish`] for why.
+    pub async fn write(&self, chunk: Bytes) -> bool {
+        let permit = match &self.limit {
+            Some(limit) => Some(limit.reserve(chunk.len()).await),
+            None => None,
+        };
+        self.chunks.send((chunk, permit)).await.is_ok()
+    }
+
+    /// Waits until every queued chunk has been written and the file is closed.
+    pub async fn finish(self) -> Result<()> {
+        drop(self.chunks);
+        match self.task {
+            Task::Blocking(task) => task.await?,
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Task::Uring(done) => done.await?,
+        }
+    }
+}
+
+/// Writes the chunks from `queue` to `file` on the blocking thread pool.
+fn write_blocking(mut file: File, mut queue: mpsc::Receiver<Chunk>) -> JoinHandle<Result<()>> {
+    tokio::task::spawn_blocking(move || {
+        while let Some(chunk) = queue.blocking_recv() {
+            // Write whatever else is already queued along with it, in as few system calls as
+            // possible, which adds up when downloading many small files at once.
+            let mut batch = vec![chunk];
+            while let Ok(chunk) = queue.try_recv() {
+                batch.push(chunk);
+            }
+            let (batch, _permits): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            write_batch(&mut file, &batch).with_context(|| "Unable to write to temporary file")?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes all of `batch` to `output` with vectored writes.
+fn write_batch(output: &mut impl Write, batch: &[Bytes]) -> std::io::Result<()> {
+    let (mut chunk, mut offset) = (0, 0);
+    while chunk < batch.len() {
+        let slices: Vec<IoSlice> = std::iter::once(&batch[chunk][offset..])
+            .chain(batch[chunk + 1..].iter().map(|chunk| &chunk[..]))
+            .map(IoSlice::new)
+            .collect();
+        let mut written = output.write_vectored(&slices)?;
+        if written == 0 && slices.iter().any(|slice| !slice.is_empty()) {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        // Skip past what was written, which may end partway through a chunk.
+        while chunk < batch.len() && written >= batch[chunk].len() - offset {
+            written -= batch[chunk].len() - offset;
+            chunk += 1;
+            offset = 0;
+        }
+        offset += written;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring {
+    use std::fs::File;
+    use std::io::Seek;
+    use std::sync::OnceLock;
+
+    use anyhow::{Context, Result};
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::Chunk;
+
+    /// A file and the chunks to write to it, and where to report how that went.
+    type Job = (File, mpsc::Receiver<Chunk>, oneshot::Sender<Result<()>>);
+
+    /// Jobs for the io_uring thread, or `None` if io_uring could not be set up, e.g. because
+    /// the kernel is too old or a seccomp filter forbids it.
+    static JOBS: OnceLock<Option<mpsc::UnboundedSender<Job>>> = OnceLock::new();
+
+    /// Starts the thread which runs tokio-uring's runtime, which cannot share a thread with the
+    /// tokio runtime downloads run on. It writes every file at once, each in a task of its own.
+    fn start() -> Option<mpsc::UnboundedSender<Job>> {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<Job>();
+        let (started, result) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name("imgurs-io-uring".to_owned())
+            .spawn(move || {
+                let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = started.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = started.send(Ok(()));
+                runtime.block_on(async move {
+                    while let Some((file, chunks, done)) = queue.recv().await {
+                        tokio_uring::spawn(async move {
+                            let _ = done.send(write_chunks(file, chunks).await);
+                        });
+                    }
+                });
+            })
+            .ok()?;
+        result.recv().ok()?.ok().map(|()| jobs)
+    }
+
+    /// Writes the chunks from `queue` to `file` through io_uring, or hands them back if
+    /// io_uring is unavailable.
+    pub(super) fn write(
+        file: File,
+        queue: mpsc::Receiver<Chunk>,
+    ) -> Result<oneshot::Receiver<Result<()>>, (File, mpsc::Receiver<Chunk>)> {
+        let Some(jobs) = JOBS.get_or_init(start) else {
+            return Err((file, queue));
+        };
+        let (done, result) = oneshot::channel();
+        jobs.send((file, queue, done))
+            .map_err(|mpsc::error::SendError((file, queue, _))| (file, queue))?;
+        Ok(result)
+    }
+
+    async fn write_chunks(mut file: File, mut queue: mpsc::Receiver<Chunk>) -> Result<()> {
+        // Writes start at the file's position, except that files opened to append to, as those
+        // of resumed downloads are, are always appended to.
+        let mut position = file.stream_position()?;
+        let file = tokio_uring::fs::File::from_std(file);
+        while let Some((chunk, _permit)) = queue.recv().await {
+            let len = chunk.len() as u64;
+            let (result, _) = file.write_all_at(chunk, position).await;
+            result.with_context(|| "Unable to write to temporary file")?;
+            position += len;
+        }
+        file.close().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most 3 bytes per write.
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_partially_written_batches() {
+        let batch = ["ab", "", "cdefg", "h"].map(Bytes::from);
+        let mut output = Trickle(vec![]);
+        write_batch(&mut output, &batch).unwrap();
+        assert_eq!(output.0, b"abcdefgh");
+    }
+
+    #[tokio::test]
+    async fn writes_chunks_in_order() {
+        let path = std::env::temp_dir().join(format!("imgurs-writer-{}", std::process::id()));
+        let limit = BufferLimit::new(4);
+        let writer = FileWriter::new(File::create(&path).unwrap(), Some(limit.clone()));
+        for chunk in ["one ", "two ", "three"] {
+            assert!(writer.write(Bytes::from(chunk)).await);
+        }
+        writer.finish().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one two three");
+        // Everything buffered was released once written.
+        assert_eq!(limit.permits.available_permits(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn appends_to_resumed_files() {
+        let path = std::env::temp_dir().join(format!("imgurs-resumed-{}", std::process::id()));
+        std::fs::write(&path, "one ").unwrap();
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let writer = FileWriter::new(file, None);
+        for chunk in ["two ", "three"] {
+            assert!(writer.write(Bytes::from(chunk)).await);
+        }
+        writer.finish().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one two three");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
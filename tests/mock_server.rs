@@ -0,0 +1,1994 @@
+//! Downloads albums from a local mock of the Imgur API.
+
+#![cfg(feature = "cli")]
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use imgurs::api::{
+    fetch_album, fetch_gallery_post, fetch_oauth_tokens, ApiClient, Auth, OAuthGrant,
+};
+use imgurs::download::{Parallelism, PartialPolicy, PauseToken};
+use imgurs::error::{categorize, retry_after, ErrorCategory};
+use imgurs::plan::DownloadPlan;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use tokio_util::sync::CancellationToken;
+
+const IMAGE: &[u8] = b"not really a png";
+
+static SLOW_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static FLAKY_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static THROTTLED_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static HELD_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static MAX_HELD_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static WAYBACK_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static QUEUED_URLS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+fn album_json(addr: SocketAddr, album_id: &str, image_id: &str) -> String {
+    serde_json::json!({
+        "status": 200,
+        "data": {
+            "id": album_id,
+            "title": format!("Album {}", album_id),
+            "description": "A story in several parts.",
+            "images": [{
+                "id": image_id,
+                "title": null,
+                "description": null,
+                "link": format!("http://{}/{}.png", addr, image_id),
+                "datetime": 1_600_000_000,
+                "size": IMAGE.len(),
+                "type": "image/png",
+            }],
+        },
+    })
+    .to_string()
+}
+
+/// Serves:
+/// - album `mock` with image `image1`, also as a gallery post;
+/// - album `deleted` with image `image2`, which is only available from `/mirror/image2` and as a
+///   Wayback Machine snapshot;
+/// - album `slow`, which takes 100ms to respond;
+/// - album `lingering`, whose image takes 300ms to respond, and `lengthy`, whose image takes 1.5s;
+/// - gallery post `single`, which is a single image;
+/// - image `image1`;
+/// - album `empty`, which has no images;
+/// - album `limited`, which is rate limited, and `exhausted`, which has no user credits left;
+/// - album `drained`, after which only one API credit remains;
+/// - album `mislabeled`, whose PNG is really a JPEG;
+/// - album `partial`, whose image supports range requests;
+/// - album `metered`, with a small image `image1` and a video;
+/// - album `flaky`, whose image fails the first time it is requested;
+/// - album `throttled`, whose image is rate limited the first time it is requested;
+/// - image `/gone.png`, which redirects to Imgur's `/removed.png` placeholder like removed media;
+/// - images under `/held/`, which take 300ms each, keeping count of how many are requested at once;
+/// - a comment, the gallery post `mock` and the album `partial` by user `someone`, and albums `mock` and `partial` of the authenticated user and their favorites, `mock` and `single`;
+/// - two pages of the top posts of the `aww` subreddit this week, `mock` and again `mock` and
+///   `single`;
+/// - the viral posts tagged `cats` this week, `mock` and `single`, which is NSFW;
+/// - the newest posts matching the search `cats AND dogs`, `mock` and `single`;
+/// - the front page, `mock` and `single`;
+/// - uploads of images, all as `uploaded` with deletehash `secret`, and of albums, as `created`
+///   with deletehash `albumsecret`;
+/// - the remaining API credits;
+/// - OAuth tokens for user `someone`;
+/// - a queue like `imgurs serve`'s at `/queue`, recording the URLs posted to it.
+async fn respond(addr: SocketAddr, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().to_owned();
+    let response = match path.as_str() {
+        "/3/album/mock" => Response::new(Body::from(album_json(addr, "mock", "image1"))),
+        "/3/album/deleted" => Response::new(Body::from(album_json(addr, "deleted", "image2"))),
+        "/3/album/slow" => {
+            SLOW_REQUESTS.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Response::new(Body::from(album_json(addr, "slow", "image1")))
+        }
+        "/3/album/limited" => Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("Retry-After", "30")
+            .header("X-RateLimit-ClientLimit", "12500")
+            .header("X-RateLimit-ClientRemaining", "0")
+            .body(Body::from(r#"{"status": 429, "success": false}"#))
+            .unwrap(),
+        "/3/album/drained" => Response::builder()
+            .header("X-RateLimit-UserRemaining", "1")
+            .body(Body::from(album_json(addr, "drained", "image1")))
+            .unwrap(),
+        "/3/album/exhausted" => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("X-RateLimit-UserRemaining", "0")
+                .header("X-RateLimit-UserReset", (now + 120).to_string())
+                .body(Body::from(r#"{"status": 429, "success": false}"#))
+                .unwrap()
+        }
+        "/oauth2/token" => Response::new(Body::from(
+            r#"{"access_token": "access", "refresh_token": "refresh", "expires_in": 315360000,
+                "token_type": "bearer", "account_username": "someone", "account_id": 1}"#,
+        )),
+        "/3/credits" => Response::new(Body::from(
+            r#"{"status": 200, "data": {"UserLimit": 2000, "UserRemaining": 1990,
+                "UserReset": 4102444800, "ClientLimit": 12500, "ClientRemaining": 12000}}"#,
+        )),
+        "/3/album/empty" => Response::new(Body::from(
+            r#"{"status": 200, "data": {"id": "empty", "title": null, "images": []}}"#,
+        )),
+        "/3/gallery/album/mock" => Response::new(Body::from(album_json(addr, "mock", "image1"))),
+        "/3/gallery/image/single" => Response::new(Body::from(
+            serde_json::json!({
+                "status": 200,
+                "data": {
+                    "id": "single",
+                    "title": "Single",
+                    "description": null,
+                    "datetime": 1_600_000_000,
+                    "is_album": false,
+                    "tags": [{ "name": "cats" }],
+                    "link": format!("http://{}/single.png", addr),
+                    "size": IMAGE.len(),
+                    "type": "image/png",
+                },
+            })
+            .to_string(),
+        )),
+        "/3/image/image1" if request.method() == hyper::Method::GET => Response::new(Body::from(
+            serde_json::json!({
+                "status": 200,
+                "data": {
+                    "id": "image1",
+                    "title": "Image",
+                    "description": null,
+                    "link": format!("http://{}/image1.png", addr),
+                    "datetime": 1_600_000_000,
+                    "size": IMAGE.len(),
+                    "type": "image/png",
+                },
+            })
+            .to_string(),
+        )),
+        "/3/account/someone/comments/newest/0" => Response::new(Body::from(
+            serde_json::json!({
+                "status": 200,
+                "data": [{
+                    "id": 7,
+                    "image_id": "mock",
+                    "comment": "Nice cat",
+                    "author": "someone",
+                    "datetime": 1_600_000_000,
+                    "vote": null,
+                }],
+            })
+            .to_string(),
+        )),
+        "/3/account/someone/submissions/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true,
+                "datetime": 1600000000}]}"#,
+        )),
+        "/3/account/someone/albums/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "datetime": 1600000000},
+                {"id": "partial", "title": "Album partial", "datetime": 1500000000}]}"#,
+        )),
+        "/3/account/me/albums/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock"},
+                {"id": "partial", "title": "Album partial"}]}"#,
+        )),
+        "/3/account/me/favorites/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true},
+                {"id": "single", "title": "Single", "is_album": false}]}"#,
+        )),
+        "/3/gallery/r/aww/top/week/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true}]}"#,
+        )),
+        "/3/gallery/r/aww/top/week/1" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true},
+                {"id": "single", "title": "Single", "is_album": false}]}"#,
+        )),
+        "/3/gallery/r/aww/top/week/2" => {
+            Response::new(Body::from(r#"{"status": 200, "data": []}"#))
+        }
+        "/3/gallery/t/cats/viral/week/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": {"name": "cats", "items": [
+                {"id": "mock", "title": "Album mock", "is_album": true, "nsfw": null},
+                {"id": "single", "title": "Single", "is_album": false, "nsfw": true}]}}"#,
+        )),
+        "/3/gallery/t/cats/viral/week/1" => Response::new(Body::from(
+            r#"{"status": 200, "data": {"name": "cats", "items": []}}"#,
+        )),
+        "/3/gallery/search/time/all/0"
+            if request.uri().query() == Some("perPage=100&q=cats%20AND%20dogs") =>
+        {
+            Response::new(Body::from(
+                r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true},
+                    {"id": "single", "title": "Single", "is_album": false}]}"#,
+            ))
+        }
+        "/3/image" if request.method() == hyper::Method::POST => Response::new(Body::from(
+            r#"{"status": 200, "data": {"id": "uploaded", "deletehash": "secret",
+                "link": "https://i.imgur.com/uploaded.png"}}"#,
+        )),
+        "/3/album" if request.method() == hyper::Method::POST => Response::new(Body::from(
+            r#"{"status": 200, "data": {"id": "created", "deletehash": "albumsecret"}}"#,
+        )),
+        "/3/image/secret" | "/3/album/albumsecret" if request.method() == hyper::Method::DELETE => {
+            Response::new(Body::from(r#"{"status": 200, "data": true}"#))
+        }
+        "/3/gallery/search/time/all/1" => {
+            Response::new(Body::from(r#"{"status": 200, "data": []}"#))
+        }
+        "/3/album/metered" => Response::new(Body::from(
+            serde_json::json!({
+                "status": 200,
+                "data": {
+                    "id": "metered",
+                    "title": "Album metered",
+                    "images": [{
+                        "id": "image1",
+                        "title": null,
+                        "description": null,
+                        "link": format!("http://{}/image1.png", addr),
+                        "datetime": 1_600_000_000,
+                        "size": IMAGE.len(),
+                        "type": "image/png",
+                        "width": 100,
+                        "height": 100,
+                    }, {
+                        "id": "video",
+                        "title": null,
+                        "description": null,
+                        "link": format!("http://{}/video.mp4", addr),
+                        "datetime": 1_600_000_000,
+                        "size": 1000,
+                        "type": "video/mp4",
+                        "animated": true,
+                    }],
+                },
+            })
+            .to_string(),
+        )),
+        "/3/gallery/hot/viral/week/0" => Response::new(Body::from(
+            r#"{"status": 200, "data": [{"id": "mock", "title": "Album mock", "is_album": true},
+                {"id": "single", "title": "Single", "is_album": false}]}"#,
+        )),
+        "/3/album/mislabeled" => Response::new(Body::from(album_json(addr, "mislabeled", "jpeg"))),
+        "/jpeg.png" => Response::new(Body::from(&b"\xff\xd8\xff\xe0 a jpeg"[..])),
+        "/3/album/partial" => Response::new(Body::from(album_json(addr, "partial", "partial"))),
+        "/partial.png" => match request.headers().get("range") {
+            Some(range) => {
+                let range = range.to_str().unwrap();
+                let start: usize = range["bytes=".len()..range.len() - 1].parse().unwrap();
+                Response::builder()
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        "content-range",
+                        format!("bytes {}-{}/{}", start, IMAGE.len() - 1, IMAGE.len()),
+                    )
+                    .body(Body::from(&IMAGE[start..]))
+                    .unwrap()
+            }
+            None => Response::new(Body::from(IMAGE)),
+        },
+        "/3/album/flaky" => Response::new(Body::from(album_json(addr, "flaky", "flaky"))),
+        "/3/album/lingering" => {
+            Response::new(Body::from(album_json(addr, "lingering", "lingering")))
+        }
+        "/lingering.png" => {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Response::new(Body::from(IMAGE))
+        }
+        "/3/album/lengthy" => Response::new(Body::from(album_json(addr, "lengthy", "lengthy"))),
+        "/lengthy.png" => {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            Response::new(Body::from(IMAGE))
+        }
+        "/flaky.png" if FLAKY_REQUESTS.fetch_add(1, Ordering::SeqCst) == 0 => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::empty())
+            .unwrap(),
+        "/3/album/throttled" => {
+            Response::new(Body::from(album_json(addr, "throttled", "throttled")))
+        }
+        "/throttled.png" if THROTTLED_REQUESTS.fetch_add(1, Ordering::SeqCst) == 0 => {
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", "1")
+                .body(Body::empty())
+                .unwrap()
+        }
+        "/flaky.png" | "/throttled.png" | "/image1.png" | "/single.png" | "/mirror/image2" => {
+            Response::new(Body::from(IMAGE))
+        }
+        "/queue" if request.method() == hyper::Method::POST => {
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            QUEUED_URLS
+                .lock()
+                .unwrap()
+                .push(String::from_utf8(body.to_vec()).unwrap());
+            Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::from("Queued 1 album(s)\n"))
+                .unwrap()
+        }
+        path if path == format!("/web/2id_/http://{}/image2.png", addr) => {
+            WAYBACK_REQUESTS.fetch_add(1, Ordering::SeqCst);
+            Response::new(Body::from(IMAGE))
+        }
+        "/gone.png" => Response::builder()
+            .status(StatusCode::FOUND)
+            .header("location", "/removed.png")
+            .body(Body::empty())
+            .unwrap(),
+        "/removed.png" => Response::new(Body::from("placeholder")),
+        path if path.starts_with("/held/") => {
+            let held = HELD_REQUESTS.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_HELD_REQUESTS.fetch_max(held, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            HELD_REQUESTS.fetch_sub(1, Ordering::SeqCst);
+            Response::new(Body::from(IMAGE))
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(
+                r#"{"status": 404, "success": false, "data": {"error": "Not found"}}"#,
+            ))
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+fn start_server() -> SocketAddr {
+    let incoming = AddrIncoming::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = incoming.local_addr();
+    let server = Server::builder(incoming).serve(make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |request| respond(addr, request)))
+    }));
+    tokio::spawn(server);
+    addr
+}
+
+/// Downloads `album` into a new directory, returning whether imgurs succeeded and the directory.
+async fn download(addr: SocketAddr, album: &str, args: &[String]) -> (bool, PathBuf) {
+    static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+    // Albums may be URLs, which must not add directories to the path.
+    let label: String = album
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let output = std::env::temp_dir().join(format!(
+        "imgurs-mock-{}-{}-{}",
+        label,
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&output);
+    (download_into(addr, album, args, &output).await, output)
+}
+
+/// Downloads `album` into `output`, which may already exist, returning whether imgurs succeeded.
+async fn download_into(addr: SocketAddr, album: &str, args: &[String], output: &Path) -> bool {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .arg(album)
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .args(["--allow-host", "127.0.0.1", "--imgur-client-id", "test"])
+        .args(args)
+        .arg("--output")
+        .arg(output)
+        .env("XDG_DATA_HOME", output.join("data"));
+    let status = tokio::task::spawn_blocking(move || command.status())
+        .await
+        .unwrap()
+        .unwrap();
+    status.success()
+}
+
+/// Downloaded media, without `post.md`.
+fn media_files(output: &Path) -> Vec<(String, u64)> {
+    let mut files = imgurs::download::album_files(output).unwrap();
+    files.retain(|(name, _)| name != "post.md");
+    files
+}
+
+fn assert_downloaded(output: &Path) {
+    let files = media_files(output);
+    assert_eq!(files.len(), 1);
+    assert_eq!(std::fs::read(output.join(&files[0].0)).unwrap(), IMAGE);
+}
+
+#[tokio::test]
+async fn downloads_album_from_mock_api() {
+    let addr = start_server();
+    let (success, output) = download(addr, "mock", &[]).await;
+    assert!(success);
+    assert_downloaded(&output);
+    let post = std::fs::read_to_string(output.join("post.md")).unwrap();
+    assert!(post.starts_with("# Album mock\n\nA story in several parts.\n"));
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn records_downloads_in_audit_log() {
+    let addr = start_server();
+    let audit_log =
+        std::env::temp_dir().join(format!("imgurs-mock-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&audit_log);
+    let args = [
+        "--audit-log".to_owned(),
+        audit_log.to_string_lossy().into_owned(),
+    ];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    // Downloading again skips the existing file.
+    assert!(download_into(addr, "mock", &args, &output).await);
+
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&audit_log)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            let timestamp = entry.as_object_mut().unwrap().remove("timestamp").unwrap();
+            assert!(timestamp.is_string());
+            entry
+        })
+        .collect();
+    let file = serde_json::json!({
+        "album": "mock",
+        "id": "image1",
+        "url": format!("http://{}/image1.png", addr),
+        "path": output.join(&media_files(&output)[0].0),
+    });
+    let metadata =
+        serde_json::json!({ "action": "metadata", "album": "mock", "status": 200, "files": 1 });
+    let mut downloaded = file.clone();
+    downloaded["action"] = "download".into();
+    downloaded["bytes"] = IMAGE.len().into();
+    let mut skipped = file;
+    skipped["action"] = "skip".into();
+    assert_eq!(entries, [metadata.clone(), downloaded, metadata, skipped]);
+    std::fs::remove_file(&audit_log).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn names_files_with_template() {
+    let addr = start_server();
+    let args = [
+        "--filename-template".to_owned(),
+        "{album_id}-{date:%Y}[ - {title}].{ext}".to_owned(),
+    ];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_eq!(media_files(&output)[0].0, "mock-2020.png");
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn uses_defaults_from_config_file() {
+    let addr = start_server();
+    let config =
+        std::env::temp_dir().join(format!("imgurs-mock-config-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        "[defaults]\nfilename_template = \"{id}.{ext}\"\nretries = 0\n",
+    )
+    .unwrap();
+    let args = ["--config".to_owned(), config.to_string_lossy().into_owned()];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_eq!(media_files(&output)[0].0, "image1.png");
+    std::fs::remove_dir_all(&output).unwrap();
+
+    // Options given on the command line take precedence.
+    let args = [
+        &args[..],
+        &[
+            "--filename-template".to_owned(),
+            "{album_id}.{ext}".to_owned(),
+        ],
+    ]
+    .concat();
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_eq!(media_files(&output)[0].0, "mock.png");
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn runs_pipelines_on_downloaded_albums() {
+    let addr = start_server();
+    let config =
+        std::env::temp_dir().join(format!("imgurs-mock-pipeline-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        r#"
+            [pipelines]
+            archive = [
+              { hook = { command = ["touch", "{file}.hooked"], per_file = true } },
+              "xmp",
+              "manifest",
+              "checksums",
+            ]
+        "#,
+    )
+    .unwrap();
+    let args = [
+        "--config".to_owned(),
+        config.to_string_lossy().into_owned(),
+        "--pipeline".to_owned(),
+        "archive".to_owned(),
+    ];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+
+    let image = media_files(&output)
+        .into_iter()
+        .map(|(name, _)| name)
+        .find(|name| name.ends_with(".png"))
+        .unwrap();
+    assert!(output.join(format!("{}.hooked", image)).is_file());
+    let xmp = std::fs::read_to_string(output.join(format!("{}.xmp", image))).unwrap();
+    assert!(xmp.contains("<x:xmpmeta"));
+    let manifest: toml::Value =
+        toml::from_str(&std::fs::read_to_string(output.join("manifest.toml")).unwrap()).unwrap();
+    let album = &manifest["albums"][0];
+    assert_eq!(album["title"].as_str(), Some("Album mock"));
+    assert_eq!(album["images"][0]["path"].as_str(), Some(image.as_str()));
+    assert_eq!(album["images"][0]["id"].as_str(), Some("image1"));
+    let checksums = std::fs::read_to_string(output.join("SHA256SUMS")).unwrap();
+    assert!(checksums
+        .lines()
+        .any(|line| line.ends_with(&format!("  {}", image))));
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[tokio::test]
+async fn selects_config_profiles() {
+    let addr = start_server();
+    let config =
+        std::env::temp_dir().join(format!("imgurs-mock-profiles-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        r#"
+            [defaults]
+            filename_template = "{id}.{ext}"
+
+            [profiles.archive]
+            filename_template = "{album_id}.{ext}"
+
+            [profiles.work]
+            account = "work"
+        "#,
+    )
+    .unwrap();
+    let config = config.to_string_lossy().into_owned();
+    let accounts =
+        std::env::temp_dir().join(format!("imgurs-mock-profiles-{}.json", std::process::id()));
+    std::fs::write(
+        &accounts,
+        r#"{"accounts": {"someone": {"access_token": "token"}}}"#,
+    )
+    .unwrap();
+    let accounts = accounts.to_string_lossy().into_owned();
+    let args = ["--config", &config, "--profile", "archive"].map(str::to_owned);
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_eq!(media_files(&output)[0].0, "mock.png");
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let args = ["--config", &config, "--profile", "missing"].map(str::to_owned);
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(!success);
+    let _ = std::fs::remove_dir_all(&output);
+
+    // The profile's account is not stored, unless another is given on the command line.
+    let args = [
+        "--config",
+        &config,
+        "--profile",
+        "work",
+        "--accounts-file",
+        &accounts,
+    ];
+    let (success, output) = download(addr, "mock", &args.map(str::to_owned)).await;
+    assert!(!success);
+    let _ = std::fs::remove_dir_all(&output);
+    let args = [&args[..], &["--account", "someone"]].concat();
+    let args = args.into_iter().map(str::to_owned).collect::<Vec<_>>();
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::remove_file(&config).unwrap();
+    std::fs::remove_file(&accounts).unwrap();
+}
+
+#[tokio::test]
+async fn fixes_mislabeled_extensions() {
+    let addr = start_server();
+    let args = [
+        "--fix-extensions".to_owned(),
+        "--filename-template".to_owned(),
+        "{id}.{ext}".to_owned(),
+    ];
+    let (success, output) = download(addr, "mislabeled", &args).await;
+    assert!(success);
+    assert!(output.join("jpeg.jpg").is_file());
+    assert!(!output.join("jpeg.png").exists());
+
+    // The renamed file is kept instead of being downloaded again.
+    assert!(download_into(addr, "mislabeled", &args, &output).await);
+    assert!(!output.join("jpeg.png").exists());
+    std::fs::remove_dir_all(&output).unwrap();
+
+    // Files downloaded without --fix-extensions are renamed, and reported, on the next run.
+    let (success, output) = download(addr, "mislabeled", &args[1..]).await;
+    assert!(success);
+    assert!(output.join("jpeg.png").is_file());
+    let audit_log = output.join("audit.jsonl");
+    let args = [&args[..], &["--audit-log".to_owned()]].concat();
+    let args = [args, vec![audit_log.to_string_lossy().into_owned()]].concat();
+    assert!(download_into(addr, "mislabeled", &args, &output).await);
+    assert!(output.join("jpeg.jpg").is_file());
+    assert!(!output.join("jpeg.png").exists());
+    let audit = std::fs::read_to_string(&audit_log).unwrap();
+    let renamed = audit
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|entry| entry["action"] == "rename")
+        .unwrap();
+    assert_eq!(
+        (&renamed["from"], &renamed["to"]),
+        (&"jpeg.png".into(), &"jpeg.jpg".into())
+    );
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn handles_conflicts_with_existing_files() {
+    let addr = start_server();
+    for mode in ["skip", "overwrite", "rename", "error"] {
+        let (success, output) = download(addr, "mock", &[]).await;
+        assert!(success);
+        let name = media_files(&output)[0].0.clone();
+        std::fs::write(output.join(&name), "existing").unwrap();
+
+        let audit_log = output.join("audit.jsonl");
+        let args = [
+            "--on-conflict".to_owned(),
+            mode.to_owned(),
+            "--audit-log".to_owned(),
+            audit_log.to_string_lossy().into_owned(),
+        ];
+        assert!(download_into(addr, "mock", &args, &output).await);
+        let file = std::fs::read_to_string(&audit_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|entry| entry["id"] == "image1")
+            .unwrap();
+        let renamed = name.replace(".png", " (1).png");
+        let mut files = media_files(&output);
+        files.retain(|(name, _)| name != "audit.jsonl");
+        let contents = |name: &str| std::fs::read(output.join(name)).unwrap();
+        match mode {
+            "skip" => {
+                assert_eq!(file["action"], "skip");
+                assert_eq!(contents(&name), b"existing");
+            }
+            "overwrite" => {
+                assert_eq!(file["action"], "download");
+                assert_eq!(contents(&name), IMAGE);
+            }
+            "rename" => {
+                assert_eq!(file["action"], "download");
+                assert_eq!(file["path"], output.join(&renamed).to_str().unwrap());
+                assert_eq!(contents(&name), b"existing");
+                assert_eq!(contents(&renamed), IMAGE);
+            }
+            _ => {
+                assert_eq!(file["action"], "failure");
+                assert!(file["error"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with(": Found existing file"));
+                assert_eq!(contents(&name), b"existing");
+            }
+        }
+        assert_eq!(files.len(), if mode == "rename" { 2 } else { 1 });
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn resumes_interrupted_downloads() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-resume-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    std::fs::create_dir_all(&output).unwrap();
+    // Only the rest of the file is requested, so the start is kept as it was.
+    std::fs::write(output.join("~!1 - partial.png"), "NOT").unwrap();
+    assert!(download_into(addr, "partial", &[], &output).await);
+    let contents = std::fs::read(output.join("1 - partial.png")).unwrap();
+    assert_eq!(contents, [&b"NOT"[..], &IMAGE[3..]].concat());
+    assert!(!output.join("~!1 - partial.png").exists());
+    // Only what was received counts towards the bandwidth used.
+    let stats = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"))
+        .args(["stats", "--bandwidth"])
+        .env("XDG_DATA_HOME", output.join("data"))
+        .output()
+        .unwrap();
+    let stats = String::from_utf8(stats.stdout).unwrap();
+    let total = format!("Total: {} B over all runs", IMAGE.len() - 3);
+    assert!(stats.starts_with(&total), "{}", stats);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn keeps_partial_files_of_failed_downloads() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-keep-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    std::fs::create_dir_all(&output).unwrap();
+    let temp_file = output.join("~!1 - image2.png");
+    std::fs::write(&temp_file, "NOT").unwrap();
+    download_into(addr, "deleted", &["--keep-partial".to_owned()], &output).await;
+    assert!(temp_file.exists());
+    // By default, they are only kept if retrying may help, which it does not for missing media.
+    download_into(addr, "deleted", &[], &output).await;
+    assert!(!temp_file.exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn retries_failed_files() {
+    let addr = start_server();
+    let args = ["--retries".to_owned(), "1".to_owned()];
+    let (success, output) = download(addr, "flaky", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    assert_eq!(FLAKY_REQUESTS.load(Ordering::SeqCst), 2);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn waits_out_rate_limits_separately_from_retries() {
+    let addr = start_server();
+    let args = ["--retries", "0", "--retries-on-429", "1"].map(str::to_owned);
+    let (success, output) = download(addr, "throttled", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    assert_eq!(THROTTLED_REQUESTS.load(Ordering::SeqCst), 2);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_mirrors_for_missing_media() {
+    let addr = start_server();
+    let (_, output) = download(addr, "deleted", &[]).await;
+    assert!(media_files(&output).is_empty());
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let mirror = format!("http://{}/mirror/{{id}}", addr);
+    let args = ["--probe".to_owned(), "--mirror".to_owned(), mirror];
+    let (success, output) = download(addr, "deleted", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn changes_parallelism_while_downloading() {
+    let addr = start_server();
+    let images = (1..=4)
+        .map(|n| {
+            serde_json::json!({
+                "id": format!("held{}", n),
+                "title": null,
+                "description": null,
+                "link": format!("http://{}/held/{}.png", addr, n),
+                "datetime": 1_600_000_000,
+                "size": IMAGE.len(),
+                "type": "image/png",
+            })
+        })
+        .collect::<Vec<_>>();
+    let album = serde_json::from_value(serde_json::json!({
+        "id": "held",
+        "title": null,
+        "images": images,
+    }))
+    .unwrap();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-held-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+
+    let parallelism = Parallelism::new(1);
+    let plan = DownloadPlan::builder(&album)
+        .destination(&output)
+        .shared_parallelism(&parallelism)
+        .build()
+        .unwrap();
+    let download = tokio::spawn(async move {
+        let progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        plan.execute(
+            &reqwest::Client::new(),
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(MAX_HELD_REQUESTS.load(Ordering::SeqCst), 1);
+    // The first file is still being downloaded, but two more are started alongside it.
+    parallelism.set(3);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(HELD_REQUESTS.load(Ordering::SeqCst), 3);
+    let errors = download.await.unwrap().unwrap();
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(MAX_HELD_REQUESTS.load(Ordering::SeqCst), 3);
+    assert_eq!(media_files(&output).len(), 4);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn leaves_files_which_were_not_started_when_stopped() {
+    let addr = start_server();
+    let images = (1..=3)
+        .map(|n| {
+            serde_json::json!({
+                "id": format!("stopped{}", n),
+                "title": null,
+                "description": null,
+                "link": format!("http://{}/lingering.png?{}", addr, n),
+                "datetime": 1_600_000_000,
+                "size": IMAGE.len(),
+                "type": "image/png",
+            })
+        })
+        .collect::<Vec<_>>();
+    let album = serde_json::from_value(serde_json::json!({
+        "id": "stopped",
+        "title": null,
+        "images": images,
+    }))
+    .unwrap();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-stopped-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    std::fs::create_dir_all(&output).unwrap();
+    // Left by an earlier run, to resume from.
+    let temp_file = output.join("~!3 - stopped3.png");
+    std::fs::write(&temp_file, "NOT").unwrap();
+
+    let plan = DownloadPlan::builder(&album)
+        .destination(&output)
+        .parallelism(1)
+        .partial(PartialPolicy::Clean)
+        .build()
+        .unwrap();
+    let stop = CancellationToken::new();
+    let download = tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            let progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+            plan.execute_with_events(
+                &reqwest::Client::new(),
+                &progress,
+                &CancellationToken::new(),
+                &stop,
+                &PauseToken::default(),
+                |_| {},
+            )
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.cancel();
+    let execution = download.await.unwrap().unwrap();
+    assert!(execution.errors.is_empty(), "{:?}", execution.errors);
+    let not_started = execution.not_started.iter().map(|file| file.id.as_str());
+    assert_eq!(not_started.collect::<Vec<_>>(), ["stopped2", "stopped3"]);
+    assert_eq!(media_files(&output).len(), 1);
+    assert!(temp_file.exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn skips_files_which_probing_finds_missing() {
+    let addr = start_server();
+    let audit_log =
+        std::env::temp_dir().join(format!("imgurs-mock-probe-{}.jsonl", std::process::id()));
+    let audit_args = [
+        "--audit-log".to_owned(),
+        audit_log.to_string_lossy().into_owned(),
+    ];
+    // Actions recorded for each file of album `metered`, whose video is not served.
+    let actions = || {
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&audit_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&audit_log).unwrap();
+        ["image1", "video"].map(|id| {
+            entries
+                .iter()
+                .filter(|entry| entry["id"] == id)
+                .map(|entry| entry["action"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let _ = std::fs::remove_file(&audit_log);
+    let (_, output) = download(addr, "metered", &audit_args).await;
+    assert_eq!(actions(), [["download"], ["failure"]]);
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let args = [&["--probe".to_owned()][..], &audit_args].concat();
+    let (success, output) = download(addr, "metered", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    assert_eq!(actions(), [["download"], ["unavailable"]]);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn fails_removed_media_without_mirrors() {
+    let addr = start_server();
+    let album = serde_json::from_value(serde_json::json!({
+        "id": "gone",
+        "title": null,
+        "images": [{
+            "id": "gone",
+            "title": null,
+            "description": null,
+            "link": format!("http://{}/gone.png", addr),
+            "datetime": 1_600_000_000,
+            "size": IMAGE.len(),
+            "type": "image/png",
+        }],
+    }))
+    .unwrap();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-gone-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    let plan = DownloadPlan::builder(&album)
+        .destination(&output)
+        .build()
+        .unwrap();
+    let progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+    let errors = plan
+        .execute(
+            &reqwest::Client::new(),
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+    // The placeholder is not saved in place of the media.
+    assert_eq!(errors.len(), 1);
+    assert_eq!(categorize(&errors[0]), ErrorCategory::Removed);
+    assert!(media_files(&output).is_empty());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_wayback_snapshots() {
+    let addr = start_server();
+    let args = [
+        "--mirror".to_owned(),
+        format!("http://{}/web/2id_/{{url}}", addr),
+    ];
+    let (success, output) = download(addr, "deleted", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    assert_eq!(WAYBACK_REQUESTS.load(Ordering::SeqCst), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_several_albums_into_subdirectories() {
+    let addr = start_server();
+    let (success, output) = download(addr, "mock", &["deleted".to_owned()]).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert!(media_files(&output.join("Album deleted")).is_empty());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn keeps_one_copy_of_media_shared_between_albums() {
+    let addr = start_server();
+    // Albums `mock` and `slow` both contain `image1`.
+    let args = ["slow".to_owned(), "--dedup".to_owned(), "skip".to_owned()];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert!(media_files(&output.join("Album slow")).is_empty());
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let args = ["slow".to_owned(), "--dedup".to_owned(), "link".to_owned()];
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_downloaded(&output.join("Album slow"));
+    let inode = |album: &str| {
+        use std::os::unix::fs::MetadataExt;
+        let name = &media_files(&output.join(album))[0].0;
+        std::fs::metadata(output.join(album).join(name))
+            .unwrap()
+            .ino()
+    };
+    assert_eq!(inode("Album mock"), inode("Album slow"));
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn dedups_against_downloads_made_without_dedup() {
+    let addr = start_server();
+    let (success, output) = download(addr, "mock", &[]).await;
+    assert!(success);
+    let state_db = output.join("data").join("imgurs").join("state.json");
+    let args = [
+        "--dedup".to_owned(),
+        "skip".to_owned(),
+        "--state-db".to_owned(),
+        state_db.display().to_string(),
+    ];
+    assert!(download_into(addr, "slow", &args, &output.join("slow")).await);
+    assert!(media_files(&output.join("slow")).is_empty());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn saves_unfinished_albums_after_max_duration() {
+    let addr = start_server();
+    let args = [
+        "mock".to_owned(),
+        "--max-duration".to_owned(),
+        "1s".to_owned(),
+    ];
+    let (success, output) = download(addr, "lengthy", &args).await;
+    assert!(success);
+    // The in-flight file is finished, but no more are started.
+    assert_downloaded(&output.join("Album lengthy"));
+    assert!(!output.join("Album mock").exists());
+    let job_file = output.join("data/imgurs/job.json");
+    let saved: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&job_file).unwrap()).unwrap();
+    assert_eq!(
+        saved,
+        serde_json::json!([
+            { "input": "lengthy", "destination": { "Under": output } },
+            { "input": "mock", "destination": { "Under": output } },
+        ])
+    );
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .arg("--resume")
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .args(["--allow-host", "127.0.0.1", "--imgur-client-id", "test"])
+        .env("XDG_DATA_HOME", output.join("data"));
+    let status = tokio::task::spawn_blocking(move || command.status())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    assert_downloaded(&output.join("Album mock"));
+    assert!(!job_file.exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_all_albums_of_account() {
+    let addr = start_server();
+    let args = ["albums", "--all", "--access-token", "token"].map(str::to_owned);
+    let (success, output) = download(addr, "account", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Album partial")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_account_favorites() {
+    let addr = start_server();
+    let args = ["favorites", "--access-token", "token"].map(str::to_owned);
+    let (success, output) = download(addr, "account", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Single")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_subreddit_gallery() {
+    let addr = start_server();
+    let args = ["https://imgur.com/r/aww", "--sort", "top", "--pages", "5"].map(str::to_owned);
+    let (success, output) = download(addr, "subreddit", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Single")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_tag_gallery() {
+    let addr = start_server();
+    let (success, output) = download(addr, "https://imgur.com/t/cats", &[]).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Single")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let (success, output) =
+        download(addr, "tag", &["cats".to_owned(), "--skip-nsfw".to_owned()]).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert!(!output.join("Single").exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_front_page() {
+    let addr = start_server();
+    let (success, output) = download(addr, "gallery", &[]).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock (mock)"));
+    assert_eq!(media_files(&output.join("Single (single)")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn searches_gallery() {
+    let addr = start_server();
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["search", "cats AND dogs", "--imgur-client-id", "test"])
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr));
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(output.status.success());
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results[0]["id"], "mock");
+    assert_eq!(results.as_array().unwrap().len(), 2);
+
+    let args = ["cats AND dogs", "--limit", "1", "--download"].map(str::to_owned);
+    let (success, output) = download(addr, "search", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert!(!output.join("Single").exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn uploads_images() {
+    let addr = start_server();
+    let dir = std::env::temp_dir().join(format!("imgurs-upload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("image.png"), IMAGE).unwrap();
+    let upload = |args: &[&str]| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+        command
+            .arg("upload")
+            .arg(dir.join("image.png"))
+            .args(args)
+            .args(["--imgur-client-id", "test"])
+            .arg("--api-base-url")
+            .arg(format!("http://{}", addr))
+            .env("XDG_DATA_HOME", dir.join("data"))
+            .env("IMGURS_VAULT_PASSPHRASE", "passphrase");
+        tokio::task::spawn_blocking(move || command.output())
+    };
+    let output = upload(&["--title", "Image"]).await.unwrap().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("https://i.imgur.com/uploaded.png (deletehash secret)"));
+    assert!(dir.join("data/imgurs/uploads.vault").is_file());
+
+    let output = upload(&["--album", "Holiday 2024"]).await.unwrap().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("album https://imgur.com/a/created (deletehash albumsecret)"));
+    assert!(stdout.contains("(deletehash secret)"));
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["uploads", "list", "IMAGE"])
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("IMGURS_VAULT_PASSPHRASE", "passphrase");
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // Both images were uploaded from image.png, but the album has no file.
+    assert_eq!(stdout.lines().count(), 2);
+    assert!(stdout.contains("image uploaded https://i.imgur.com/uploaded.png /"));
+    assert!(stdout.contains("image.png Image\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn filters_listed_uploads() {
+    let addr = start_server();
+    let dir = std::env::temp_dir().join(format!("imgurs-uploads-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let imgurs = |args: &[&str]| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+        command
+            .args(args)
+            .args(["--imgur-client-id", "test"])
+            .arg("--api-base-url")
+            .arg(format!("http://{}", addr))
+            .env("XDG_DATA_HOME", dir.join("data"))
+            .env("IMGURS_VAULT_PASSPHRASE", "passphrase");
+        async move {
+            let output = tokio::task::spawn_blocking(move || command.output())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        }
+    };
+    for name in ["cat.png", "dog.png"] {
+        std::fs::write(dir.join(name), IMAGE).unwrap();
+    }
+    let (cat, dog) = (dir.join("cat.png"), dir.join("dog.png"));
+    let (cat, dog) = (cat.to_str().unwrap(), dog.to_str().unwrap());
+    imgurs(&["upload", cat, "--title", "Sleepy"]).await;
+    imgurs(&["upload", dog, "--album", "Holiday 2024"]).await;
+
+    let list = |query: Option<&'static str>| {
+        let args = [&["uploads", "list"][..], query.as_slice()].concat();
+        let output = imgurs(&args);
+        async move {
+            output
+                .await
+                .lines()
+                .map(|line| line.split_once(' ').unwrap().1.to_owned())
+                .collect::<Vec<_>>()
+        }
+    };
+    let cat_upload = format!(
+        "image uploaded https://i.imgur.com/uploaded.png {} Sleepy",
+        cat
+    );
+    let dog_upload = format!("image uploaded https://i.imgur.com/uploaded.png {}", dog);
+    let album_upload = "album created https://imgur.com/a/created Holiday 2024";
+    assert_eq!(
+        list(None).await,
+        [cat_upload.as_str(), album_upload, dog_upload.as_str()]
+    );
+    // Titles and files are matched, ignoring case.
+    assert_eq!(list(Some("sLEEPY")).await, [cat_upload.as_str()]);
+    assert_eq!(list(Some("DOG")).await, [dog_upload.as_str()]);
+    assert_eq!(list(Some("holiday")).await, [album_upload]);
+    assert_eq!(
+        list(Some(".png")).await,
+        [cat_upload.as_str(), dog_upload.as_str()]
+    );
+    assert!(list(Some("bird")).await.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn deletes_uploads_by_deletehash() {
+    let addr = start_server();
+    let delete = |args: &[&str]| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+        command
+            .arg("delete")
+            .args(args)
+            .args(["--imgur-client-id", "test"])
+            .arg("--api-base-url")
+            .arg(format!("http://{}", addr));
+        tokio::task::spawn_blocking(move || command.output())
+    };
+    let output = delete(&["secret"]).await.unwrap().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Deleted image secret\n");
+    let output = delete(&["albumsecret", "--album"]).await.unwrap().unwrap();
+    assert_eq!(output.stdout, b"Deleted album albumsecret\n");
+}
+
+#[tokio::test]
+async fn caps_buffered_data() {
+    let addr = start_server();
+    let args = ["--max-buffer", "16B"].map(str::to_owned);
+    let (success, output) = download(addr, "mock", &args).await;
+    assert!(success);
+    assert_downloaded(&output);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_less_with_metered_preset() {
+    let addr = start_server();
+    let (success, output) = download(addr, "metered", &["--metered".to_owned()]).await;
+    assert!(success);
+    assert_downloaded(&output);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn downscales_large_still_images() {
+    let album: imgurs::api::ImgurAlbum = serde_json::from_value(serde_json::json!({
+        "id": "album",
+        "title": null,
+        "images": [
+            {"id": "large", "title": null, "description": null, "link": "https://i.imgur.com/large.png",
+             "datetime": 0, "size": 4000, "type": "image/png", "width": 4096, "height": 2048},
+            {"id": "small", "title": null, "description": null, "link": "https://i.imgur.com/small.png",
+             "datetime": 0, "size": 1000, "type": "image/png", "width": 100, "height": 100},
+            {"id": "gif", "title": null, "description": null, "link": "https://i.imgur.com/gif.gif",
+             "datetime": 0, "size": 1000, "type": "image/gif", "width": 4096, "height": 2048,
+             "animated": true},
+        ],
+    }))
+    .unwrap();
+    let plan = imgurs::plan::DownloadPlan::builder(&album)
+        .downscale(imgurs::api::ThumbnailSize::Huge)
+        .build()
+        .unwrap();
+    let large = &plan.files[0];
+    assert_eq!(large.url, "https://i.imgur.com/largeh.jpg");
+    assert!(large.filename.ends_with("large.jpg"));
+    assert_eq!(large.size, 250);
+    assert_eq!(plan.files[1].url, "https://i.imgur.com/small.png");
+    assert_eq!(plan.files[2].url, "https://i.imgur.com/gif.gif");
+}
+
+#[tokio::test]
+async fn downloads_albums_listed_in_input_file() {
+    let addr = start_server();
+    let list = std::env::temp_dir().join(format!("imgurs-mock-list-{}", std::process::id()));
+    std::fs::write(&list, "# Albums to archive\n\n  mock  \n").unwrap();
+    let args = ["--input-file".to_owned(), list.display().to_string()];
+    let (success, output) = download(addr, "deleted", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::remove_file(&list).unwrap();
+}
+
+#[tokio::test]
+async fn fails_empty_albums_if_asked() {
+    let addr = start_server();
+    let (success, output) = download(addr, "empty", &[]).await;
+    assert!(success);
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let args = ["--empty-album".to_owned(), "error".to_owned()];
+    let (success, output) = download(addr, "empty", &args).await;
+    assert!(!success);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn records_bandwidth_of_each_run() {
+    let addr = start_server();
+    let (success, output) = download(addr, "mock", &[]).await;
+    assert!(success);
+    assert!(download_into(addr, "partial", &[], &output).await);
+    let stats = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"))
+        .args(["stats", "--bandwidth"])
+        .env("XDG_DATA_HOME", output.join("data"))
+        .output()
+        .unwrap();
+    assert!(stats.status.success());
+    let stats = String::from_utf8(stats.stdout).unwrap();
+    let total = format!("Total: {} B over all runs", IMAGE.len() * 2);
+    assert!(stats.starts_with(&total), "{}", stats);
+    assert_eq!(stats.matches(" 1 albums in ").count(), 2, "{}", stats);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn prints_details_as_json() {
+    let addr = start_server();
+    let data = std::env::temp_dir().join(format!("imgurs-mock-json-{}", std::process::id()));
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["mock", "--details", "--json", "--imgur-client-id", "test"])
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .env("XDG_DATA_HOME", &data);
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(output.status.success());
+    let details: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(details["id"], "mock");
+    assert_eq!(details["images"][0]["type"], "image/png");
+    assert_eq!(details["downloaded_files"], 0);
+    assert_eq!(details["destination"], "Album mock");
+    let _ = std::fs::remove_dir_all(&data);
+}
+
+#[tokio::test]
+async fn coalesces_concurrent_album_requests() {
+    let addr = start_server();
+    let api = ApiClient::builder()
+        .base_url(format!("http://{}", addr))
+        .build()
+        .unwrap();
+    let auth = Auth::ClientId("test".to_owned());
+    let (a, b) = tokio::join!(
+        fetch_album(&api, &auth, "slow"),
+        fetch_album(&api, &auth, "slow")
+    );
+    assert_eq!(a.unwrap().data.unwrap().id, "slow");
+    assert_eq!(b.unwrap().data.unwrap().id, "slow");
+    assert_eq!(SLOW_REQUESTS.load(Ordering::SeqCst), 1);
+
+    // Completed requests are not reused.
+    fetch_album(&api, &auth, "slow").await.unwrap();
+    assert_eq!(SLOW_REQUESTS.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn reports_rate_limits() {
+    let addr = start_server();
+    let api = ApiClient::builder()
+        .base_url(format!("http://{}", addr))
+        .build()
+        .unwrap();
+    let auth = Auth::ClientId("test".to_owned());
+    let error = fetch_album(&api, &auth, "limited").await.unwrap_err();
+    assert_eq!(categorize(&error), ErrorCategory::RateLimited);
+    assert_eq!(retry_after(&error), Some(Duration::from_secs(30)));
+    let limits = api.rate_limits();
+    assert_eq!(limits.client_remaining, Some(0));
+    assert_eq!(limits.client_limit, Some(12500));
+
+    // Without Retry-After, wait until the exhausted credits are reset.
+    let error = fetch_album(&api, &auth, "exhausted").await.unwrap_err();
+    let wait = retry_after(&error).unwrap();
+    assert!(wait > Duration::from_secs(100) && wait <= Duration::from_secs(120));
+}
+
+#[tokio::test]
+async fn stops_when_credits_run_low() {
+    let addr = start_server();
+    let args = ["mock", "--min-credits", "100"].map(str::to_owned);
+    let (success, output) = download(addr, "drained", &args).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album drained"));
+    assert!(!output.join("Album mock").exists());
+    let job = std::fs::read_to_string(output.join("data/imgurs/job.json")).unwrap();
+    assert!(job.contains("\"mock\""));
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn prints_remaining_credits() {
+    let addr = start_server();
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["credits", "--imgur-client-id", "test"])
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .env("TZ", "UTC");
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Client: 12000/12500 requests remaining"));
+    assert!(stdout.contains("User: 1990/2000 requests remaining, reset at 2100-01-01"));
+}
+
+#[tokio::test]
+async fn routes_requests_through_proxies() {
+    let addr = start_server();
+    // The mock server serves absolute-form proxy requests by their path, so it doubles as a
+    // proxy for an API which cannot be reached directly.
+    let output = std::env::temp_dir().join(format!("imgurs-mock-proxy-{}", std::process::id()));
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["mock", "--api-base-url", "http://api.imgur.invalid"])
+        .args(["--allow-host", "127.0.0.1", "--imgur-client-id", "test"])
+        .arg("--proxy")
+        .arg(format!("http://{}", addr))
+        .arg("--output")
+        .arg(&output)
+        .env("XDG_DATA_HOME", output.join("data"));
+    let status = tokio::task::spawn_blocking(move || command.status())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    assert_downloaded(&output);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn answers_offline_without_requests() {
+    let addr = start_server();
+    let (success, output) = download(addr, "mock", &[]).await;
+    assert!(success);
+    // Refreshing this account's tokens would need a request.
+    std::fs::write(
+        output.join("data/imgurs/accounts.json"),
+        r#"{"default": "someone", "accounts": {"someone": {"access_token": "old",
+            "refresh_token": "refresh", "expires_at": 0}}}"#,
+    )
+    .unwrap();
+    // Connections to the listener are accepted by the OS, but never by the test.
+    let unreachable = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    unreachable.set_nonblocking(true).unwrap();
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["mock", "--offline", "--details", "--api-base-url"])
+        .arg(format!("http://{}", unreachable.local_addr().unwrap()))
+        .args(["--imgur-client-id", "test"])
+        .env("IMGUR_CLIENT_SECRET", "secret")
+        .env("XDG_DATA_HOME", output.join("data"));
+    let status = tokio::task::spawn_blocking(move || command.status())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    let accepted = unreachable.accept();
+    assert_eq!(
+        accepted.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::WouldBlock)
+    );
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+/// Sends a JSON-RPC request to a control socket, returning its result.
+#[cfg(unix)]
+async fn control(socket: &Path, method: &str, params: serde_json::Value) -> serde_json::Value {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut stream = tokio::net::UnixStream::connect(socket).await.unwrap();
+    let request =
+        serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    response["result"].clone()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn controls_jobs_through_sockets() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-control-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    std::fs::create_dir_all(&output).unwrap();
+    let socket = output.join("control.sock");
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .arg("lingering")
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .args(["--allow-host", "127.0.0.1", "--imgur-client-id", "test"])
+        .arg("--control-socket")
+        .arg(&socket)
+        .arg("--output")
+        .arg(output.join("lingering"))
+        // Added albums are downloaded to directories named after them, in the working directory.
+        .current_dir(&output)
+        .env("XDG_DATA_HOME", output.join("data"));
+    let mut child = command.spawn().unwrap();
+    while !socket.exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The image takes long enough to respond for the job to still be running.
+    assert_eq!(
+        control(&socket, "pause", serde_json::json!(null)).await,
+        true
+    );
+    assert_eq!(
+        control(&socket, "add-url", serde_json::json!({ "url": "mock" })).await,
+        true
+    );
+    assert_eq!(
+        control(&socket, "set-parallelism", serde_json::json!([2])).await,
+        true
+    );
+    let status = control(&socket, "status", serde_json::json!(null)).await;
+    assert_eq!(status["paused"], true);
+    assert_eq!(status["parallelism"], 2);
+    assert_eq!(status["queued"], serde_json::json!(["mock"]));
+    assert_eq!(
+        control(&socket, "resume", serde_json::json!(null)).await,
+        true
+    );
+
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    assert_downloaded(&output.join("lingering"));
+    assert_downloaded(&output.join("Album mock"));
+    assert!(!socket.exists());
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+/// Runs `imgurs serve` with the queue file `queue.json` in `output`, downloading albums into
+/// `output`, and returns it with its URL once it accepts connections.
+async fn serve(addr: SocketAddr, output: &Path, args: &[&str]) -> (std::process::Child, String) {
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let child = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"))
+        .arg("serve")
+        .arg("--listen")
+        .arg(listen.to_string())
+        .arg("--queue-file")
+        .arg(output.join("queue.json"))
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .args(["--allow-host", "127.0.0.1", "--imgur-client-id", "test"])
+        .args(args)
+        .arg("--output")
+        .arg(output)
+        .env("XDG_DATA_HOME", output.join("data"))
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    while tokio::net::TcpStream::connect(listen).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (child, format!("http://{}", listen))
+}
+
+/// Polls the status of the server at `server` until `done` returns true for it, or 5s pass.
+async fn server_status(
+    server: &str,
+    done: impl Fn(&serde_json::Value) -> bool,
+) -> serde_json::Value {
+    let mut status = serde_json::Value::Null;
+    for _ in 0..500 {
+        status = reqwest::get(format!("{}/status", server))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if done(&status) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    status
+}
+
+#[tokio::test]
+async fn downloads_albums_queued_on_server() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-serve-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    std::fs::create_dir_all(&output).unwrap();
+    // Albums left over from an earlier run are downloaded first.
+    let queue_file = output.join("queue.json");
+    std::fs::write(&queue_file, r#"["mock"]"#).unwrap();
+    let (mut child, server) = serve(addr, &output, &[]).await;
+
+    let queued = reqwest::Client::new()
+        .post(&server)
+        .body("slow\n")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(queued.status(), StatusCode::ACCEPTED);
+    assert_eq!(queued.text().await.unwrap(), "Queued 1 album(s)\n");
+
+    let status = server_status(&server, |status| status["queued"] == serde_json::json!([])).await;
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(status["queued"], serde_json::json!([]));
+    assert_eq!(
+        status["history"],
+        serde_json::json!([
+            { "url": "slow", "outcome": "Done" },
+            { "url": "mock", "outcome": "Done" },
+        ])
+    );
+    assert_downloaded(&output.join("Album mock"));
+    assert_downloaded(&output.join("Album slow"));
+    assert_eq!(std::fs::read_to_string(&queue_file).unwrap(), "[]");
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[tokio::test]
+async fn registers_url_handler() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let addr = start_server();
+    let dir = std::env::temp_dir().join(format!("imgurs-mock-handler-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // Stands in for xdg-mime, recording the arguments it is run with.
+    let bin = dir.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::fs::write(
+        bin.join("xdg-mime"),
+        format!(
+            "#!/bin/sh\necho \"$@\" > {}\n",
+            dir.join("xdg-mime.args").display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(bin.join("xdg-mime"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::join_paths(
+        std::iter::once(bin.clone())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    let server = format!("http://{}/queue", addr);
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"))
+        .args(["register-handler", "--server", &server])
+        .env("PATH", &path)
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("xdg-mime.args")).unwrap(),
+        "default imgurs-handler.desktop x-scheme-handler/imgurs\n"
+    );
+    let desktop_file =
+        std::fs::read_to_string(dir.join("data/applications/imgurs-handler.desktop")).unwrap();
+    let exec = desktop_file
+        .lines()
+        .find_map(|line| line.strip_prefix("Exec="))
+        .unwrap();
+    assert_eq!(
+        exec,
+        format!(
+            "\"{}\" handle-url --server \"{}\" %u",
+            env!("CARGO_BIN_EXE_imgurs"),
+            server
+        )
+    );
+
+    // Run the handler as the desktop would when an imgurs:// link is opened.
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args([
+            "handle-url",
+            "--server",
+            &server,
+            "imgurs://imgur.com/a/abc",
+        ])
+        .stdout(std::process::Stdio::null());
+    let status = tokio::task::spawn_blocking(move || command.status())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        *QUEUED_URLS.lock().unwrap(),
+        vec!["https://imgur.com/a/abc".to_owned()]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_only_inside_schedule_window() {
+    let addr = start_server();
+    let now = imgurs::date::DateTime::now_local();
+    let hour = |offset: u32| format!("{:02}:00", (now.hour + offset) % 24);
+    for (window, inside) in [
+        (format!("{}-{}", hour(23), hour(1)), true),
+        (format!("{}-{}", hour(2), hour(3)), false),
+    ] {
+        let output = std::env::temp_dir().join(format!(
+            "imgurs-mock-window-{}-{}",
+            inside,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&output);
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join("queue.json"), r#"["mock"]"#).unwrap();
+        let (mut child, server) = serve(addr, &output, &["--schedule-window", &window]).await;
+
+        let status = server_status(&server, |status| {
+            !status["album"].is_null() || !status["history"][0].is_null()
+        })
+        .await;
+        if inside {
+            assert_eq!(status["paused"], false);
+            let status = server_status(&server, |status| !status["history"][0].is_null()).await;
+            assert_eq!(status["history"][0]["outcome"], "Done");
+            assert_downloaded(&output.join("Album mock"));
+        } else {
+            // The album is started, but none of its files are downloaded until the window opens.
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let status = server_status(&server, |_| true).await;
+            assert_eq!(status["paused"], true);
+            assert_eq!(status["queued"], serde_json::json!(["mock"]));
+            assert_eq!(status["completed_files"], 0);
+            assert!(media_files(&output.join("Album mock")).is_empty());
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
+
+#[test]
+fn verifies_checksums_with_distinct_exit_codes() {
+    let dir = std::env::temp_dir().join(format!("imgurs-verify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1.png"), IMAGE).unwrap();
+    std::fs::write(dir.join("2.png"), IMAGE).unwrap();
+    imgurs::torrent::write_checksums(&dir).unwrap();
+    std::fs::write(
+        dir.join("manifest.toml"),
+        "[[albums]]\n\n[[albums.images]]\npath = \"1.png\"\n",
+    )
+    .unwrap();
+    let verify = |command: &str, path: &Path| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"))
+            .arg(command)
+            .arg(path)
+            .status()
+            .unwrap()
+            .code()
+    };
+    let manifest = dir.join("manifest.toml");
+    assert_eq!(verify("verify", &dir), Some(0));
+    assert_eq!(verify("verify-manifest", &manifest), Some(0));
+
+    std::fs::remove_file(dir.join("2.png")).unwrap();
+    assert_eq!(verify("verify", &dir), Some(3));
+    // Only the manifest's files are checked.
+    assert_eq!(verify("verify-manifest", &manifest), Some(0));
+
+    std::fs::write(dir.join("1.png"), b"corrupted").unwrap();
+    assert_eq!(verify("verify", &dir), Some(4));
+    assert_eq!(verify("verify-manifest", &manifest), Some(4));
+    std::fs::remove_file(dir.join("1.png")).unwrap();
+    assert_eq!(verify("verify-manifest", &manifest), Some(3));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn diagnoses_setup() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-doctor-{}", std::process::id()));
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_imgurs"));
+    command
+        .args(["doctor", "--imgur-client-id", "test"])
+        .arg("--api-base-url")
+        .arg(format!("http://{}", addr))
+        .arg("--output")
+        .arg(output.join("albums"));
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .unwrap();
+    // The image servers are not mocked, so only the other checks are known to pass.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("API: OK, http://{} responded with", addr)));
+    assert!(stdout.contains("Credentials: OK, client ID accepted"));
+    assert!(stdout.contains("Credits: OK, 12000/12500 for the client ID, 1990/2000 for the user"));
+    assert!(stdout.contains("Output directory: OK"));
+    assert!(stdout.contains("Clock: OK"));
+}
+
+#[tokio::test]
+async fn exchanges_oauth_pins() {
+    let addr = start_server();
+    let api = ApiClient::builder()
+        .base_url(format!("http://{}", addr))
+        .build()
+        .unwrap();
+    let tokens = fetch_oauth_tokens(&api, "test", "secret", OAuthGrant::Pin("1234"))
+        .await
+        .unwrap();
+    assert_eq!(tokens.access_token, "access");
+    assert_eq!(tokens.refresh_token, "refresh");
+    assert_eq!(tokens.account_username.as_deref(), Some("someone"));
+}
+
+#[tokio::test]
+async fn fetches_gallery_albums_and_images() {
+    let addr = start_server();
+    let api = ApiClient::builder()
+        .base_url(format!("http://{}", addr))
+        .build()
+        .unwrap();
+    let auth = Auth::ClientId("test".to_owned());
+
+    let album = fetch_gallery_post(&api, &auth, "mock").await.unwrap();
+    assert_eq!(album.data.unwrap().images[0].id, "image1");
+
+    let image = fetch_gallery_post(&api, &auth, "single").await.unwrap();
+    let image = image.data.unwrap();
+    assert_eq!(image.title.as_deref(), Some("Single"));
+    assert_eq!(image.tags[0].name, "cats");
+    assert_eq!(image.images.len(), 1);
+    assert_eq!(image.images[0].link, format!("http://{}/single.png", addr));
+
+    let missing = fetch_gallery_post(&api, &auth, "missing").await.unwrap();
+    assert!(missing.data.is_none());
+    assert_eq!(missing.status, 404);
+}
+
+#[tokio::test]
+async fn downloads_user_posts() {
+    let addr = start_server();
+    let (success, output) = download(addr, "user", &["someone".to_owned()]).await;
+    assert!(success);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Album partial")).len(), 1);
+    let index = std::fs::read_to_string(output.join("index.md")).unwrap();
+    assert_eq!(
+        index,
+        "# Posts by someone\n\n\
+         - 2020-09-13 [Album mock](https://imgur.com/gallery/mock)\n\
+         - 2017-07-14 [Album partial](https://imgur.com/a/partial)\n"
+    );
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_image_links() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-image-{}", std::process::id()));
+    assert!(download_into(addr, "https://i.imgur.com/image1.png", &[], &output).await);
+    assert_downloaded(&output);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_posts_of_user_links() {
+    let addr = start_server();
+    let output = std::env::temp_dir().join(format!("imgurs-mock-user-{}", std::process::id()));
+    assert!(download_into(addr, "https://imgur.com/user/someone", &[], &output).await);
+    assert_downloaded(&output.join("Album mock"));
+    assert_eq!(media_files(&output.join("Album partial")).len(), 1);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[tokio::test]
+async fn archives_user_comments() {
+    let addr = start_server();
+    let args = ["someone".to_owned(), "--comments".to_owned()];
+    let (success, output) = download(addr, "user", &args).await;
+    assert!(success);
+    let markdown = std::fs::read_to_string(output.join("comments.md")).unwrap();
+    assert!(markdown.contains("<https://imgur.com/gallery/mock/comment/7>\n\nNice cat\n"));
+    let json = std::fs::read_to_string(output.join("comments.json")).unwrap();
+    assert!(json.contains("\"vote\": null"));
+    std::fs::remove_dir_all(&output).unwrap();
+}